@@ -0,0 +1,143 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reading input from a tar archive by extracting the tables' files to a temporary directory,
+//! or only listing them for a dry run
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use tar::{Archive, Entry};
+use tempfile::TempDir;
+use tracing::{debug, info};
+
+use crate::error::{Result, TpcError};
+use crate::Tpc;
+
+/// Check whether an input path is a tar archive, optionally gzip compressed
+pub(crate) fn is_tar_archive(input_path: &str) -> bool {
+    let path = Path::new(input_path);
+    path.is_file()
+        && [".tar", ".tar.gz", ".tgz"]
+            .iter()
+            .any(|ext| input_path.ends_with(ext))
+}
+
+/// Extract the input files of the tables from a tar archive to a temporary directory, which
+/// is laid out like an input directory and removed when dropped. Members are matched by the
+/// first part of their path named after a table's input, such as `lineitem.tbl`,
+/// `lineitem.tbl.1`, or `lineitem.tbl/part-1.tbl`, so the archive can have a top-level
+/// directory.
+pub(crate) fn extract_tables(
+    benchmark: &dyn Tpc,
+    archive_path: &str,
+    tables: &[&str],
+) -> Result<TempDir> {
+    info!("Extracting input from {}", archive_path);
+    unpack_tables(benchmark, archive_path, tables, |entry, dest| {
+        entry.unpack(dest)?;
+        Ok(())
+    })
+}
+
+/// Lay out the input files of the tables in a tar archive in a temporary directory like
+/// [`extract_tables`], but only write the first line of each uncompressed file, which is
+/// enough to plan a conversion without extracting the archive
+pub(crate) fn list_tables(
+    benchmark: &dyn Tpc,
+    archive_path: &str,
+    tables: &[&str],
+) -> Result<TempDir> {
+    info!("Listing input in {}", archive_path);
+    unpack_tables(benchmark, archive_path, tables, |entry, dest| {
+        // the first line of a compressed file can't be read without decompressing it
+        if dest.extension().is_some_and(|ext| ext == "gz") {
+            entry.unpack(dest)?;
+        } else {
+            let mut first_line = vec![];
+            BufReader::new(entry).read_until(b'\n', &mut first_line)?;
+            fs::write(dest, first_line)?;
+        }
+        Ok(())
+    })
+}
+
+/// Write the members of a tar archive that are the input files of the tables to a temporary
+/// directory with `unpack`, returning the directory
+fn unpack_tables(
+    benchmark: &dyn Tpc,
+    archive_path: &str,
+    tables: &[&str],
+    mut unpack: impl FnMut(&mut Entry<'_, Box<dyn Read>>, &Path) -> Result<()>,
+) -> Result<TempDir> {
+    let file = File::open(archive_path)?;
+    let reader: Box<dyn Read> = if archive_path.ends_with(".tar") {
+        Box::new(file)
+    } else {
+        Box::new(GzDecoder::new(file))
+    };
+    let names: Vec<String> = tables
+        .iter()
+        .map(|table| format!("{}.{}", table, benchmark.get_table_ext()))
+        .collect();
+
+    let dir = tempfile::Builder::new().prefix("tpctools-").tempdir()?;
+    debug!("Writing archive members to {}", dir.path().display());
+    let mut archive = Archive::new(reader);
+    let mut extracted = 0;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let member = entry.path()?.into_owned();
+        if let Some(relative) = table_member_path(&member, &names)? {
+            let dest = dir.path().join(relative);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            unpack(&mut entry, &dest)?;
+            extracted += 1;
+        }
+    }
+    info!("Found {} files", extracted);
+    Ok(dir)
+}
+
+/// Get the path of an archive member relative to the input directory, from the first part of
+/// its path named after a table's input, or `None` if it is not a table's input
+fn table_member_path(member: &Path, names: &[String]) -> Result<Option<PathBuf>> {
+    let mut parts = vec![];
+    for component in member.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().into_owned()),
+            Component::CurDir => {}
+            _ => {
+                return Err(TpcError::Conversion(format!(
+                    "Archive member has an unsupported path: {}",
+                    member.display()
+                )))
+            }
+        }
+    }
+    let start = parts.iter().position(|part| {
+        names.iter().any(|name| {
+            part == name
+                || part
+                    .strip_prefix(name.as_str())
+                    .is_some_and(|rest| rest.starts_with('.'))
+        })
+    });
+    Ok(start.map(|start| parts[start..].iter().collect()))
+}
@@ -0,0 +1,198 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! SHA-256 checksums of converted files, in the format read by `sha256sum --check`
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWrite;
+use tracing::info;
+
+use crate::error::{Result, TpcError};
+use crate::remote;
+
+/// Name of the checksum file written to each table's output directory
+pub const CHECKSUM_FILE: &str = "checksums.sha256";
+
+/// Compute the SHA-256 of a file as a hex string
+pub(crate) fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Format `(file name, hash)` pairs as the contents of a checksum file
+pub(crate) fn format_checksums(checksums: &[(String, String)]) -> String {
+    checksums
+        .iter()
+        .map(|(file_name, hash)| format!("{}  {}\n", hash, file_name))
+        .collect()
+}
+
+/// Recompute the checksum of every file listed in the checksum files of the tables under
+/// `output_path`, failing if any file is missing or has changed, or if no table has a checksum
+/// file
+pub fn verify_checksums(output_path: &str) -> Result<()> {
+    if remote::parse_url(output_path)?.is_some() {
+        return Err(TpcError::InvalidOption(
+            "Verifying checksums is only supported for local paths".to_string(),
+        ));
+    }
+
+    let mut mismatches = vec![];
+    let mut verified = 0;
+    let mut tables = 0;
+    for entry in fs::read_dir(output_path)? {
+        let table_dir = entry?.path();
+        if table_dir.join(CHECKSUM_FILE).exists() {
+            tables += 1;
+        }
+        verified += check_table_checksums(&table_dir, &mut mismatches)?;
+    }
+
+    if tables == 0 {
+        return Err(TpcError::Conversion(format!(
+            "No {} files found under {}; convert with --write-checksums to write them",
+            CHECKSUM_FILE, output_path
+        )));
+    }
+
+    if !mismatches.is_empty() {
+        return Err(TpcError::Conversion(format!(
+            "Checksum verification failed: {}",
+            mismatches.join(", ")
+        )));
+    }
+    info!(
+        "Verified checksums of {} files in {} tables",
+        verified, tables
+    );
+    Ok(())
+}
+
+/// Recompute the checksum of every file listed in a table directory's checksum file, if it has
+/// one, adding a message for each file that is missing or has changed and returning the number
+/// of files that are unchanged
+pub(crate) fn check_table_checksums(
+    table_dir: &Path,
+    mismatches: &mut Vec<String>,
+) -> Result<usize> {
+    let checksum_file = table_dir.join(CHECKSUM_FILE);
+    if !checksum_file.exists() {
+        return Ok(0);
+    }
+    let mut verified = 0;
+    for line in fs::read_to_string(&checksum_file)?.lines() {
+        let (expected, file_name) = line.split_once("  ").ok_or_else(|| {
+            TpcError::Conversion(format!(
+                "Invalid line in {}: {}",
+                checksum_file.display(),
+                line
+            ))
+        })?;
+        let path = table_dir.join(file_name);
+        if !path.exists() {
+            mismatches.push(format!("{} is missing", path.display()));
+        } else if sha256_file(&path)? != expected {
+            mismatches.push(format!("{} has changed", path.display()));
+        } else {
+            verified += 1;
+        }
+    }
+    Ok(verified)
+}
+
+/// Writer that computes the SHA-256 of everything written through it
+pub(crate) struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W> HashingWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Get the SHA-256 of the data written so far as a hex string
+    pub(crate) fn sha256(&self) -> String {
+        format!("{:x}", self.hasher.clone().finalize())
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for HashingWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            self.hasher.update(&buf[..n]);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_table(output_dir: &Path, contents: &str) -> std::path::PathBuf {
+        let table_dir = output_dir.join("nation.parquet");
+        fs::create_dir(&table_dir).unwrap();
+        let file = table_dir.join("part-0.parquet");
+        fs::write(&file, contents).unwrap();
+        let checksums = [("part-0.parquet".to_string(), sha256_file(&file).unwrap())];
+        fs::write(table_dir.join(CHECKSUM_FILE), format_checksums(&checksums)).unwrap();
+        file
+    }
+
+    #[test]
+    fn unchanged_files_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        write_table(dir.path(), "data");
+        verify_checksums(dir.path().to_str().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn changed_files_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = write_table(dir.path(), "data");
+        fs::write(file, "changed").unwrap();
+        let err = verify_checksums(dir.path().to_str().unwrap()).unwrap_err();
+        assert!(err.to_string().contains("has changed"), "{}", err);
+    }
+
+    #[test]
+    fn output_without_checksum_files_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("nation.parquet")).unwrap();
+        let err = verify_checksums(dir.path().to_str().unwrap()).unwrap_err();
+        assert!(err.to_string().contains(CHECKSUM_FILE), "{}", err);
+    }
+}
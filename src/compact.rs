@@ -0,0 +1,546 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rewriting a converted table's Parquet files into fewer, larger files, or a given number of
+//! files
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use datafusion::logical_expr::Partitioning;
+use datafusion::parquet::arrow::ARROW_SCHEMA_META_KEY;
+use datafusion::parquet::basic::{Compression, Type};
+use datafusion::parquet::file::metadata::KeyValue;
+use datafusion::parquet::file::properties::{EnabledStatistics, WriterProperties, WriterVersion};
+use datafusion::parquet::file::reader::{FileReader, SerializedFileReader};
+use datafusion::prelude::*;
+use tracing::{debug, info};
+
+use crate::checksum::{format_checksums, sha256_file};
+use crate::error::{Result, TpcError};
+use crate::{remote, CompressionCodec, Manifest, CHECKSUM_FILE, MANIFEST_FILE, SUCCESS_MARKER};
+
+/// Rewrite the Parquet files of `{output_path}/{table}.parquet` into files of approximately
+/// `target_file_bytes` each, replacing the table directory once the new files are complete.
+/// Each partition directory of a partitioned table is compacted separately.
+pub async fn compact(output_path: &str, table: &str, target_file_bytes: u64) -> Result<()> {
+    if target_file_bytes == 0 {
+        return Err(TpcError::InvalidOption(
+            "Target file size must be greater than zero".to_string(),
+        ));
+    }
+    let (table_dir, parts) = table_parts(output_path, table, "Compacting")?;
+
+    let mut total_bytes = 0;
+    let mut dirs = vec![];
+    for (dir, dir_parts) in partition_dirs(&table_dir, &parts) {
+        let bytes = dir_parts
+            .iter()
+            .map(|part| Ok(fs::metadata(part)?.len()))
+            .sum::<Result<u64>>()?;
+        total_bytes += bytes;
+        let files = (bytes.div_ceil(target_file_bytes).max(1) as usize).min(dir_parts.len());
+        dirs.push((dir, files));
+    }
+    if dirs.iter().map(|(_, files)| files).sum::<usize>() >= parts.len() {
+        info!(
+            "Table {} already has {} files of {} bytes in total, not compacting",
+            table,
+            parts.len(),
+            total_bytes
+        );
+        return Ok(());
+    }
+
+    let start = Instant::now();
+    let files = rewrite_table(output_path, table, &table_dir, &parts, &dirs).await?;
+    info!(
+        "Compacted {} files of {} into {} files in {} ms",
+        parts.len(),
+        table,
+        files,
+        start.elapsed().as_millis()
+    );
+    Ok(())
+}
+
+/// Rewrite the Parquet files of `{output_path}/{table}.parquet` into exactly
+/// `target_partitions` files, distributing record batches round-robin, and replace the table
+/// directory once the new files are complete. Each partition directory of a partitioned table
+/// gets `target_partitions` files.
+pub async fn repartition(output_path: &str, table: &str, target_partitions: usize) -> Result<()> {
+    if target_partitions == 0 {
+        return Err(TpcError::InvalidOption(
+            "Target partitions must be at least 1".to_string(),
+        ));
+    }
+    let (table_dir, parts) = table_parts(output_path, table, "Repartitioning")?;
+    if table_dir.is_file() && target_partitions > 1 {
+        return Err(TpcError::InvalidOption(format!(
+            "Table {} was written to a single file, so it can only be repartitioned into 1 file",
+            table
+        )));
+    }
+    let dirs: Vec<_> = partition_dirs(&table_dir, &parts)
+        .into_keys()
+        .map(|dir| (dir, target_partitions))
+        .collect();
+
+    let start = Instant::now();
+    let files = rewrite_table(output_path, table, &table_dir, &parts, &dirs).await?;
+    info!(
+        "Repartitioned {} files of {} into {} files in {} ms",
+        parts.len(),
+        table,
+        files,
+        start.elapsed().as_millis()
+    );
+    Ok(())
+}
+
+/// Find a local table directory and its Parquet files, or a table written to a single file
+fn table_parts(output_path: &str, table: &str, action: &str) -> Result<(PathBuf, Vec<PathBuf>)> {
+    if remote::parse_url(output_path)?.is_some() {
+        return Err(TpcError::InvalidOption(format!(
+            "{} is only supported for local paths",
+            action
+        )));
+    }
+    let table_dir = Path::new(output_path).join(format!("{}.parquet", table));
+    if table_dir.is_file() {
+        return Ok((table_dir.clone(), vec![table_dir]));
+    }
+    if !table_dir.is_dir() {
+        return Err(TpcError::MissingPath {
+            description: "Table directory".to_string(),
+            path: table_dir,
+        });
+    }
+    let parts = parquet_files(&table_dir)?;
+    if parts.is_empty() {
+        return Err(TpcError::Conversion(format!(
+            "Table directory has no Parquet files: {}",
+            table_dir.display()
+        )));
+    }
+    Ok((table_dir, parts))
+}
+
+/// Group a table's files by the directory they are in, relative to the table directory, which
+/// is only a partition directory when the table is partitioned
+fn partition_dirs(table_dir: &Path, parts: &[PathBuf]) -> BTreeMap<PathBuf, Vec<PathBuf>> {
+    let mut dirs: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for part in parts {
+        let dir = part
+            .parent()
+            .and_then(|dir| dir.strip_prefix(table_dir).ok())
+            .unwrap_or_else(|| Path::new(""));
+        dirs.entry(dir.to_path_buf())
+            .or_default()
+            .push(part.clone());
+    }
+    dirs
+}
+
+/// Rewrite each of a table's directories, given relative to the table directory with the number
+/// of files to write to it, next to the table directory, then swap them in and update the
+/// manifest. A table written to a single file is rewritten to a single file. Returns the number
+/// of files written.
+async fn rewrite_table(
+    output_path: &str,
+    table: &str,
+    table_dir: &Path,
+    parts: &[PathBuf],
+    dirs: &[(PathBuf, usize)],
+) -> Result<usize> {
+    // write next to the table directory so that it can be swapped in with a rename
+    let temp_dir = Path::new(output_path).join(format!("{}-compact.parquet", table));
+    if temp_dir.exists() {
+        fs::remove_dir_all(&temp_dir)?;
+    }
+    debug!("Writing {}", temp_dir.display());
+    let ctx = SessionContext::new();
+    let props = writer_properties(Path::new(output_path), parts)?;
+
+    let (files, bytes) = if table_dir.is_file() {
+        let df = ctx
+            .read_parquet(path_str(table_dir)?, ParquetReadOptions::default())
+            .await?
+            .repartition(Partitioning::RoundRobinBatch(1))?;
+        df.write_parquet(path_str(&temp_dir)?, Some(props)).await?;
+        // renaming over the old file replaces it without the table ever being missing
+        fs::rename(temp_dir.join("part-0.parquet"), table_dir)?;
+        fs::remove_dir_all(&temp_dir)?;
+        let bytes = fs::metadata(table_dir)?.len();
+        (vec![format!("{}.parquet", table)], bytes)
+    } else {
+        let (files, bytes) =
+            write_table_dir(&ctx, table, table_dir, &temp_dir, dirs, props).await?;
+        swap_dirs(&temp_dir, table_dir)?;
+        (files, bytes)
+    };
+
+    let count = files.len();
+    let manifest_path = Path::new(output_path).join(MANIFEST_FILE);
+    if manifest_path.exists() {
+        update_manifest(&manifest_path, table, files, bytes)?;
+    }
+    Ok(count)
+}
+
+/// Rewrite the directories of a table to a new table directory, with the same metadata files
+/// as the conversion wrote. Returns the files written, relative to the output path, and their
+/// total size.
+async fn write_table_dir(
+    ctx: &SessionContext,
+    table: &str,
+    table_dir: &Path,
+    new_dir: &Path,
+    dirs: &[(PathBuf, usize)],
+    props: WriterProperties,
+) -> Result<(Vec<String>, u64)> {
+    for (dir, partitions) in dirs {
+        let df = ctx
+            .read_parquet(
+                path_str(&table_dir.join(dir))?,
+                ParquetReadOptions::default(),
+            )
+            .await?
+            .repartition(Partitioning::RoundRobinBatch(*partitions))?;
+        // DataFusion only creates the last directory of the path it writes to
+        let dir_path = new_dir.join(dir);
+        if let Some(parent) = dir_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        df.write_parquet(path_str(&dir_path)?, Some(props.clone()))
+            .await?;
+    }
+
+    // the new files are already named part-N, so they only need the checksums and marker that
+    // the old directory had
+    let write_checksums = table_dir.join(CHECKSUM_FILE).exists();
+    let mut files = vec![];
+    let mut checksums = vec![];
+    let mut bytes = 0;
+    for (dir, _) in dirs {
+        for part in 0..parquet_files(&new_dir.join(dir))?.len() {
+            let file_name = dir
+                .join(format!("part-{}.parquet", part))
+                .display()
+                .to_string();
+            let path = new_dir.join(&file_name);
+            if write_checksums {
+                checksums.push((file_name.clone(), sha256_file(&path)?));
+            }
+            bytes += fs::metadata(&path)?.len();
+            files.push(format!("{}.parquet/{}", table, file_name));
+        }
+    }
+    if write_checksums {
+        fs::write(new_dir.join(CHECKSUM_FILE), format_checksums(&checksums))?;
+    }
+    if table_dir.join(SUCCESS_MARKER).exists() {
+        fs::File::create(new_dir.join(SUCCESS_MARKER))?;
+    }
+    Ok((files, bytes))
+}
+
+/// Replace a table directory with a new one, removing the old files once the new ones are in
+/// place. The directories are exchanged atomically where the file system supports it, and
+/// otherwise the table is missing between two renames.
+fn swap_dirs(new_dir: &Path, table_dir: &Path) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    match exchange_paths(new_dir, table_dir) {
+        Ok(()) => {
+            // the new directory's path now has the old files
+            fs::remove_dir_all(new_dir)?;
+            return Ok(());
+        }
+        Err(e) if matches!(e.raw_os_error(), Some(libc::EINVAL) | Some(libc::ENOSYS)) => {
+            debug!("Cannot exchange directories, renaming instead: {}", e);
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    let old_dir = table_dir.with_file_name(format!(
+        "{}-old",
+        table_dir.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    if old_dir.exists() {
+        fs::remove_dir_all(&old_dir)?;
+    }
+    fs::rename(table_dir, &old_dir)?;
+    fs::rename(new_dir, table_dir)?;
+    fs::remove_dir_all(&old_dir)?;
+    Ok(())
+}
+
+/// Atomically exchange two paths with `renameat2`, which the standard library doesn't expose
+#[cfg(target_os = "linux")]
+fn exchange_paths(path1: &Path, path2: &Path) -> std::io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path1 = CString::new(path1.as_os_str().as_bytes())?;
+    let path2 = CString::new(path2.as_os_str().as_bytes())?;
+    // SAFETY: both paths are valid NUL-terminated strings that outlive the call
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_renameat2,
+            libc::AT_FDCWD,
+            path1.as_ptr(),
+            libc::AT_FDCWD,
+            path2.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// List the Parquet files in a directory and its partition directories, sorted by path
+fn parquet_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(parquet_files(&path)?);
+        } else if path.is_file() && path.extension().is_some_and(|ext| ext == "parquet") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Writer properties that keep what can be read from the footer of a table's first file: its
+/// writer version and maximum row group size, and the codec, dictionary encoding, statistics,
+/// and whether there is a bloom filter of each column. The key-value metadata of every file is
+/// kept. Footers don't record compression levels, so columns with the table's codec use the
+/// level recorded by the conversion, and other columns the codec's default level.
+fn writer_properties(output_path: &Path, parts: &[PathBuf]) -> Result<WriterProperties> {
+    let key_value_metadata = key_value_metadata(parts)?;
+    let table_compression = recorded_compression(output_path, key_value_metadata.as_deref())?;
+    let reader = SerializedFileReader::new(fs::File::open(&parts[0])?)?;
+    let metadata = reader.metadata();
+    let writer_version = match metadata.file_metadata().version() {
+        1 => WriterVersion::PARQUET_1_0,
+        _ => WriterVersion::PARQUET_2_0,
+    };
+    let mut builder = WriterProperties::builder().set_writer_version(writer_version);
+    // only a file with more than one row group shows that its first one is full
+    if metadata.num_row_groups() > 1 {
+        builder = builder.set_max_row_group_size(metadata.row_group(0).num_rows() as usize);
+    }
+    if metadata.num_row_groups() > 0 {
+        let columns = metadata.row_group(0).columns();
+        // byte array columns get a column index whatever the setting, so only the other
+        // columns show whether page statistics were written
+        let page_statistics = columns.iter().any(|column| {
+            column.column_type() != Type::BYTE_ARRAY && column.column_index_offset().is_some()
+        });
+        for column in columns {
+            let path = column.column_path().clone();
+            let statistics = match column.statistics() {
+                None => EnabledStatistics::None,
+                Some(_) if page_statistics => EnabledStatistics::Page,
+                Some(_) => EnabledStatistics::Chunk,
+            };
+            let compression = match table_compression {
+                Some(compression)
+                    if mem::discriminant(&compression)
+                        == mem::discriminant(&column.compression()) =>
+                {
+                    compression
+                }
+                _ => column.compression(),
+            };
+            builder = builder
+                .set_column_compression(path.clone(), compression)
+                .set_column_dictionary_enabled(
+                    path.clone(),
+                    column.dictionary_page_offset().is_some(),
+                )
+                .set_column_statistics_enabled(path.clone(), statistics)
+                .set_column_bloom_filter_enabled(path, column.bloom_filter_offset().is_some());
+        }
+    }
+    builder = builder.set_key_value_metadata(key_value_metadata);
+    Ok(builder.build())
+}
+
+/// The table's compression, with its level, as recorded by the conversion in the footer metadata
+/// written with `footer_metadata`, or else in the manifest written with `write_manifest`
+fn recorded_compression(
+    output_path: &Path,
+    key_value_metadata: Option<&[KeyValue]>,
+) -> Result<Option<Compression>> {
+    let footer_codec = key_value_metadata
+        .into_iter()
+        .flatten()
+        .find(|key_value| key_value.key == "tpctools.compression")
+        .and_then(|key_value| key_value.value.clone());
+    let codec = match footer_codec {
+        Some(codec) => codec,
+        None => {
+            let manifest_path = output_path.join(MANIFEST_FILE);
+            if !manifest_path.exists() {
+                return Ok(None);
+            }
+            let manifest: Manifest = serde_json::from_str(&fs::read_to_string(manifest_path)?)?;
+            manifest.compression
+        }
+    };
+    let codec: CompressionCodec = codec.parse()?;
+    Ok(Some(codec.to_parquet_compression()?))
+}
+
+/// Collect the key-value metadata of a table's files, keeping the first value of each key.
+/// The Arrow schema is left out because the writer encodes it again.
+fn key_value_metadata(parts: &[PathBuf]) -> Result<Option<Vec<KeyValue>>> {
+    let mut key_value_metadata: Vec<KeyValue> = vec![];
+    for part in parts {
+        let reader = SerializedFileReader::new(fs::File::open(part)?)?;
+        let file_metadata = reader.metadata().file_metadata();
+        for key_value in file_metadata.key_value_metadata().into_iter().flatten() {
+            if key_value.key != ARROW_SCHEMA_META_KEY
+                && !key_value_metadata.iter().any(|kv| kv.key == key_value.key)
+            {
+                key_value_metadata.push(key_value.clone());
+            }
+        }
+    }
+    Ok((!key_value_metadata.is_empty()).then_some(key_value_metadata))
+}
+
+/// Replace a table's files in an existing manifest
+fn update_manifest(path: &Path, table: &str, files: Vec<String>, bytes: u64) -> Result<()> {
+    let mut manifest: Manifest = serde_json::from_str(&fs::read_to_string(path)?)?;
+    if let Some(entry) = manifest
+        .tables
+        .iter_mut()
+        .find(|entry| entry.table == table)
+    {
+        entry.files = files;
+        entry.bytes = bytes;
+        let json = serde_json::to_string_pretty(&manifest)?;
+        fs::write(path, json + "\n")?;
+    }
+    Ok(())
+}
+
+fn path_str(path: &Path) -> Result<&str> {
+    path.to_str().ok_or_else(|| {
+        TpcError::InvalidOption(format!("Path is not valid UTF-8: {}", path.display()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use datafusion::arrow::array::Int32Array;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::parquet::arrow::ArrowWriter;
+    use datafusion::parquet::basic::ZstdLevel;
+    use datafusion::parquet::schema::types::ColumnPath;
+
+    use super::*;
+
+    /// Write a Parquet file with one column `a` at zstd level 19
+    fn write_part(dir: &Path, key_value_metadata: Option<Vec<KeyValue>>) -> PathBuf {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let batch =
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(Int32Array::from(vec![1, 2]))])
+                .unwrap();
+        let props = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::try_new(19).unwrap()))
+            .set_key_value_metadata(key_value_metadata)
+            .build();
+        let path = dir.join("part-0.parquet");
+        let mut writer =
+            ArrowWriter::try_new(fs::File::create(&path).unwrap(), schema, Some(props)).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        path
+    }
+
+    fn compression(output_path: &Path, part: PathBuf) -> Compression {
+        writer_properties(output_path, &[part])
+            .unwrap()
+            .compression(&ColumnPath::from("a"))
+    }
+
+    #[test]
+    fn compression_level_is_read_from_the_footer_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let metadata = vec![KeyValue::new(
+            "tpctools.compression".to_string(),
+            "zstd:19".to_string(),
+        )];
+        let part = write_part(dir.path(), Some(metadata));
+        assert_eq!(
+            compression(dir.path(), part),
+            Compression::ZSTD(ZstdLevel::try_new(19).unwrap())
+        );
+    }
+
+    #[test]
+    fn compression_level_is_read_from_the_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = Manifest {
+            format: "parquet".to_string(),
+            compression: "zstd:19".to_string(),
+            scale_factor: None,
+            partitions: None,
+            tables: vec![],
+        };
+        fs::write(
+            dir.path().join(MANIFEST_FILE),
+            serde_json::to_string(&manifest).unwrap(),
+        )
+        .unwrap();
+        let part = write_part(dir.path(), None);
+        assert_eq!(
+            compression(dir.path(), part),
+            Compression::ZSTD(ZstdLevel::try_new(19).unwrap())
+        );
+    }
+
+    #[test]
+    fn unrecorded_levels_are_the_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let part = write_part(dir.path(), None);
+        assert_eq!(
+            compression(dir.path(), part.clone()),
+            Compression::ZSTD(ZstdLevel::default())
+        );
+
+        // a column with another codec than the table's gets that codec's default level
+        let metadata = vec![KeyValue::new(
+            "tpctools.compression".to_string(),
+            "gzip:9".to_string(),
+        )];
+        let part = write_part(dir.path(), Some(metadata));
+        assert_eq!(
+            compression(dir.path(), part),
+            Compression::ZSTD(ZstdLevel::default())
+        );
+    }
+}
@@ -0,0 +1,117 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Parsing date and timestamp columns with a format string, for input that doesn't use the
+//! formats the CSV reader expects
+
+use std::sync::Arc;
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{NaiveDate, NaiveDateTime};
+use datafusion::arrow::array::{
+    Array, ArrayRef, Date32Array, StringArray, TimestampNanosecondArray,
+};
+use datafusion::arrow::datatypes::{DataType, TimeUnit};
+use datafusion::error::DataFusionError;
+use datafusion::logical_expr::{create_udf, ColumnarValue, Volatility};
+use datafusion::prelude::Expr;
+
+use crate::error::{Result, TpcError};
+
+/// Check that a format string only has valid `strftime` specifiers
+pub(crate) fn check_format(description: &str, format: &str) -> Result<()> {
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return Err(TpcError::InvalidOption(format!(
+            "Invalid {} format: {}",
+            description, format
+        )));
+    }
+    Ok(())
+}
+
+/// Parse a string expression into a `Date32` with a format such as `%m/%d/%Y`
+pub(crate) fn parse_date(value: Expr, format: &str) -> Expr {
+    let format = format.to_string();
+    let udf = create_udf(
+        "tpctools_parse_date",
+        vec![DataType::Utf8],
+        Arc::new(DataType::Date32),
+        Volatility::Immutable,
+        Arc::new(move |args: &[ColumnarValue]| {
+            let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap_or_default();
+            parse_strings(&args[0], |s| {
+                let date = NaiveDate::parse_from_str(s, &format)
+                    .map_err(|_| parse_error(s, "date", &format))?;
+                Ok((date - epoch).num_days() as i32)
+            })
+            .map(|values| Arc::new(values.into_iter().collect::<Date32Array>()) as ArrayRef)
+            .map(ColumnarValue::Array)
+        }),
+    );
+    udf.call(vec![value])
+}
+
+/// Parse a string expression into a nanosecond timestamp with a format such as
+/// `%Y-%m-%d %H:%M:%S`
+pub(crate) fn parse_timestamp(value: Expr, format: &str) -> Expr {
+    let format = format.to_string();
+    let udf = create_udf(
+        "tpctools_parse_timestamp",
+        vec![DataType::Utf8],
+        Arc::new(DataType::Timestamp(TimeUnit::Nanosecond, None)),
+        Volatility::Immutable,
+        Arc::new(move |args: &[ColumnarValue]| {
+            parse_strings(&args[0], |s| {
+                let timestamp = NaiveDateTime::parse_from_str(s, &format)
+                    .map_err(|_| parse_error(s, "timestamp", &format))?;
+                timestamp
+                    .timestamp()
+                    .checked_mul(1_000_000_000)
+                    .and_then(|nanos| nanos.checked_add(timestamp.timestamp_subsec_nanos() as i64))
+                    .ok_or_else(|| parse_error(s, "timestamp", &format))
+            })
+            .map(|values| {
+                Arc::new(values.into_iter().collect::<TimestampNanosecondArray>()) as ArrayRef
+            })
+            .map(ColumnarValue::Array)
+        }),
+    );
+    udf.call(vec![value])
+}
+
+/// Parse each non-null value of a string column
+fn parse_strings<T>(
+    value: &ColumnarValue,
+    parse: impl Fn(&str) -> datafusion::error::Result<T>,
+) -> datafusion::error::Result<Vec<Option<T>>> {
+    let array = value.clone().into_array(1);
+    let strings = array
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .ok_or_else(|| DataFusionError::Internal("Expected a string column".to_string()))?;
+    (0..strings.len())
+        .map(|i| {
+            if strings.is_null(i) {
+                Ok(None)
+            } else {
+                parse(strings.value(i)).map(Some)
+            }
+        })
+        .collect()
+}
+
+fn parse_error(value: &str, description: &str, format: &str) -> DataFusionError {
+    DataFusionError::Execution(format!(
+        "Cannot parse {:?} as a {} with format {}",
+        value, description, format
+    ))
+}
@@ -0,0 +1,111 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Generating `CREATE TABLE` statements from the benchmark schemas
+
+use std::fmt;
+use std::str::FromStr;
+
+use datafusion::arrow::datatypes::{DataType, Schema};
+
+use crate::error::{Result, TpcError};
+use crate::Tpc;
+
+/// SQL dialect to generate DDL for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SqlDialect {
+    Ansi,
+    Spark,
+    DuckDb,
+}
+
+impl SqlDialect {
+    /// Get the SQL type for an Arrow type in this dialect
+    fn sql_type(&self, data_type: &DataType) -> Result<String> {
+        let sql_type = match (self, data_type) {
+            (Self::DuckDb, DataType::Int32) => "INTEGER".to_string(),
+            (_, DataType::Int32) => "INT".to_string(),
+            (_, DataType::Int64) => "BIGINT".to_string(),
+            (Self::Ansi, DataType::Float64) => "DOUBLE PRECISION".to_string(),
+            (_, DataType::Float64) => "DOUBLE".to_string(),
+            (Self::Spark, DataType::Utf8) => "STRING".to_string(),
+            (_, DataType::Utf8) => "VARCHAR".to_string(),
+            (_, DataType::Date32) => "DATE".to_string(),
+            (_, DataType::Decimal128(precision, scale)) => {
+                format!("DECIMAL({}, {})", precision, scale)
+            }
+            (_, other) => {
+                return Err(TpcError::Conversion(format!(
+                    "No SQL type for {:?} in the {} dialect",
+                    other, self
+                )))
+            }
+        };
+        Ok(sql_type)
+    }
+}
+
+impl FromStr for SqlDialect {
+    type Err = TpcError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "ansi" => Ok(Self::Ansi),
+            "spark" => Ok(Self::Spark),
+            "duckdb" => Ok(Self::DuckDb),
+            other => Err(TpcError::InvalidOption(format!(
+                "Invalid SQL dialect: {}",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for SqlDialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ansi => write!(f, "ansi"),
+            Self::Spark => write!(f, "spark"),
+            Self::DuckDb => write!(f, "duckdb"),
+        }
+    }
+}
+
+/// Generate a `CREATE TABLE` statement for every table in the benchmark
+pub fn generate_ddl(benchmark: &dyn Tpc, dialect: SqlDialect) -> Result<String> {
+    let mut ddl = String::new();
+    for (table, schema) in benchmark.schemas()? {
+        if !ddl.is_empty() {
+            ddl.push('\n');
+        }
+        ddl.push_str(&table_ddl(table, &schema, dialect)?);
+    }
+    Ok(ddl)
+}
+
+/// Generate a `CREATE TABLE` statement for a table with a schema
+pub(crate) fn table_ddl(table: &str, schema: &Schema, dialect: SqlDialect) -> Result<String> {
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            let sql_type = dialect.sql_type(field.data_type())?;
+            let not_null = if field.is_nullable() { "" } else { " NOT NULL" };
+            Ok(format!("    {} {}{}", field.name(), sql_type, not_null))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(format!(
+        "CREATE TABLE {} (\n{}\n);\n",
+        table,
+        columns.join(",\n")
+    ))
+}
@@ -0,0 +1,147 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Comparing two sets of converted Parquet tables with each other
+
+use std::path::Path;
+
+use datafusion::arrow::row::{RowConverter, SortField};
+use datafusion::prelude::*;
+use futures::TryStreamExt;
+use sha2::{Digest, Sha256};
+use tracing::{info, info_span, Instrument};
+
+use crate::error::Result;
+use crate::Tpc;
+
+/// Result of comparing the converted tables under two paths
+#[derive(Debug, Clone, Default)]
+pub struct DiffReport {
+    /// Result for each table that exists under either path, in benchmark table order
+    pub tables: Vec<TableDiff>,
+}
+
+impl DiffReport {
+    /// Check whether every table is the same under both paths
+    pub fn is_equal(&self) -> bool {
+        self.tables.iter().all(|table| table.is_equal())
+    }
+}
+
+/// Result of comparing one table under two paths
+#[derive(Debug, Clone)]
+pub struct TableDiff {
+    pub table: String,
+    /// Ways in which the table differs between the paths
+    pub differences: Vec<String>,
+}
+
+impl TableDiff {
+    /// Check whether the table is the same under both paths
+    pub fn is_equal(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+/// Row count and a hash of the rows of a table that doesn't depend on their order
+struct TableContents {
+    rows: u64,
+    hash: [u64; 4],
+}
+
+/// Compare the Parquet tables under `path_a` with those under `path_b`, checking that each table
+/// has the same schema, the same number of rows, and the same rows in any order. Tables that
+/// exist under neither path are left out of the report.
+pub async fn diff(path_a: &str, path_b: &str, benchmark: &dyn Tpc) -> Result<DiffReport> {
+    let ctx = SessionContext::new();
+    let mut tables = vec![];
+    for table in benchmark.get_table_names() {
+        let a = format!("{}/{}.parquet", path_a, table);
+        let b = format!("{}/{}.parquet", path_b, table);
+        let differences = match (Path::new(&a).exists(), Path::new(&b).exists()) {
+            (false, false) => continue,
+            (true, false) => vec![format!("Missing from {}", path_b)],
+            (false, true) => vec![format!("Missing from {}", path_a)],
+            (true, true) => {
+                diff_table(&ctx, &a, &b)
+                    .instrument(info_span!("table", table))
+                    .await?
+            }
+        };
+        tables.push(TableDiff {
+            table: table.to_owned(),
+            differences,
+        });
+    }
+    Ok(DiffReport { tables })
+}
+
+async fn diff_table(ctx: &SessionContext, a: &str, b: &str) -> Result<Vec<String>> {
+    info!("Comparing {} and {}", a, b);
+    let df_a = ctx.read_parquet(a, ParquetReadOptions::default()).await?;
+    let df_b = ctx.read_parquet(b, ParquetReadOptions::default()).await?;
+
+    // nullability can differ between writers without the values differing
+    let columns = |df: &DataFrame| {
+        df.schema()
+            .fields()
+            .iter()
+            .map(|field| (field.name().clone(), field.data_type().clone()))
+            .collect::<Vec<_>>()
+    };
+    if columns(&df_a) != columns(&df_b) {
+        return Ok(vec!["Schemas differ".to_string()]);
+    }
+
+    let contents_a = table_contents(df_a).await?;
+    let contents_b = table_contents(df_b).await?;
+    let mut differences = vec![];
+    if contents_a.rows != contents_b.rows {
+        differences.push(format!(
+            "Row counts differ: {} and {}",
+            contents_a.rows, contents_b.rows
+        ));
+    } else if contents_a.hash != contents_b.hash {
+        differences.push("Rows differ".to_string());
+    }
+    Ok(differences)
+}
+
+/// Count the rows of a table and add up the SHA-256 hashes of their encoded values, so that the
+/// hash is the same whichever order the rows were written in
+async fn table_contents(df: DataFrame) -> Result<TableContents> {
+    let fields = df
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| SortField::new(field.data_type().clone()))
+        .collect();
+    let mut converter = RowConverter::new(fields)?;
+    let mut contents = TableContents {
+        rows: 0,
+        hash: [0; 4],
+    };
+    let mut batches = df.execute_stream().await?;
+    while let Some(batch) = batches.try_next().await? {
+        let rows = converter.convert_columns(batch.columns())?;
+        for row in rows.iter() {
+            let digest = Sha256::digest(row.as_ref());
+            for (lane, bytes) in contents.hash.iter_mut().zip(digest.chunks_exact(8)) {
+                let mut word = [0; 8];
+                word.copy_from_slice(bytes);
+                *lane = lane.wrapping_add(u64::from_le_bytes(word));
+            }
+        }
+        contents.rows += batch.num_rows() as u64;
+    }
+    Ok(contents)
+}
@@ -0,0 +1,82 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The error type returned by this crate
+
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+use datafusion::arrow::error::ArrowError;
+use datafusion::error::DataFusionError;
+use datafusion::parquet::errors::ParquetError;
+use thiserror::Error;
+
+use crate::malformed::MalformedRow;
+
+/// Result type returned by this crate
+pub type Result<T, E = TpcError> = std::result::Result<T, E>;
+
+/// Errors returned when generating, converting, or checking TPC data
+#[derive(Debug, Error)]
+pub enum TpcError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    DataFusion(#[from] DataFusionError),
+    #[error("Arrow error: {0}")]
+    Arrow(#[from] ArrowError),
+    #[error("Parquet error: {0}")]
+    Parquet(#[from] ParquetError),
+    #[error("Object store error: {0}")]
+    ObjectStore(#[from] object_store::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    /// A table name that is not part of the benchmark
+    #[error("Unknown table: {table}. Valid tables are: {}", valid.join(", "))]
+    UnknownTable { table: String, valid: Vec<String> },
+    /// An input or output path, or a file that should be under it, that does not exist
+    #[error("{description} does not exist: {}", path.display())]
+    MissingPath { description: String, path: PathBuf },
+    /// An option or argument that cannot be used
+    #[error("{0}")]
+    InvalidOption(String),
+    /// A compression level outside of the range that the codec accepts
+    #[error(
+        "Invalid {codec} compression level: {level} (valid range is {}-{})",
+        allowed.start(),
+        allowed.end()
+    )]
+    InvalidCompressionLevel {
+        codec: String,
+        level: i64,
+        allowed: RangeInclusive<i64>,
+    },
+    /// A conversion or check that could not be completed
+    #[error("{0}")]
+    Conversion(String),
+    /// Input lines of a table that have the wrong number of fields, in input order
+    #[error(
+        "Table {table} has {} malformed input rows, the first at {}",
+        rows.len(),
+        rows[0]
+    )]
+    MalformedRows {
+        table: String,
+        rows: Vec<MalformedRow>,
+    },
+    /// A conversion that was stopped by its cancellation token
+    #[error("Conversion was cancelled")]
+    Cancelled,
+    /// A data generator that could not be run or exited with an error
+    #[error("Generator failed: {0}")]
+    Generator(String),
+}
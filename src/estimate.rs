@@ -0,0 +1,76 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rough estimates of the size of converted output, for provisioning disk before converting
+
+use datafusion::arrow::datatypes::{DataType, Schema};
+
+use crate::error::Result;
+use crate::{CompressionCodec, Tpc};
+
+/// Estimated bytes per value of a string column, between the short codes and flags that
+/// dictionary encoding makes small and the comment columns that are far longer
+const STRING_BYTES: u64 = 16;
+
+/// Estimate the total size in bytes of a benchmark's tables converted to Parquet at a scale
+/// factor with a codec.
+///
+/// This is only a rough guide. Each table's estimated row count is multiplied by a fixed
+/// number of bytes per value of each column's type, assuming 16 bytes for every string, and by
+/// a typical compression ratio for the codec, ignoring its level. Real output depends on the
+/// data, and is usually smaller, as dictionary encoding makes many columns smaller than
+/// estimated. Tables whose size doesn't follow from the scale factor, such as the TPC-DS
+/// `inventory` table and the dimension tables that grow sublinearly, are not counted. Fails if
+/// the benchmark's generator can't generate data at the scale factor.
+pub fn estimate_output_bytes(
+    benchmark: &dyn Tpc,
+    scale: f64,
+    codec: CompressionCodec,
+) -> Result<u64> {
+    benchmark.validate_scale(scale)?;
+    let bytes: f64 = benchmark
+        .get_table_names()
+        .into_iter()
+        .filter_map(|table| {
+            let rows = benchmark.estimated_row_count(table, scale)?;
+            let schema = benchmark.get_schema(table).ok()?;
+            Some(rows as f64 * row_bytes(&schema) as f64)
+        })
+        .sum();
+    Ok((bytes * compression_ratio(codec)) as u64)
+}
+
+/// Estimate the uncompressed size in bytes of a row of a table
+fn row_bytes(schema: &Schema) -> u64 {
+    schema
+        .fields()
+        .iter()
+        .map(|field| match field.data_type() {
+            DataType::Boolean | DataType::Int8 | DataType::UInt8 => 1,
+            DataType::Int16 | DataType::UInt16 => 2,
+            DataType::Int32 | DataType::UInt32 | DataType::Float32 | DataType::Date32 => 4,
+            DataType::Utf8 | DataType::LargeUtf8 => STRING_BYTES,
+            // timestamps, 64-bit numbers, and decimals with up to 18 digits take 8 bytes
+            _ => 8,
+        })
+        .sum()
+}
+
+/// Typical ratio of compressed to uncompressed size of Parquet data with a codec
+fn compression_ratio(codec: CompressionCodec) -> f64 {
+    match codec {
+        CompressionCodec::None => 1.0,
+        CompressionCodec::Snappy | CompressionCodec::Lz4 | CompressionCodec::Lzo => 0.5,
+        CompressionCodec::Zstd(_) | CompressionCodec::Gzip(_) => 0.35,
+        CompressionCodec::Brotli(_) => 0.3,
+    }
+}
@@ -0,0 +1,209 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Running the dbgen and dsdgen data generators
+
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use tracing::{debug, info, warn};
+
+use crate::error::{Result, TpcError};
+use crate::{RetryPolicy, Tpc};
+
+/// Options controlling how data is generated
+#[derive(Debug, Clone, Default)]
+pub struct GenerateOptions {
+    /// Maximum number of generator processes to run at the same time, or one per partition
+    /// when not set
+    pub concurrency: Option<usize>,
+    /// Generate data at scale factors the benchmark specification does not allow, such as
+    /// 0.01 for small test data sets
+    pub force_scale: bool,
+    /// Retry moving and copying generated files into the output directory when they fail with
+    /// a transient error, such as on a network file system. Each is only tried once when not
+    /// set.
+    pub io_retry: Option<RetryPolicy>,
+}
+
+impl GenerateOptions {
+    /// Check that the options are usable before any work is done
+    pub fn validate(&self) -> Result<()> {
+        if self.concurrency == Some(0) {
+            return Err(TpcError::InvalidOption(
+                "Concurrency must be at least 1".to_string(),
+            ));
+        }
+        if let Some(io_retry) = &self.io_retry {
+            io_retry.validate()?;
+        }
+        Ok(())
+    }
+}
+
+/// Generate a benchmark's data at a scale factor with the generator at `generator_path`, running
+/// one generator process per partition, and move the files into a directory per table under
+/// `output_path`. This is the same as calling [`Tpc::generate`].
+pub fn generate(
+    benchmark: &dyn Tpc,
+    scale: f64,
+    partitions: usize,
+    generator_path: &str,
+    output_path: &str,
+    options: &GenerateOptions,
+) -> Result<()> {
+    benchmark.generate(scale, partitions, generator_path, output_path, options)
+}
+
+/// Check that a scale factor is a number greater than zero
+pub(crate) fn check_positive_scale(scale: f64) -> Result<()> {
+    if !(scale.is_finite() && scale > 0.0) {
+        return Err(TpcError::InvalidOption(format!(
+            "Scale factor must be greater than zero, got {}",
+            scale
+        )));
+    }
+    Ok(())
+}
+
+/// Check that a scale factor is allowed by the benchmark specification, only warning when
+/// `force_scale` is set
+pub(crate) fn check_scale(
+    benchmark: &str,
+    scale: f64,
+    allowed: &[f64],
+    options: &GenerateOptions,
+) -> Result<()> {
+    check_positive_scale(scale)?;
+    if !allowed.contains(&scale) {
+        let allowed: Vec<String> = allowed.iter().map(|scale| scale.to_string()).collect();
+        let message = format!(
+            "Scale factor {} is not one of the {} scale factors: {}",
+            scale,
+            benchmark,
+            allowed.join(", ")
+        );
+        if !options.force_scale {
+            return Err(TpcError::InvalidOption(format!(
+                "{}. Set force_scale to generate it anyway",
+                message
+            )));
+        }
+        warn!("{}", message);
+    }
+    Ok(())
+}
+
+/// Find a data generator binary and the file it needs alongside it, failing with a clear error
+/// if either is missing
+pub(crate) fn find_generator(
+    generator_path: &str,
+    binary: &str,
+    required_file: &str,
+) -> Result<PathBuf> {
+    let dir = Path::new(generator_path);
+    let binary_path = dir.join(binary);
+    if !binary_path.is_file() {
+        return Err(TpcError::MissingPath {
+            description: binary.to_string(),
+            path: binary_path,
+        });
+    }
+    if !dir.join(required_file).is_file() {
+        return Err(TpcError::MissingPath {
+            description: format!("{} needed by {}", required_file, binary),
+            path: dir.join(required_file),
+        });
+    }
+    // the generator runs from its own directory, so a relative path would not resolve
+    Ok(fs::canonicalize(binary_path)?)
+}
+
+/// Run generator commands, each described by the partition it generates, with at most
+/// `concurrency` running at the same time. Fails with the errors of every command that failed.
+pub(crate) fn run_generators(
+    commands: Vec<(String, Command)>,
+    concurrency: Option<usize>,
+) -> Result<()> {
+    let workers = concurrency
+        .unwrap_or(commands.len())
+        .min(commands.len())
+        .max(1);
+    let queue = Arc::new(Mutex::new(commands.into_iter().collect::<VecDeque<_>>()));
+
+    let handles: Vec<_> = (0..workers)
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            thread::spawn(move || {
+                let mut errors = vec![];
+                loop {
+                    let next = queue.lock().map(|mut queue| queue.pop_front());
+                    let (partition, command) = match next {
+                        Ok(Some(next)) => next,
+                        _ => break,
+                    };
+                    info!("Generating {} ...", partition);
+                    if let Err(e) = run_generator(command) {
+                        errors.push(format!("{}: {}", partition, e));
+                    }
+                }
+                errors
+            })
+        })
+        .collect();
+
+    // wait for all threads to finish
+    let mut errors = vec![];
+    for handle in handles {
+        match handle.join() {
+            Ok(thread_errors) => errors.extend(thread_errors),
+            Err(_) => errors.push("Generator thread panicked".to_string()),
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(TpcError::Generator(errors.join("; ")))
+    }
+}
+
+/// Run a data generator, failing with its output if it exits with an error
+fn run_generator(mut command: Command) -> Result<()> {
+    let program = Path::new(command.get_program())
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let output = command
+        .output()
+        .map_err(|e| TpcError::Generator(format!("Failed to run {}: {}", program, e)))?;
+    debug!("{:?}", output);
+    if !output.status.success() {
+        // the generators write some errors to stdout
+        let message = if output.stderr.is_empty() {
+            &output.stdout
+        } else {
+            &output.stderr
+        };
+        return Err(TpcError::Generator(format!(
+            "{} exited with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(message).trim()
+        )));
+    }
+    Ok(())
+}
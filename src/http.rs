@@ -0,0 +1,180 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reading input from web servers that only answer `GET` and `HEAD` requests
+
+use std::fmt;
+use std::ops::Range;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use object_store::http::{HttpBuilder, HttpStore};
+use object_store::path::Path as ObjectPath;
+use object_store::{ClientOptions, GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore};
+use reqwest::header::{CONTENT_LENGTH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use tokio::io::AsyncWrite;
+use url::Url;
+
+use crate::error::Result;
+
+/// Object store for the files on a web server. object_store's HTTP store gets the size of a
+/// file with a WebDAV `PROPFIND` request, which most web servers don't support, so this gets it
+/// with a `HEAD` request instead.
+#[derive(Debug)]
+pub(crate) struct HttpInputStore {
+    inner: HttpStore,
+    client: reqwest::Client,
+    origin: Url,
+}
+
+impl HttpInputStore {
+    /// Create the store for the server of a URL
+    pub(crate) fn new(url: &Url) -> Result<Self> {
+        let origin = Url::parse(&url[..url::Position::BeforePath]).map_err(|e| {
+            object_store::Error::Generic {
+                store: "HTTP",
+                source: Box::new(e),
+            }
+        })?;
+        let inner = HttpBuilder::new()
+            .with_url(origin.as_str())
+            .with_client_options(ClientOptions::new().with_allow_http(url.scheme() == "http"))
+            .build()?;
+        Ok(Self {
+            inner,
+            client: reqwest::Client::new(),
+            origin,
+        })
+    }
+}
+
+impl fmt::Display for HttpInputStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HttpInputStore({})", self.origin)
+    }
+}
+
+/// Error for a `HEAD` response that can't be used
+fn head_error(message: String) -> object_store::Error {
+    object_store::Error::Generic {
+        store: "HTTP",
+        source: message.into(),
+    }
+}
+
+#[async_trait]
+impl ObjectStore for HttpInputStore {
+    async fn put(&self, location: &ObjectPath, bytes: Bytes) -> object_store::Result<()> {
+        self.inner.put(location, bytes).await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &ObjectPath,
+    ) -> object_store::Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.inner.put_multipart(location).await
+    }
+
+    async fn abort_multipart(
+        &self,
+        location: &ObjectPath,
+        multipart_id: &MultipartId,
+    ) -> object_store::Result<()> {
+        self.inner.abort_multipart(location, multipart_id).await
+    }
+
+    async fn get(&self, location: &ObjectPath) -> object_store::Result<GetResult> {
+        self.inner.get(location).await
+    }
+
+    async fn get_range(
+        &self,
+        location: &ObjectPath,
+        range: Range<usize>,
+    ) -> object_store::Result<Bytes> {
+        self.inner.get_range(location, range).await
+    }
+
+    async fn head(&self, location: &ObjectPath) -> object_store::Result<ObjectMeta> {
+        let mut url = self.origin.clone();
+        url.set_path(location.as_ref());
+        let response = self
+            .client
+            .head(url.clone())
+            .send()
+            .await
+            .map_err(|e| head_error(format!("HEAD {} failed: {}", url, e)))?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(object_store::Error::NotFound {
+                path: location.to_string(),
+                source: format!("{} was not found", url).into(),
+            });
+        }
+        if !response.status().is_success() {
+            return Err(head_error(format!(
+                "HEAD {} failed: {}",
+                url,
+                response.status()
+            )));
+        }
+        let headers = response.headers();
+        let size = headers
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| head_error(format!("No Content-Length for {}", url)))?;
+        // the time is only used for caching, so a server that doesn't send it is not an error
+        let last_modified = headers
+            .get(LAST_MODIFIED)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+            .map_or_else(Utc::now, |time| time.with_timezone(&Utc));
+        Ok(ObjectMeta {
+            location: location.clone(),
+            last_modified,
+            size,
+        })
+    }
+
+    async fn delete(&self, location: &ObjectPath) -> object_store::Result<()> {
+        self.inner.delete(location).await
+    }
+
+    async fn list<'a>(
+        &'a self,
+        prefix: Option<&ObjectPath>,
+    ) -> object_store::Result<BoxStream<'a, object_store::Result<ObjectMeta>>> {
+        self.inner.list(prefix).await
+    }
+
+    async fn list_with_delimiter(
+        &self,
+        prefix: Option<&ObjectPath>,
+    ) -> object_store::Result<ListResult> {
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &ObjectPath, to: &ObjectPath) -> object_store::Result<()> {
+        self.inner.copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(
+        &self,
+        from: &ObjectPath,
+        to: &ObjectPath,
+    ) -> object_store::Result<()> {
+        self.inner.copy_if_not_exists(from, to).await
+    }
+}
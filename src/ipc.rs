@@ -0,0 +1,57 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reading converted Arrow IPC files, which DataFusion can't read as a listing table
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use datafusion::arrow::ipc::reader::FileReader;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::datasource::MemTable;
+
+use crate::error::{Result, TpcError};
+
+/// Read an Arrow IPC file, or the `.arrow` files in a table directory and its partition
+/// directories, into an in-memory table with one partition per file
+pub(crate) fn read_ipc_table(path: &Path) -> Result<MemTable> {
+    let mut files = vec![];
+    if path.is_dir() {
+        ipc_files(path, &mut files)?;
+        files.sort();
+    } else {
+        files.push(path.to_path_buf());
+    }
+
+    let mut schema = None;
+    let mut partitions = vec![];
+    for file in &files {
+        let reader = FileReader::try_new(File::open(file)?, None)?;
+        schema.get_or_insert_with(|| reader.schema());
+        partitions.push(reader.collect::<std::result::Result<Vec<RecordBatch>, _>>()?);
+    }
+    let schema = schema
+        .ok_or_else(|| TpcError::Conversion(format!("No Arrow IPC files in {}", path.display())))?;
+    Ok(MemTable::try_new(schema, partitions)?)
+}
+
+fn ipc_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            ipc_files(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "arrow") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
@@ -10,166 +10,2506 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ffi::OsStr;
+use std::fmt;
 use std::fs;
-use std::io::Result;
+use std::ops::RangeInclusive;
 use std::path::{Path, PathBuf};
-use std::time::Instant;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaBuilder};
-use datafusion::error::DataFusionError;
-use datafusion::parquet::basic::Compression;
-use datafusion::parquet::file::properties::WriterProperties;
+use datafusion::datasource::file_format::file_type::FileCompressionType;
+use datafusion::execution::runtime_env::{RuntimeConfig, RuntimeEnv};
+use datafusion::logical_expr::Partitioning;
+use datafusion::parquet::basic::{BrotliLevel, Compression, GzipLevel, ZstdLevel};
+use datafusion::parquet::file::metadata::KeyValue;
+use datafusion::parquet::file::properties::{EnabledStatistics, WriterProperties, WriterVersion};
+use datafusion::parquet::schema::types::ColumnPath;
 use datafusion::prelude::*;
+use futures::{stream, Stream, StreamExt};
+use tempfile::TempDir;
+use tracing::{debug, info, info_span, warn, Instrument};
 
+use crate::archive::{extract_tables, is_tar_archive, list_tables};
+use crate::checksum::{check_table_checksums, sha256_file};
+use crate::datetime::{check_format, parse_date, parse_timestamp};
+use crate::ddl::table_ddl;
+use crate::generate::check_positive_scale;
+use crate::ipc::read_ipc_table;
+use crate::malformed::check_input;
+use crate::partition::write_partitions;
+use crate::progress::send_progress;
+use crate::retry::with_retry;
+use crate::schema::{check_field_count, detect_delimiter};
+use crate::sink::{finish_table_dir, output_sink, prepare_table_dir, LocalSink, OutputSink};
+use crate::writer::{write_dataframe, Written};
+
+pub use checksum::{verify_checksums, CHECKSUM_FILE};
+pub use compact::{compact, repartition};
+pub use ddl::{generate_ddl, SqlDialect};
+pub use diff::{diff, DiffReport, TableDiff};
+pub use error::{Result, TpcError};
+pub use estimate::estimate_output_bytes;
+pub use generate::{generate, GenerateOptions};
+pub use malformed::{MalformedRow, MalformedRowMode};
+pub use manifest::{Manifest, ManifestTable, MANIFEST_FILE};
+pub use partition::{DateGranularity, DatePartitioning};
+pub use pipeline::{generate_and_convert, PipelineOptions};
+pub use progress::{ProgressEvent, ProgressSender};
+pub use query::{query_sql, run_query};
+pub use retry::RetryPolicy;
+pub use schema::load_schema_overrides;
+pub use sink::IoLimit;
+pub use tokio_util::sync::CancellationToken;
+pub use verify::{verify, TableVerification, VerifyReport};
+
+mod archive;
+mod checksum;
+mod compact;
+mod datetime;
+mod ddl;
+mod diff;
+mod error;
+mod estimate;
+mod generate;
+#[cfg(feature = "http")]
+mod http;
+mod ipc;
+mod malformed;
+mod manifest;
+mod partition;
+mod pipeline;
+mod progress;
+mod query;
+mod remote;
+mod retry;
+mod schema;
+mod sink;
 pub mod tpcds;
 pub mod tpch;
+mod verify;
+mod writer;
+
+/// A benchmark, which is shared with the blocking threads that run its generator
+#[async_trait]
+pub trait Tpc: Send + Sync {
+    fn generate(
+        &self,
+        scale: f64,
+        partitions: usize,
+        input_path: &str,
+        output_path: &str,
+        options: &GenerateOptions,
+    ) -> Result<()>;
+
+    fn get_table_names(&self) -> Vec<&str>;
+
+    /// Alternative names for tables, as `(alias, table)` pairs
+    fn get_table_aliases(&self) -> Vec<(&str, &str)> {
+        vec![]
+    }
+
+    /// Resolve a table name ignoring case, or an alias, to the name returned by
+    /// `get_table_names`
+    fn resolve_table_name(&self, name: &str) -> Option<&str> {
+        let name = name.trim().to_lowercase();
+        self.get_table_names()
+            .into_iter()
+            .find(|table| *table == name)
+            .or_else(|| {
+                self.get_table_aliases()
+                    .into_iter()
+                    .find(|(alias, _)| *alias == name)
+                    .map(|(_, table)| table)
+            })
+    }
+
+    fn get_table_ext(&self) -> &str;
+
+    /// Get the short name of the benchmark, such as `tpch`
+    fn get_name(&self) -> &str;
+
+    /// Check that the benchmark's generator can generate data at a scale factor, which may
+    /// still be one the specification does not allow. Defaults to any scale factor greater
+    /// than zero.
+    fn validate_scale(&self, scale: f64) -> Result<()> {
+        check_positive_scale(scale)
+    }
+
+    /// Get the number of rows the benchmark specification requires for a table at a scale
+    /// factor, or `None` if it is not a fixed number or the scale factor is not valid
+    fn get_expected_row_count(&self, _table: &str, _scale: f64) -> Option<u64> {
+        None
+    }
+
+    /// Estimate the number of rows of a table at a scale factor from the cardinalities in the
+    /// benchmark specification, or `None` if the size depends on the data or the scale factor
+    /// is not valid. Defaults to the expected row count.
+    fn estimated_row_count(&self, table: &str, scale: f64) -> Option<u64> {
+        self.get_expected_row_count(table, scale)
+    }
+
+    /// Get the schema of a table, failing if the table is not part of the benchmark
+    fn get_schema(&self, table: &str) -> Result<Schema>;
+
+    /// Get the name and schema of every table, in the order of `get_table_names`
+    fn schemas(&self) -> Result<Vec<(&str, Schema)>> {
+        self.get_table_names()
+            .into_iter()
+            .map(|table| Ok((table, self.get_schema(table)?)))
+            .collect()
+    }
+
+    /// Get the SQL of the benchmark's standard queries, with query N at index N - 1
+    fn queries(&self) -> &[&'static str] {
+        &[]
+    }
+}
+
+/// Create the benchmark with a name such as `tpch` or `tpc-ds`, or `None` if there is no such
+/// benchmark
+pub fn benchmark_by_name(name: &str) -> Option<Box<dyn Tpc>> {
+    match name {
+        "tpcds" | "tpc-ds" => Some(Box::new(tpcds::TpcDs::new())),
+        "tpch" | "tpc-h" => Some(Box::new(tpch::TpcH::new())),
+        _ => None,
+    }
+}
+
+/// Error for a table name that is not part of a benchmark
+pub(crate) fn unknown_table(table: &str, valid: &[&str]) -> TpcError {
+    TpcError::UnknownTable {
+        table: table.to_owned(),
+        valid: valid.iter().map(|name| name.to_string()).collect(),
+    }
+}
+
+/// Name of the marker file written once all of a table's output files are in place
+pub const SUCCESS_MARKER: &str = "_SUCCESS";
+
+/// How to handle output directories left by a previous run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResumeMode {
+    /// Fail if the output directory already exists
+    Fail,
+    /// Remove the existing output directory and convert the table again
+    Overwrite,
+    /// Skip tables that were fully converted by a previous run and redo any that were not
+    Skip,
+}
+
+impl FromStr for ResumeMode {
+    type Err = TpcError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(Self::Fail),
+            "overwrite" => Ok(Self::Overwrite),
+            "skip" => Ok(Self::Skip),
+            other => Err(TpcError::InvalidOption(format!(
+                "Invalid resume mode: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Options controlling how tables are converted
+#[derive(Debug, Clone)]
+pub struct ConversionOptions {
+    /// Output file format
+    pub format: OutputFormat,
+    /// Compression codec used for Parquet output, which is Snappy when not set. Other formats
+    /// are written uncompressed, and setting a codec other than `None` for them is an error.
+    pub compression: Option<CompressionCodec>,
+    /// Compression codecs for individual Parquet columns, which override `compression`. Each
+    /// column must be part of at least one of the converted tables.
+    pub column_compression: Vec<(String, CompressionCodec)>,
+    /// What to do when a table's output directory already exists
+    pub resume_mode: ResumeMode,
+    /// Maximum number of tables to convert at the same time
+    pub concurrency: usize,
+    /// Maximum number of files to convert at the same time within each table
+    pub file_concurrency: usize,
+    /// Number of rows per record batch when reading input. Smaller batches use less memory,
+    /// larger batches can improve throughput. Defaults to 8192.
+    pub batch_size: usize,
+    /// Maximum memory in bytes that DataFusion may use for sorting and repartitioning while
+    /// converting, beyond which it spills to disk or fails instead of running out of memory
+    pub memory_limit_bytes: Option<usize>,
+    /// Maximum number of rows per Parquet row group, or the Parquet default when not set
+    pub max_row_group_size: Option<usize>,
+    /// Best-effort maximum size in bytes of a Parquet data page
+    pub data_page_size_limit: Option<usize>,
+    /// Best-effort maximum size in bytes of a Parquet dictionary page
+    pub dictionary_page_size_limit: Option<usize>,
+    /// Use dictionary encoding for Parquet columns
+    pub dictionary_enabled: bool,
+    /// Columns to write without dictionary encoding even when it is enabled
+    pub dictionary_disabled_columns: Vec<String>,
+    /// Level of column statistics to write to Parquet files
+    pub statistics: EnabledStatistics,
+    /// Version of the Parquet format to write. Version 1.0 is readable by more readers.
+    pub writer_version: WriterVersion,
+    /// Write a Parquet bloom filter for every column
+    pub bloom_filter_enabled: bool,
+    /// Columns to write Parquet bloom filters for when they are not enabled for every column.
+    /// Each column must be part of at least one of the converted tables.
+    pub bloom_filter_columns: Vec<String>,
+    /// False positive probability of the bloom filters, or the Parquet default when not set
+    pub bloom_filter_fpp: Option<f64>,
+    /// Record the benchmark, table, compression, tpctools version, and the scale factor and
+    /// partitions when they are set in the key-value metadata of each Parquet file, with keys
+    /// such as `tpctools.benchmark`
+    pub footer_metadata: bool,
+    /// Key-value pairs to write to the metadata of each Parquet file
+    pub key_value_metadata: Vec<(String, String)>,
+    /// Field delimiter used in the input files, unless one is detected
+    pub delimiter: u8,
+    /// Detect each table's delimiter from its first input file, falling back to `delimiter`
+    /// when none is found. Setting a delimiter with the builder disables detection.
+    pub detect_delimiter: bool,
+    /// Input lines end with a delimiter after the last field, as written by dbgen and dsdgen
+    pub trailing_delimiter: bool,
+    /// Drop the empty field after a trailing delimiter. When `false`, it is written as an extra
+    /// last column named `__trailing_delimiter`, which shows any content after the last field of
+    /// malformed lines. Defaults to `true`.
+    pub drop_trailing_column: bool,
+    /// Check every input line for the table's number of fields before converting, failing or
+    /// skipping the lines that don't have it. Lines are not checked when not set, and the CSV
+    /// reader fails on the first malformed line without saying where it is.
+    pub malformed_rows: Option<MalformedRowMode>,
+    /// Field value that is read as null in nullable columns, such as `\N`, `NULL`, or an empty
+    /// string. Values are read literally when not set, apart from empty non-string fields.
+    pub null_value: Option<String>,
+    /// `strftime` format of date columns, such as `%m/%d/%Y`, when they are not `YYYY-MM-DD`
+    pub date_format: Option<String>,
+    /// `strftime` format of timestamp columns, such as `%Y-%m-%d %H:%M:%S`, when the CSV
+    /// reader can't parse them
+    pub timestamp_format: Option<String>,
+    /// Plan the conversion and check the inputs without reading or writing any data
+    pub dry_run: bool,
+    /// Only convert these tables, or every table in the benchmark when not set
+    pub tables: Option<Vec<String>>,
+    /// After converting a table, check that it has as many rows as its input and, when
+    /// `scale_factor` is set, as many rows as the benchmark requires
+    pub verify_row_counts: bool,
+    /// After converting a table to Parquet, check that the first output file has the columns of
+    /// the table's schema in order and with the same types, apart from nullability and the
+    /// trailing delimiter column
+    pub verify_schema: bool,
+    /// Scale factor the input was generated at
+    pub scale_factor: Option<f64>,
+    /// Number of partitions the input was generated with, recorded in the manifest
+    pub partitions: Option<usize>,
+    /// Write a manifest listing the converted files to `{output_path}/manifest.json`
+    pub write_manifest: bool,
+    /// Write the SHA-256 of each output file to `checksums.sha256` in the table's output
+    /// directory
+    pub write_checksums: bool,
+    /// Write an empty `_SUCCESS` file to each table's output directory once all of its files
+    /// are in place, which Spark and Hadoop read as the sign that the directory is complete
+    /// and which resuming with [`ResumeMode::Skip`] relies on
+    pub write_success_marker: bool,
+    /// Write a `CREATE TABLE` statement for each converted table in this dialect to
+    /// `{output_path}/{table}.sql`
+    pub write_ddl: Option<SqlDialect>,
+    /// Number of files to write for each table, or one file per input file when not set. Rows
+    /// are distributed a record batch at a time, so some files may be empty when a table has
+    /// fewer batches than partitions.
+    pub target_partitions: Option<usize>,
+    /// Number of files to write for particular tables, keyed by table name, instead of
+    /// `target_partitions`
+    pub table_partitions: Option<HashMap<String, usize>>,
+    /// Write each table to a single `{table}.{ext}` file instead of a directory of parts
+    pub single_file: bool,
+    /// Write the tables that have a partition column to Hive-style directories by the year or
+    /// month of the column. Each directory holds one file, or the table's number of target
+    /// partitions when that is set. The input is read once and sorted by the partition keys, so
+    /// that each directory's files are written and closed in turn. The sort is limited by
+    /// `memory_limit_bytes`, while the open Parquet writers of one directory each hold a row
+    /// group in memory.
+    pub partition_by: Option<DatePartitioning>,
+    /// Send an event as each table starts and finishes and as each output file is written
+    pub progress: Option<ProgressSender>,
+    /// Stop converting when the token is cancelled. No more tables or files are started once it
+    /// is, and the output of the tables being converted is removed.
+    pub cancellation: Option<CancellationToken>,
+    /// Schemas to read tables with instead of the benchmark's, keyed by table name. The input
+    /// files must have a field for each column of the schema.
+    pub schema_override: Option<HashMap<String, Schema>>,
+    /// Maximum number of rows per output file. Each file that would have more rows is split
+    /// into files of this many rows and a last file with the rest. Only supported for local
+    /// output that is not a single file.
+    pub max_rows_per_file: Option<usize>,
+    /// Sort the rows of each output file by these columns, in ascending order, which makes
+    /// Parquet statistics more selective. Every selected table must have the columns.
+    pub sort_columns: Option<Vec<String>>,
+    /// Retry moving and copying output files into place when they fail with a transient
+    /// error, such as on a network file system. Each is only tried once when not set.
+    pub io_retry: Option<RetryPolicy>,
+    /// Maximum number of output files to move or copy into place at the same time, independent
+    /// of `concurrency` and `file_concurrency`, such as to avoid thrashing spinning disks. The
+    /// limit is shared by every table, and by every conversion with a clone of the options.
+    pub io_limit: Option<IoLimit>,
+    /// Remove each table's input files once its output is complete, after checking the output
+    /// against its checksums when `write_checksums` is set. Input that fails to convert is
+    /// never removed, and neither are archives.
+    pub delete_source_on_success: bool,
+}
+
+impl ConversionOptions {
+    /// Create a builder starting from the default options
+    pub fn builder() -> ConversionOptionsBuilder {
+        ConversionOptionsBuilder::default()
+    }
+
+    /// Check that the options are usable before any work is done
+    pub fn validate(&self) -> Result<()> {
+        if self.concurrency == 0 || self.file_concurrency == 0 {
+            return Err(TpcError::InvalidOption(
+                "Concurrency must be at least 1".to_string(),
+            ));
+        }
+        if self.batch_size == 0 {
+            return Err(TpcError::InvalidOption(
+                "Batch size must be at least 1".to_string(),
+            ));
+        }
+        if self.memory_limit_bytes == Some(0) {
+            return Err(TpcError::InvalidOption(
+                "Memory limit must be at least 1 byte".to_string(),
+            ));
+        }
+        if let Some(date_format) = &self.date_format {
+            check_format("date", date_format)?;
+        }
+        if let Some(timestamp_format) = &self.timestamp_format {
+            check_format("timestamp", timestamp_format)?;
+        }
+        if self.delimiter == b'\n' || self.delimiter == b'\r' {
+            return Err(TpcError::InvalidOption(
+                "Delimiter cannot be a line break".to_string(),
+            ));
+        }
+        if self.target_partitions == Some(0) {
+            return Err(TpcError::InvalidOption(
+                "Target partitions must be at least 1".to_string(),
+            ));
+        }
+        if self
+            .table_partitions
+            .iter()
+            .flat_map(|partitions| partitions.values())
+            .any(|partitions| *partitions == 0)
+        {
+            return Err(TpcError::InvalidOption(
+                "Table partitions must be at least 1".to_string(),
+            ));
+        }
+        if self.single_file && (self.target_partitions.is_some() || self.table_partitions.is_some())
+        {
+            return Err(TpcError::InvalidOption(
+                "Single file output cannot be combined with target partitions".to_string(),
+            ));
+        }
+        if self.single_file && self.partition_by.is_some() {
+            return Err(TpcError::InvalidOption(
+                "Single file output cannot be combined with partitioning".to_string(),
+            ));
+        }
+        if self.max_rows_per_file == Some(0) {
+            return Err(TpcError::InvalidOption(
+                "Max rows per file must be at least 1".to_string(),
+            ));
+        }
+        if self.single_file && self.max_rows_per_file.is_some() {
+            return Err(TpcError::InvalidOption(
+                "Single file output cannot be combined with a maximum number of rows per file"
+                    .to_string(),
+            ));
+        }
+        if self.single_file && self.write_checksums {
+            return Err(TpcError::InvalidOption(
+                "Checksums are only written for tables with an output directory".to_string(),
+            ));
+        }
+        if self.resume_mode == ResumeMode::Skip && !self.write_success_marker {
+            return Err(TpcError::InvalidOption(
+                "Skipping converted tables needs the success marker that shows they are complete"
+                    .to_string(),
+            ));
+        }
+        if self
+            .sort_columns
+            .as_ref()
+            .is_some_and(|columns| columns.is_empty())
+        {
+            return Err(TpcError::InvalidOption(
+                "Sort columns cannot be empty".to_string(),
+            ));
+        }
+        if self
+            .bloom_filter_fpp
+            .is_some_and(|fpp| !(fpp > 0.0 && fpp < 1.0))
+        {
+            return Err(TpcError::InvalidOption(
+                "Bloom filter false positive probability must be between 0 and 1".to_string(),
+            ));
+        }
+        if self.format != OutputFormat::Parquet {
+            if let Some(codec) = self
+                .compression
+                .filter(|codec| *codec != CompressionCodec::None)
+            {
+                return Err(TpcError::InvalidOption(format!(
+                    "Compression codec {} is only supported for Parquet output, {} output is \
+                     written uncompressed",
+                    codec, self.format
+                )));
+            }
+            if !self.column_compression.is_empty() {
+                return Err(TpcError::InvalidOption(
+                    "Column compression is only supported for Parquet output".to_string(),
+                ));
+            }
+        }
+        if self.verify_schema && self.format != OutputFormat::Parquet {
+            return Err(TpcError::InvalidOption(
+                "Verifying schemas is only supported for Parquet output".to_string(),
+            ));
+        }
+        if self.max_row_group_size == Some(0) {
+            return Err(TpcError::InvalidOption(
+                "Max row group size must be at least 1".to_string(),
+            ));
+        }
+        if let Some(io_retry) = &self.io_retry {
+            io_retry.validate()?;
+        }
+        if self
+            .io_limit
+            .as_ref()
+            .is_some_and(|limit| limit.permits() == 0)
+        {
+            return Err(TpcError::InvalidOption(
+                "IO concurrency must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Get the codec the output is compressed with, which is Snappy for Parquet output by
+    /// default and `None` for the other formats
+    pub fn codec(&self) -> CompressionCodec {
+        match (self.compression, self.format) {
+            (Some(codec), _) => codec,
+            (None, OutputFormat::Parquet) => CompressionCodec::Snappy,
+            (None, _) => CompressionCodec::None,
+        }
+    }
+
+    /// Get the number of files to write for a table, which is its entry in `table_partitions`
+    /// if there is one and `target_partitions` otherwise
+    pub(crate) fn table_target_partitions(&self, table: &str) -> Option<usize> {
+        self.table_partitions
+            .as_ref()
+            .and_then(|partitions| partitions.get(table))
+            .copied()
+            .or(self.target_partitions)
+    }
+
+    /// Get the schema to read a table with, which is the override for the table if there is
+    /// one
+    pub(crate) fn table_schema(&self, benchmark: &dyn Tpc, table: &str) -> Result<Schema> {
+        match self
+            .schema_override
+            .as_ref()
+            .and_then(|schemas| schemas.get(table))
+        {
+            Some(schema) => Ok(schema.clone()),
+            None => benchmark.get_schema(table),
+        }
+    }
+
+    /// Fail with [`TpcError::Cancelled`] if the cancellation token has been cancelled
+    pub(crate) fn check_cancelled(&self) -> Result<()> {
+        match &self.cancellation {
+            Some(token) if token.is_cancelled() => Err(TpcError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    /// Get the delimiter to read a table's input files with, detecting it from the files when
+    /// detection is enabled
+    pub(crate) fn input_delimiter(&self, table: &str, files: &[PathBuf]) -> Result<u8> {
+        if !self.detect_delimiter {
+            return Ok(self.delimiter);
+        }
+        Ok(match detect_delimiter(files)? {
+            Some(delimiter) => {
+                if delimiter != self.delimiter {
+                    info!(
+                        "Detected delimiter {:?} in the input of table {}",
+                        delimiter as char, table
+                    );
+                }
+                delimiter
+            }
+            None => self.delimiter,
+        })
+    }
+
+    /// Create a DataFusion context configured with these options, to share between the
+    /// conversions of every table
+    pub fn session_context(&self) -> Result<SessionContext> {
+        let config = SessionConfig::new().with_batch_size(self.batch_size);
+        let mut runtime = RuntimeConfig::new();
+        if let Some(memory_limit_bytes) = self.memory_limit_bytes {
+            runtime = runtime.with_memory_limit(memory_limit_bytes, 1.0);
+        }
+        Ok(SessionContext::with_config_rt(
+            config,
+            Arc::new(RuntimeEnv::new(runtime)?),
+        ))
+    }
+
+    /// Build the Parquet writer properties for these options
+    pub fn writer_properties(&self) -> Result<WriterProperties> {
+        let mut builder =
+            WriterProperties::builder().set_compression(self.codec().to_parquet_compression()?);
+        for (column, codec) in &self.column_compression {
+            builder = builder.set_column_compression(
+                ColumnPath::from(column.as_str()),
+                codec.to_parquet_compression()?,
+            );
+        }
+        if let Some(max_row_group_size) = self.max_row_group_size {
+            builder = builder.set_max_row_group_size(max_row_group_size);
+        }
+        if let Some(data_page_size_limit) = self.data_page_size_limit {
+            builder = builder.set_data_pagesize_limit(data_page_size_limit);
+        }
+        if let Some(dictionary_page_size_limit) = self.dictionary_page_size_limit {
+            builder = builder.set_dictionary_pagesize_limit(dictionary_page_size_limit);
+        }
+        builder = builder.set_dictionary_enabled(self.dictionary_enabled);
+        for column in &self.dictionary_disabled_columns {
+            builder =
+                builder.set_column_dictionary_enabled(ColumnPath::from(column.as_str()), false);
+        }
+        builder = builder.set_statistics_enabled(self.statistics);
+        builder = builder.set_writer_version(self.writer_version);
+        if self.bloom_filter_enabled {
+            builder = builder.set_bloom_filter_enabled(true);
+            if let Some(fpp) = self.bloom_filter_fpp {
+                builder = builder.set_bloom_filter_fpp(fpp);
+            }
+        }
+        for column in &self.bloom_filter_columns {
+            let path = ColumnPath::from(column.as_str());
+            builder = builder.set_column_bloom_filter_enabled(path.clone(), true);
+            if let Some(fpp) = self.bloom_filter_fpp {
+                builder = builder.set_column_bloom_filter_fpp(path, fpp);
+            }
+        }
+        if !self.key_value_metadata.is_empty() {
+            builder = builder.set_key_value_metadata(Some(
+                self.key_value_metadata
+                    .iter()
+                    .map(|(key, value)| KeyValue::new(key.clone(), value.clone()))
+                    .collect(),
+            ));
+        }
+        Ok(builder.build())
+    }
+
+    /// Get the options to convert a table with, which have the table's provenance in their
+    /// key-value metadata when `footer_metadata` is set
+    fn table_options(&self, benchmark: &dyn Tpc, table: &str) -> Self {
+        let mut options = self.clone();
+        if self.footer_metadata {
+            let mut metadata = vec![
+                ("benchmark", benchmark.get_name().to_string()),
+                ("table", table.to_string()),
+                ("compression", self.codec().to_string()),
+                ("version", env!("CARGO_PKG_VERSION").to_string()),
+            ];
+            if let Some(scale_factor) = self.scale_factor {
+                metadata.push(("scale_factor", scale_factor.to_string()));
+            }
+            if let Some(partitions) = self.partitions {
+                metadata.push(("partitions", partitions.to_string()));
+            }
+            for (key, value) in metadata {
+                options
+                    .key_value_metadata
+                    .push((format!("tpctools.{}", key), value));
+            }
+        }
+        options
+    }
+
+    /// Get the tables to convert, in benchmark table order. Fails if a requested table is not
+    /// part of the benchmark.
+    pub fn selected_tables<'a>(&self, benchmark: &'a dyn Tpc) -> Result<Vec<&'a str>> {
+        let names = benchmark.get_table_names();
+        match &self.tables {
+            None => Ok(names),
+            Some(tables) => {
+                let mut selected = vec![];
+                for table in tables {
+                    match benchmark.resolve_table_name(table) {
+                        Some(name) => selected.push(name),
+                        None => return Err(unknown_table(table, &names)),
+                    }
+                }
+                Ok(names
+                    .into_iter()
+                    .filter(|name| selected.contains(name))
+                    .collect())
+            }
+        }
+    }
+}
+
+impl Default for ConversionOptions {
+    fn default() -> Self {
+        Self {
+            format: OutputFormat::Parquet,
+            compression: None,
+            column_compression: vec![],
+            resume_mode: ResumeMode::Fail,
+            concurrency: 1,
+            file_concurrency: 1,
+            batch_size: 8192,
+            memory_limit_bytes: None,
+            max_row_group_size: None,
+            data_page_size_limit: None,
+            dictionary_page_size_limit: None,
+            dictionary_enabled: true,
+            dictionary_disabled_columns: vec![],
+            bloom_filter_enabled: false,
+            bloom_filter_columns: vec![],
+            bloom_filter_fpp: None,
+            footer_metadata: false,
+            key_value_metadata: vec![],
+            statistics: EnabledStatistics::default(),
+            writer_version: WriterVersion::PARQUET_1_0,
+            delimiter: b'|',
+            detect_delimiter: true,
+            trailing_delimiter: true,
+            drop_trailing_column: true,
+            malformed_rows: None,
+            null_value: None,
+            date_format: None,
+            timestamp_format: None,
+            dry_run: false,
+            tables: None,
+            verify_row_counts: false,
+            verify_schema: false,
+            scale_factor: None,
+            partitions: None,
+            write_manifest: false,
+            write_checksums: false,
+            write_success_marker: true,
+            write_ddl: None,
+            target_partitions: None,
+            table_partitions: None,
+            single_file: false,
+            partition_by: None,
+            progress: None,
+            cancellation: None,
+            schema_override: None,
+            max_rows_per_file: None,
+            sort_columns: None,
+            io_retry: None,
+            io_limit: None,
+            delete_source_on_success: false,
+        }
+    }
+}
+
+/// Builder for [`ConversionOptions`], starting from the defaults
+#[derive(Debug, Clone, Default)]
+pub struct ConversionOptionsBuilder {
+    options: ConversionOptions,
+}
+
+impl ConversionOptionsBuilder {
+    /// Set the output file format
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.options.format = format;
+        self
+    }
+
+    /// Set the compression codec used for Parquet output
+    pub fn compression(mut self, compression: CompressionCodec) -> Self {
+        self.options.compression = Some(compression);
+        self
+    }
+
+    /// Set the compression codecs of individual Parquet columns
+    pub fn column_compression(
+        mut self,
+        column_compression: Vec<(String, CompressionCodec)>,
+    ) -> Self {
+        self.options.column_compression = column_compression;
+        self
+    }
+
+    /// Set what to do when a table's output directory already exists
+    pub fn resume_mode(mut self, resume_mode: ResumeMode) -> Self {
+        self.options.resume_mode = resume_mode;
+        self
+    }
+
+    /// Set the maximum number of tables to convert at the same time
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.options.concurrency = concurrency;
+        self
+    }
+
+    /// Set the maximum number of files to convert at the same time within each table
+    pub fn file_concurrency(mut self, file_concurrency: usize) -> Self {
+        self.options.file_concurrency = file_concurrency;
+        self
+    }
+
+    /// Set the number of rows per record batch when reading input
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.options.batch_size = batch_size;
+        self
+    }
+
+    /// Limit the memory DataFusion may use while converting
+    pub fn memory_limit_bytes(mut self, memory_limit_bytes: usize) -> Self {
+        self.options.memory_limit_bytes = Some(memory_limit_bytes);
+        self
+    }
+
+    /// Set the maximum number of rows per Parquet row group
+    pub fn max_row_group_size(mut self, max_row_group_size: usize) -> Self {
+        self.options.max_row_group_size = Some(max_row_group_size);
+        self
+    }
+
+    /// Set the best-effort maximum size in bytes of a Parquet data page
+    pub fn data_page_size_limit(mut self, data_page_size_limit: usize) -> Self {
+        self.options.data_page_size_limit = Some(data_page_size_limit);
+        self
+    }
+
+    /// Set the best-effort maximum size in bytes of a Parquet dictionary page
+    pub fn dictionary_page_size_limit(mut self, dictionary_page_size_limit: usize) -> Self {
+        self.options.dictionary_page_size_limit = Some(dictionary_page_size_limit);
+        self
+    }
+
+    /// Enable or disable dictionary encoding for Parquet columns
+    pub fn dictionary_enabled(mut self, dictionary_enabled: bool) -> Self {
+        self.options.dictionary_enabled = dictionary_enabled;
+        self
+    }
+
+    /// Set the columns to write without dictionary encoding
+    pub fn dictionary_disabled_columns(mut self, dictionary_disabled_columns: Vec<String>) -> Self {
+        self.options.dictionary_disabled_columns = dictionary_disabled_columns;
+        self
+    }
+
+    /// Set the level of column statistics to write to Parquet files
+    pub fn statistics(mut self, statistics: EnabledStatistics) -> Self {
+        self.options.statistics = statistics;
+        self
+    }
+
+    /// Set the version of the Parquet format to write
+    pub fn writer_version(mut self, writer_version: WriterVersion) -> Self {
+        self.options.writer_version = writer_version;
+        self
+    }
+
+    /// Enable or disable Parquet bloom filters for every column
+    pub fn bloom_filter_enabled(mut self, bloom_filter_enabled: bool) -> Self {
+        self.options.bloom_filter_enabled = bloom_filter_enabled;
+        self
+    }
+
+    /// Set the columns to write Parquet bloom filters for
+    pub fn bloom_filter_columns(mut self, bloom_filter_columns: Vec<String>) -> Self {
+        self.options.bloom_filter_columns = bloom_filter_columns;
+        self
+    }
+
+    /// Set the false positive probability of the bloom filters
+    pub fn bloom_filter_fpp(mut self, bloom_filter_fpp: f64) -> Self {
+        self.options.bloom_filter_fpp = Some(bloom_filter_fpp);
+        self
+    }
+
+    /// Record how each Parquet file was produced in its key-value metadata
+    pub fn footer_metadata(mut self, footer_metadata: bool) -> Self {
+        self.options.footer_metadata = footer_metadata;
+        self
+    }
+
+    /// Add a key-value pair to the metadata of each Parquet file
+    pub fn key_value_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.options
+            .key_value_metadata
+            .push((key.into(), value.into()));
+        self
+    }
+
+    /// Set the field delimiter used in the input files, which disables detecting it
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.options.delimiter = delimiter;
+        self.options.detect_delimiter = false;
+        self
+    }
+
+    /// Set whether to detect each table's delimiter from its input files
+    pub fn detect_delimiter(mut self, detect_delimiter: bool) -> Self {
+        self.options.detect_delimiter = detect_delimiter;
+        self
+    }
+
+    /// Set whether input lines end with a delimiter after the last field
+    pub fn trailing_delimiter(mut self, trailing_delimiter: bool) -> Self {
+        self.options.trailing_delimiter = trailing_delimiter;
+        self
+    }
+
+    /// Set whether to drop the empty field after a trailing delimiter rather than writing it
+    pub fn drop_trailing_column(mut self, drop_trailing_column: bool) -> Self {
+        self.options.drop_trailing_column = drop_trailing_column;
+        self
+    }
+
+    /// Check input lines for the wrong number of fields, failing or skipping them
+    pub fn malformed_rows(mut self, mode: MalformedRowMode) -> Self {
+        self.options.malformed_rows = Some(mode);
+        self
+    }
+
+    /// Set the field value that is read as null in nullable columns
+    pub fn null_value(mut self, null_value: impl Into<String>) -> Self {
+        self.options.null_value = Some(null_value.into());
+        self
+    }
+
+    /// Set the `strftime` format of date columns
+    pub fn date_format(mut self, date_format: impl Into<String>) -> Self {
+        self.options.date_format = Some(date_format.into());
+        self
+    }
+
+    /// Set the `strftime` format of timestamp columns
+    pub fn timestamp_format(mut self, timestamp_format: impl Into<String>) -> Self {
+        self.options.timestamp_format = Some(timestamp_format.into());
+        self
+    }
+
+    /// Plan the conversion without reading or writing any data
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.options.dry_run = dry_run;
+        self
+    }
+
+    /// Only convert these tables
+    pub fn tables(mut self, tables: Vec<String>) -> Self {
+        self.options.tables = Some(tables);
+        self
+    }
+
+    /// Check that each converted table has as many rows as its input
+    pub fn verify_row_counts(mut self, verify_row_counts: bool) -> Self {
+        self.options.verify_row_counts = verify_row_counts;
+        self
+    }
+
+    /// Check that each converted Parquet table has the columns of its schema
+    pub fn verify_schema(mut self, verify_schema: bool) -> Self {
+        self.options.verify_schema = verify_schema;
+        self
+    }
+
+    /// Set the scale factor the input was generated at
+    pub fn scale_factor(mut self, scale_factor: f64) -> Self {
+        self.options.scale_factor = Some(scale_factor);
+        self
+    }
+
+    /// Set the number of partitions the input was generated with
+    pub fn partitions(mut self, partitions: usize) -> Self {
+        self.options.partitions = Some(partitions);
+        self
+    }
+
+    /// Write a manifest listing the converted files
+    pub fn write_manifest(mut self, write_manifest: bool) -> Self {
+        self.options.write_manifest = write_manifest;
+        self
+    }
+
+    /// Write the SHA-256 of each output file to the table's output directory
+    pub fn write_checksums(mut self, write_checksums: bool) -> Self {
+        self.options.write_checksums = write_checksums;
+        self
+    }
+
+    /// Write an empty `_SUCCESS` file to each table's output directory once it is complete,
+    /// which is on by default
+    pub fn write_success_marker(mut self, write_success_marker: bool) -> Self {
+        self.options.write_success_marker = write_success_marker;
+        self
+    }
+
+    /// Write a `{table}.sql` file with a `CREATE TABLE` statement in a dialect for each table
+    pub fn write_ddl(mut self, dialect: SqlDialect) -> Self {
+        self.options.write_ddl = Some(dialect);
+        self
+    }
+
+    /// Set the number of files to write for each table
+    pub fn target_partitions(mut self, target_partitions: usize) -> Self {
+        self.options.target_partitions = Some(target_partitions);
+        self
+    }
+
+    /// Set the number of files to write for particular tables, keyed by table name
+    pub fn table_partitions(mut self, table_partitions: HashMap<String, usize>) -> Self {
+        self.options.table_partitions = Some(table_partitions);
+        self
+    }
+
+    /// Write each table to a single file instead of a directory of parts
+    pub fn single_file(mut self, single_file: bool) -> Self {
+        self.options.single_file = single_file;
+        self
+    }
+
+    /// Partition each table's output by a date column
+    pub fn partition_by(mut self, partition_by: DatePartitioning) -> Self {
+        self.options.partition_by = Some(partition_by);
+        self
+    }
+
+    /// Read tables with these schemas instead of the benchmark's, keyed by table name
+    pub fn schema_override(mut self, schema_override: HashMap<String, Schema>) -> Self {
+        self.options.schema_override = Some(schema_override);
+        self
+    }
+
+    /// Set the maximum number of rows per output file
+    pub fn max_rows_per_file(mut self, max_rows_per_file: usize) -> Self {
+        self.options.max_rows_per_file = Some(max_rows_per_file);
+        self
+    }
+
+    /// Sort the rows of each output file by these columns
+    pub fn sort_columns(mut self, sort_columns: Vec<String>) -> Self {
+        self.options.sort_columns = Some(sort_columns);
+        self
+    }
+
+    /// Retry moving and copying output files into place when they fail with a transient error
+    pub fn io_retry(mut self, io_retry: RetryPolicy) -> Self {
+        self.options.io_retry = Some(io_retry);
+        self
+    }
+
+    /// Limit the number of output files moved or copied into place at the same time
+    pub fn io_concurrency(mut self, io_concurrency: usize) -> Self {
+        self.options.io_limit = Some(IoLimit::new(io_concurrency));
+        self
+    }
+
+    /// Remove each table's input files once its output is complete
+    pub fn delete_source_on_success(mut self, delete_source_on_success: bool) -> Self {
+        self.options.delete_source_on_success = delete_source_on_success;
+        self
+    }
+
+    /// Send progress events to a channel while converting
+    pub fn progress(mut self, progress: ProgressSender) -> Self {
+        self.options.progress = Some(progress);
+        self
+    }
+
+    /// Stop converting when a token is cancelled
+    pub fn cancellation_token(mut self, cancellation: CancellationToken) -> Self {
+        self.options.cancellation = Some(cancellation);
+        self
+    }
+
+    /// Build the options. They are validated when a conversion starts.
+    pub fn build(self) -> ConversionOptions {
+        self.options
+    }
+}
+
+/// Summary of a conversion run
+#[derive(Debug, Clone, Default)]
+pub struct ConversionReport {
+    /// Statistics for each converted table, in benchmark table order. Tables skipped
+    /// because they were already converted are not included.
+    pub per_table: Vec<TableStats>,
+    /// The files each table was planned to read and write, in benchmark table order
+    pub plan: Vec<TablePlan>,
+    /// Time taken to convert every table
+    pub elapsed: Duration,
+}
+
+impl ConversionReport {
+    /// Number of rows converted per second over the whole run
+    pub fn rows_per_second(&self) -> f64 {
+        per_second(
+            self.per_table.iter().map(|stats| stats.rows).sum(),
+            self.elapsed,
+        )
+    }
+
+    /// Number of input bytes converted per second over the whole run
+    pub fn input_bytes_per_second(&self) -> f64 {
+        per_second(
+            self.per_table.iter().map(|stats| stats.input_bytes).sum(),
+            self.elapsed,
+        )
+    }
+
+    /// Format a table of the rows and input megabytes converted per second for each table and
+    /// for the whole run, in which tables converted at the same time overlap
+    pub fn throughput_summary(&self) -> String {
+        let width = self
+            .per_table
+            .iter()
+            .map(|stats| stats.table.len())
+            .chain(std::iter::once("total".len()))
+            .max()
+            .unwrap_or_default();
+        let row = |table: &str, rows: u64, bytes: u64, elapsed: Duration| {
+            format!(
+                "{:<width$} {:>12} {:>12.1} {:>10.3} {:>14.0} {:>12.1}\n",
+                table,
+                rows,
+                bytes as f64 / 1e6,
+                elapsed.as_secs_f64(),
+                per_second(rows, elapsed),
+                per_second(bytes, elapsed) / 1e6,
+                width = width
+            )
+        };
+        let mut summary = format!(
+            "{:<width$} {:>12} {:>12} {:>10} {:>14} {:>12}\n",
+            "table",
+            "rows",
+            "input MB",
+            "seconds",
+            "rows/s",
+            "input MB/s",
+            width = width
+        );
+        for stats in &self.per_table {
+            summary.push_str(&row(
+                &stats.table,
+                stats.rows,
+                stats.input_bytes,
+                stats.elapsed,
+            ));
+        }
+        summary.push_str(&row(
+            "total",
+            self.per_table.iter().map(|stats| stats.rows).sum(),
+            self.per_table.iter().map(|stats| stats.input_bytes).sum(),
+            self.elapsed,
+        ));
+        summary
+    }
+}
+
+/// Divide an amount by a duration in seconds, or return zero for an empty duration
+fn per_second(amount: u64, elapsed: Duration) -> f64 {
+    let seconds = elapsed.as_secs_f64();
+    if seconds > 0.0 {
+        amount as f64 / seconds
+    } else {
+        0.0
+    }
+}
+
+/// The input files of a table and the output files they will be converted to
+#[derive(Debug, Clone)]
+pub struct TablePlan {
+    pub table: String,
+    pub inputs: Vec<PathBuf>,
+    /// Field delimiter the input files will be read with
+    pub delimiter: u8,
+    /// Expected output files, assuming one output file per input file unless the number of
+    /// partitions is set, and not counting files split by `max_rows_per_file`
+    pub outputs: Vec<String>,
+}
+
+/// Statistics for a single converted table
+#[derive(Debug, Clone)]
+pub struct TableStats {
+    pub table: String,
+    /// Number of rows written
+    pub rows: u64,
+    /// Total size of the output files in bytes
+    pub bytes: u64,
+    /// Total size of the input files in bytes
+    pub input_bytes: u64,
+    /// Output files, relative to the output path
+    pub files: Vec<String>,
+    /// Time taken to convert the table
+    pub elapsed: Duration,
+    /// Time spent reading and parsing the input, summed over the files converted at the same
+    /// time
+    pub read_time: Duration,
+    /// Time spent encoding and writing the output, summed over the files converted at the same
+    /// time
+    pub write_time: Duration,
+    /// Input rows that were skipped because they had the wrong number of fields
+    pub malformed_rows: Vec<MalformedRow>,
+}
+
+impl TableStats {
+    /// Number of rows converted per second
+    pub fn rows_per_second(&self) -> f64 {
+        per_second(self.rows, self.elapsed)
+    }
+
+    /// Number of input bytes converted per second
+    pub fn input_bytes_per_second(&self) -> f64 {
+        per_second(self.input_bytes, self.elapsed)
+    }
+}
+
+pub async fn convert_to_parquet(
+    benchmark: &dyn Tpc,
+    input_path: &str,
+    output_path: &str,
+    options: &ConversionOptions,
+) -> Result<ConversionReport> {
+    options.validate()?;
+    let start = Instant::now();
+    let tables = options.selected_tables(benchmark)?;
+    let extracted = if options.dry_run {
+        list_input(benchmark, input_path, &tables)?
+    } else {
+        extract_input(benchmark, input_path, &tables)?
+    };
+    let archive_path = input_path;
+    let extracted_path = extracted
+        .as_ref()
+        .map(|dir| dir.path().display().to_string());
+    let input_path = extracted_path.as_deref().unwrap_or(input_path);
+
+    // check that every table is present before creating any output
+    let mut plan = plan_conversion(benchmark, input_path, output_path, options)?;
+    if options.dry_run {
+        // the listed files are removed on return, so name the members of the archive instead
+        if let Some(dir) = &extracted {
+            for input in plan.iter_mut().flat_map(|table| table.inputs.iter_mut()) {
+                if let Ok(member) = input.strip_prefix(dir.path()) {
+                    *input = Path::new(archive_path).join(member);
+                }
+            }
+        }
+        for table in &plan {
+            info!(
+                "Would convert {} files for table {}",
+                table.inputs.len(),
+                table.table
+            );
+            for (input, output) in table.inputs.iter().zip(&table.outputs) {
+                info!("Would convert '{}' to {}", input.display(), output);
+            }
+        }
+        return Ok(ConversionReport {
+            per_table: vec![],
+            plan,
+            elapsed: start.elapsed(),
+        });
+    }
+
+    let ctx = options.session_context()?;
+    if let Some(url) = remote::parse_input_url(input_path)? {
+        remote::register_object_store(&ctx, &url)?;
+    }
+    // a table that fails cancels the others, so that they stop and remove their incomplete
+    // output rather than being dropped part way through
+    let cancellation = match &options.cancellation {
+        Some(token) => token.child_token(),
+        None => CancellationToken::new(),
+    };
+    let table_options = &ConversionOptions {
+        cancellation: Some(cancellation.clone()),
+        ..options.clone()
+    };
+    let results = stream::iter(plan.iter().enumerate())
+        .map(|(i, table)| {
+            let span = info_span!("table", table = table.table.as_str());
+            let ctx = &ctx;
+            let cancellation = &cancellation;
+            async move {
+                let stats = write_table(ctx, benchmark, table, output_path, table_options).await;
+                if stats.is_err() {
+                    cancellation.cancel();
+                }
+                Ok::<_, TpcError>(stats?.map(|stats| (i, stats)))
+            }
+            .instrument(span)
+        })
+        .buffer_unordered(options.concurrency)
+        .collect::<Vec<_>>()
+        .await;
+    // report the error that stopped the conversion rather than the tables it cancelled
+    let mut per_table = vec![];
+    let mut cancelled = false;
+    for result in results {
+        match result {
+            Ok(stats) => per_table.extend(stats),
+            Err(TpcError::Cancelled) => cancelled = true,
+            Err(e) => return Err(e),
+        }
+    }
+    if cancelled {
+        return Err(TpcError::Cancelled);
+    }
+    per_table.sort_by_key(|(i, _)| *i);
+
+    let report = ConversionReport {
+        per_table: per_table.into_iter().map(|(_, stats)| stats).collect(),
+        plan,
+        elapsed: start.elapsed(),
+    };
+    if options.write_manifest {
+        Manifest::new(&report, options).write(output_path).await?;
+    }
+    Ok(report)
+}
+
+/// List the files that would be read and written for each table, without reading or writing
+/// any data. Fails if a table's input is missing or two input files would be written to the
+/// same output.
+pub fn plan_conversion(
+    benchmark: &dyn Tpc,
+    input_path: &str,
+    output_path: &str,
+    options: &ConversionOptions,
+) -> Result<Vec<TablePlan>> {
+    check_option_tables(benchmark, options)?;
+    if let Some(scale_factor) = options.scale_factor {
+        benchmark.validate_scale(scale_factor)?;
+    }
+    let tables = options.selected_tables(benchmark)?;
+    check_option_columns(benchmark, &tables, options)?;
+    tables
+        .into_iter()
+        .map(|table| plan_table(benchmark, table, input_path, output_path, options))
+        .collect()
+}
+
+/// Extract the input files of the tables to a temporary directory when the input path is a
+/// tar archive
+pub(crate) fn extract_input(
+    benchmark: &dyn Tpc,
+    input_path: &str,
+    tables: &[&str],
+) -> Result<Option<TempDir>> {
+    if is_tar_archive(input_path) {
+        Ok(Some(extract_tables(benchmark, input_path, tables)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Lay out the input files of the tables in a temporary directory with only their first
+/// lines when the input path is a tar archive, to plan a dry run without extracting it
+fn list_input(benchmark: &dyn Tpc, input_path: &str, tables: &[&str]) -> Result<Option<TempDir>> {
+    if is_tar_archive(input_path) {
+        Ok(Some(list_tables(benchmark, input_path, tables)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Check that every table with a schema override or a number of partitions is part of the
+/// benchmark
+fn check_option_tables(benchmark: &dyn Tpc, options: &ConversionOptions) -> Result<()> {
+    let names = benchmark.get_table_names();
+    let schema_tables = options
+        .schema_override
+        .iter()
+        .flat_map(|schemas| schemas.keys());
+    let partition_tables = options
+        .table_partitions
+        .iter()
+        .flat_map(|partitions| partitions.keys());
+    for table in schema_tables.chain(partition_tables) {
+        if !names.contains(&table.as_str()) {
+            return Err(unknown_table(table, &names));
+        }
+    }
+    Ok(())
+}
+
+/// Check that every bloom filter and column compression column is a column of at least one of
+/// the tables, and that a null value can apply to at least one nullable column
+fn check_option_columns(
+    benchmark: &dyn Tpc,
+    tables: &[&str],
+    options: &ConversionOptions,
+) -> Result<()> {
+    let schemas = tables
+        .iter()
+        .map(|table| options.table_schema(benchmark, table))
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(null_value) = &options.null_value {
+        let nullable = schemas
+            .iter()
+            .any(|schema| schema.fields().iter().any(|field| field.is_nullable()));
+        if !nullable {
+            return Err(TpcError::InvalidOption(format!(
+                "Null value {:?} cannot be used because no converted table has nullable columns",
+                null_value
+            )));
+        }
+    }
+    let columns = options
+        .bloom_filter_columns
+        .iter()
+        .map(|column| ("Bloom filter", column))
+        .chain(
+            options
+                .column_compression
+                .iter()
+                .map(|(column, _)| ("Compression", column)),
+        );
+    for (description, column) in columns {
+        if !schemas
+            .iter()
+            .any(|schema| schema.field_with_name(column).is_ok())
+        {
+            return Err(TpcError::InvalidOption(format!(
+                "{} column {} is not a column of any converted table",
+                description, column
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// List the files that would be read and written for one table
+fn plan_table(
+    benchmark: &dyn Tpc,
+    table: &str,
+    input_path: &str,
+    output_path: &str,
+    options: &ConversionOptions,
+) -> Result<TablePlan> {
+    let ext = options.format.extension();
+    let inputs = table_inputs(benchmark, table, input_path)?;
+    let remote_input = remote::parse_input_url(input_path)?.is_some();
+    if remote_input && (options.malformed_rows.is_some() || options.delete_source_on_success) {
+        return Err(TpcError::InvalidOption(
+            "Checking input rows and removing input files are only supported for local input"
+                .to_string(),
+        ));
+    }
+
+    // input read over HTTP is only read once, when it is converted
+    let delimiter = if remote_input {
+        options.delimiter
+    } else {
+        options.input_delimiter(table, &inputs)?
+    };
+    if let Some(schema) = options
+        .schema_override
+        .as_ref()
+        .and_then(|schemas| schemas.get(table))
+        .filter(|_| !remote_input)
+    {
+        check_field_count(
+            table,
+            schema,
+            &inputs,
+            delimiter,
+            options.trailing_delimiter,
+        )?;
+    }
+
+    if let Some(columns) = &options.sort_columns {
+        let schema = options.table_schema(benchmark, table)?;
+        for column in columns {
+            if schema.field_with_name(column).is_err() {
+                return Err(TpcError::InvalidOption(format!(
+                    "Sort column {} is not a column of table {}",
+                    column, table
+                )));
+            }
+        }
+    }
+
+    let partitioning = options
+        .partition_by
+        .as_ref()
+        .filter(|partitioning| partitioning.column(table).is_some());
+    if let Some(partitioning) = partitioning {
+        partitioning.validate(table, &options.table_schema(benchmark, table)?)?;
+        if remote::parse_url(output_path)?.is_some() {
+            return Err(TpcError::InvalidOption(
+                "Partitioned output is only supported for local paths".to_string(),
+            ));
+        }
+    }
+
+    if options.max_rows_per_file.is_some() && remote::parse_url(output_path)?.is_some() {
+        return Err(TpcError::InvalidOption(
+            "Splitting files by row count is only supported for local paths".to_string(),
+        ));
+    }
+
+    // local conversions stage each file in a temp dir named after the input file
+    if remote::parse_url(output_path)?.is_none() {
+        let mut stubs: HashMap<String, &PathBuf> = HashMap::new();
+        for file in &inputs {
+            if let Some(other) = stubs.insert(file_stub(file), file) {
+                return Err(TpcError::Conversion(format!(
+                    "Input files {} and {} have the same name for table {}",
+                    other.display(),
+                    file.display(),
+                    table
+                )));
+            }
+        }
+    }
+
+    let outputs = if options.single_file {
+        vec![format!("{}/{}.{}", output_path, table, ext)]
+    } else if let Some(partitioning) = partitioning {
+        // the partition directories depend on the data, so only the pattern is known
+        (0..options.table_target_partitions(table).unwrap_or(1))
+            .map(|part| {
+                format!(
+                    "{}/{}.{}/{}/part-{}.{}",
+                    output_path,
+                    table,
+                    ext,
+                    partitioning.dir_pattern(),
+                    part,
+                    ext
+                )
+            })
+            .collect()
+    } else {
+        let output_count = options
+            .table_target_partitions(table)
+            .unwrap_or(inputs.len());
+        (0..output_count)
+            .map(|part| format!("{}/{}.{}/part-{}.{}", output_path, table, ext, part, ext))
+            .collect()
+    };
+    Ok(TablePlan {
+        table: table.to_owned(),
+        inputs,
+        delimiter,
+        outputs,
+    })
+}
+
+/// Convert one table, which may be split across many input files, to
+/// `{output_path}/{table}.{ext}`. Returns `None` if the table was skipped because a previous
+/// run already converted it.
+pub async fn convert_table(
+    benchmark: &dyn Tpc,
+    table: &str,
+    input_path: &str,
+    output_path: &str,
+    options: &ConversionOptions,
+) -> Result<Option<TableStats>> {
+    options.validate()?;
+    let table = benchmark
+        .resolve_table_name(table)
+        .ok_or_else(|| unknown_table(table, &benchmark.get_table_names()))?;
+    let extracted = extract_input(benchmark, input_path, &[table])?;
+    let extracted_path = extracted
+        .as_ref()
+        .map(|dir| dir.path().display().to_string());
+    let input_path = extracted_path.as_deref().unwrap_or(input_path);
+    check_option_tables(benchmark, options)?;
+    check_option_columns(benchmark, &[table], options)?;
+    let plan = plan_table(benchmark, table, input_path, output_path, options)?;
+    let ctx = options.session_context()?;
+    if let Some(url) = remote::parse_input_url(input_path)? {
+        remote::register_object_store(&ctx, &url)?;
+    }
+    write_table(&ctx, benchmark, &plan, output_path, options)
+        .instrument(info_span!("table", table))
+        .await
+}
+
+/// Read a table's input files into a DataFrame with the benchmark's schema, without
+/// converting them. The input is read with the default delimiter and trailing delimiter.
+pub async fn read_table(
+    benchmark: &dyn Tpc,
+    table: &str,
+    input_path: &str,
+    ctx: &SessionContext,
+) -> Result<DataFrame> {
+    let table = benchmark
+        .resolve_table_name(table)
+        .ok_or_else(|| unknown_table(table, &benchmark.get_table_names()))?;
+    let files = table_inputs(benchmark, table, input_path)?;
+    if let Some(url) = remote::parse_input_url(input_path)? {
+        remote::register_object_store(ctx, &url)?;
+    }
+
+    let options = ConversionOptions::default();
+    let schema = benchmark.get_schema(table)?;
+    let file_schema = tbl_file_schema(&schema, &options)?;
+    let csv_options = csv_read_options(benchmark, &file_schema, options.delimiter);
+    read_tbl_files(ctx, &files, &csv_options).await
+}
+
+async fn write_table(
+    ctx: &SessionContext,
+    benchmark: &dyn Tpc,
+    plan: &TablePlan,
+    output_path: &str,
+    options: &ConversionOptions,
+) -> Result<Option<TableStats>> {
+    options.check_cancelled()?;
+    let table = plan.table.as_str();
+    info!("Converting table");
+    let start = Instant::now();
+    let options = &options.table_options(benchmark, table);
+    send_progress(
+        options,
+        ProgressEvent::TableStarted {
+            table: table.to_owned(),
+            input_files: plan.inputs.len(),
+        },
+    );
+
+    // measured before converting, as the input may be removed afterwards
+    let input_bytes = input_bytes(ctx, &plan.inputs).await?;
+    let schema = options.table_schema(benchmark, table)?;
+    let file_schema = tbl_file_schema(&schema, options)?;
+    let csv_options = csv_read_options(benchmark, &file_schema, plan.delimiter);
+
+    let checked = match options.malformed_rows {
+        Some(mode) => Some(check_input(
+            table,
+            &plan.inputs,
+            file_schema.fields().len(),
+            plan.delimiter,
+            mode,
+        )?),
+        None => None,
+    };
+    let file_vec = match &checked {
+        Some(checked) => &checked.files,
+        None => &plan.inputs,
+    };
+    let malformed_rows = checked
+        .as_ref()
+        .map(|checked| checked.malformed_rows.clone())
+        .unwrap_or_default();
+    if let Some(first) = malformed_rows.first() {
+        warn!(
+            "Skipping {} malformed rows, the first at {}",
+            malformed_rows.len(),
+            first
+        );
+    }
+
+    let result = match remote::parse_url(output_path)? {
+        Some(output_url) => {
+            remote::convert_table(
+                ctx,
+                table,
+                file_vec,
+                &output_url,
+                &csv_options,
+                options,
+                start,
+            )
+            .await
+        }
+        None if options.single_file => {
+            write_local_file(
+                ctx,
+                table,
+                file_vec,
+                output_path,
+                &csv_options,
+                options,
+                start,
+            )
+            .await
+        }
+        None => {
+            write_local_table(
+                ctx,
+                table,
+                file_vec,
+                output_path,
+                &csv_options,
+                options,
+                start,
+            )
+            .await
+        }
+    };
+    let stats = match result {
+        Err(TpcError::Cancelled) => {
+            remove_cancelled_output(ctx, table, output_path, options).await?;
+            return Err(TpcError::Cancelled);
+        }
+        result => result?,
+    };
+    let stats = stats.map(|stats| TableStats {
+        input_bytes,
+        malformed_rows,
+        ..stats
+    });
+
+    match &stats {
+        Some(stats) => {
+            if options.verify_row_counts {
+                verify_row_count(ctx, benchmark, stats, file_vec, &csv_options, options).await?;
+            }
+            if options.verify_schema {
+                verify_output_schema(ctx, benchmark, stats, output_path, options).await?;
+            }
+            if let Some(dialect) = options.write_ddl {
+                write_table_ddl(ctx, benchmark, table, output_path, options, dialect).await?;
+            }
+            if options.delete_source_on_success {
+                delete_source(plan, output_path, options)?;
+            }
+            send_progress(options, ProgressEvent::TableFinished(stats.clone()));
+        }
+        None => send_progress(
+            options,
+            ProgressEvent::TableSkipped {
+                table: table.to_owned(),
+            },
+        ),
+    }
+    Ok(stats)
+}
+
+/// Write a `CREATE TABLE` statement for a converted table to `{output_path}/{table}.sql`,
+/// including the trailing delimiter column when it is kept
+async fn write_table_ddl(
+    ctx: &SessionContext,
+    benchmark: &dyn Tpc,
+    table: &str,
+    output_path: &str,
+    options: &ConversionOptions,
+    dialect: SqlDialect,
+) -> Result<()> {
+    let mut schema = SchemaBuilder::from(options.table_schema(benchmark, table)?.fields());
+    if options.trailing_delimiter && !options.drop_trailing_column {
+        schema.push(Field::new(TRAILING_FIELD, DataType::Utf8, true));
+    }
+    let ddl = table_ddl(table, &schema.finish(), dialect)?;
+    output_sink(ctx, output_path)?
+        .put(&format!("{}.sql", table), ddl.into_bytes())
+        .await
+}
+
+/// Get the total size of a table's input files, which may be local files or URLs
+async fn input_bytes(ctx: &SessionContext, inputs: &[PathBuf]) -> Result<u64> {
+    let mut bytes = 0;
+    for input in inputs {
+        bytes += match remote::parse_input_url(&input.display().to_string())? {
+            Some(url) => remote::object_size(ctx, &url).await?,
+            None => fs::metadata(input)?.len(),
+        };
+    }
+    Ok(bytes)
+}
+
+/// Remove the input files of a table whose output is complete, first checking the output
+/// against its checksums when they were written to local disk
+fn delete_source(plan: &TablePlan, output_path: &str, options: &ConversionOptions) -> Result<()> {
+    if options.write_checksums && remote::parse_url(output_path)?.is_none() {
+        let table_dir =
+            Path::new(output_path).join(format!("{}.{}", plan.table, options.format.extension()));
+        let mut mismatches = vec![];
+        check_table_checksums(&table_dir, &mut mismatches)?;
+        if !mismatches.is_empty() {
+            return Err(TpcError::Conversion(format!(
+                "Not removing the input of {}, checksum verification failed: {}",
+                plan.table,
+                mismatches.join(", ")
+            )));
+        }
+    }
+    info!("Removing {} input files", plan.inputs.len());
+    for input in &plan.inputs {
+        debug!("Removing {}", input.display());
+        fs::remove_file(input)?;
+    }
+    Ok(())
+}
+
+/// Remove the incomplete output of a table whose conversion was cancelled. The table directory
+/// only exists once the conversion has prepared it, so an existing table is never removed.
+async fn remove_cancelled_output(
+    ctx: &SessionContext,
+    table: &str,
+    output_path: &str,
+    options: &ConversionOptions,
+) -> Result<()> {
+    let sink = output_sink(ctx, output_path)?;
+    let extension = options.format.extension();
+    let mut incomplete = vec![format!("{}-temp.{}", table, extension)];
+    if !options.single_file {
+        incomplete.push(format!("{}.{}", table, extension));
+    }
+    for path in incomplete {
+        if sink.exists(&path).await? {
+            info!("Removing incomplete output: {}", sink.describe(&path));
+            sink.remove_all(&path).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Collect the results of a stream of file conversions. When the conversion is cancelled, the
+/// conversions that are already running are finished rather than dropped part way through
+/// writing, and the stream starts no more.
+pub(crate) async fn collect_converted<T>(
+    mut converted: impl Stream<Item = Result<T>> + Unpin,
+) -> Result<Vec<T>> {
+    let mut results = vec![];
+    while let Some(result) = converted.next().await {
+        match result {
+            Ok(result) => results.push(result),
+            Err(TpcError::Cancelled) => {
+                while converted.next().await.is_some() {}
+                return Err(TpcError::Cancelled);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(results)
+}
+
+/// Check that the first output file of a table has the columns of the table's schema in order
+/// and with the same types, ignoring nullability, which can differ after reading, and the
+/// trailing delimiter column
+async fn verify_output_schema(
+    ctx: &SessionContext,
+    benchmark: &dyn Tpc,
+    stats: &TableStats,
+    output_path: &str,
+    options: &ConversionOptions,
+) -> Result<()> {
+    let file = match stats.files.first() {
+        Some(file) => format!("{}/{}", output_path.trim_end_matches('/'), file),
+        None => return Ok(()),
+    };
+    let columns = |schema: &Schema| {
+        schema
+            .fields()
+            .iter()
+            .filter(|field| field.name() != TRAILING_FIELD)
+            .map(|field| (field.name().clone(), field.data_type().clone()))
+            .collect::<Vec<_>>()
+    };
+    let expected = columns(&options.table_schema(benchmark, &stats.table)?);
+    let df = ctx
+        .read_parquet(&file, ParquetReadOptions::default())
+        .await?;
+    let actual = columns(&Schema::from(df.schema()));
+    if actual == expected {
+        return Ok(());
+    }
+
+    let mut differences = vec![];
+    for i in 0..actual.len().max(expected.len()) {
+        match (actual.get(i), expected.get(i)) {
+            (Some(found), Some(column)) if found != column => differences.push(format!(
+                "column {} is {} {:?} instead of {} {:?}",
+                i + 1,
+                found.0,
+                found.1,
+                column.0,
+                column.1
+            )),
+            (Some(found), None) => {
+                differences.push(format!("unexpected column {} {:?}", found.0, found.1))
+            }
+            (None, Some(column)) => {
+                differences.push(format!("missing column {} {:?}", column.0, column.1))
+            }
+            _ => {}
+        }
+    }
+    Err(TpcError::Conversion(format!(
+        "Schema of {} does not match table {}: {}",
+        file,
+        stats.table,
+        differences.join(", ")
+    )))
+}
+
+/// Compare the number of rows written for a table with the number of rows in its input and,
+/// when the scale factor is known, with the row count required by the benchmark
+async fn verify_row_count(
+    ctx: &SessionContext,
+    benchmark: &dyn Tpc,
+    stats: &TableStats,
+    files: &[PathBuf],
+    csv_options: &CsvReadOptions<'_>,
+    options: &ConversionOptions,
+) -> Result<()> {
+    let mut input_rows = 0;
+    for file in files {
+        input_rows += read_tbl(ctx, file, csv_options).await?.count().await? as u64;
+    }
+    if input_rows != stats.rows {
+        return Err(TpcError::Conversion(format!(
+            "Row count mismatch for table {}: read {} rows but wrote {}",
+            stats.table, input_rows, stats.rows
+        )));
+    }
 
-#[async_trait]
-pub trait Tpc {
-    fn generate(
-        &self,
-        scale: usize,
-        partitions: usize,
-        input_path: &str,
-        output_path: &str,
-    ) -> Result<()>;
+    let expected = options
+        .scale_factor
+        .and_then(|scale| benchmark.get_expected_row_count(&stats.table, scale));
+    if let Some(expected) = expected {
+        if expected != stats.rows {
+            return Err(TpcError::Conversion(format!(
+                "Row count mismatch for table {}: expected {} rows but wrote {}",
+                stats.table, expected, stats.rows
+            )));
+        }
+    }
+    debug!("Verified row count of {}", stats.rows);
+    Ok(())
+}
 
-    fn get_table_names(&self) -> Vec<&str>;
+/// Convert a table's input files to a single `{output_path}/{table}.{ext}` file on the local
+/// file system
+async fn write_local_file(
+    ctx: &SessionContext,
+    table: &str,
+    file_vec: &[PathBuf],
+    output_path: &str,
+    csv_options: &CsvReadOptions<'_>,
+    options: &ConversionOptions,
+    start: Instant,
+) -> Result<Option<TableStats>> {
+    let format = options.format;
+    let sink =
+        LocalSink::new(output_path, options.io_retry).with_io_limit(options.io_limit.clone());
+    let file_name = format!("{}.{}", table, format.extension());
+    let dest_path = Path::new(output_path).join(&file_name);
 
-    fn get_table_ext(&self) -> &str;
+    // the file is moved into place once it is complete, so an existing file is never partial
+    if dest_path.exists() {
+        match options.resume_mode {
+            ResumeMode::Fail => {
+                return Err(TpcError::Conversion(format!(
+                    "Output file already exists: {}",
+                    dest_path.display()
+                )));
+            }
+            ResumeMode::Skip => {
+                info!("Skipping table which is already converted");
+                return Ok(None);
+            }
+            ResumeMode::Overwrite => {
+                info!("Removing existing file: {}", dest_path.display());
+                sink.remove_all(&file_name).await?;
+            }
+        }
+    }
+    sink.create_dir("").await?;
+
+    let output_parts_dir = format!("{}/{}-temp.{}", output_path, table, format.extension());
+    if Path::new(&output_parts_dir).exists() {
+        fs::remove_dir_all(&output_parts_dir)?;
+    }
+    debug!("Writing {}", output_parts_dir);
+    let written =
+        convert_repartitioned(ctx, file_vec, &output_parts_dir, csv_options, options, 1).await?;
+    for path in sorted_files(Path::new(&output_parts_dir))? {
+        sink.publish(&path, &file_name).await?;
+    }
+    debug!("Removing {}", output_parts_dir);
+    fs::remove_dir_all(&output_parts_dir)?;
 
-    fn get_schema(&self, table: &str) -> Schema;
+    let rows = written.rows;
+    let bytes = fs::metadata(&dest_path)?.len();
+    send_progress(
+        options,
+        ProgressEvent::FileConverted {
+            table: table.to_owned(),
+            file: file_name.clone(),
+            rows,
+            bytes,
+        },
+    );
+    Ok(Some(TableStats {
+        table: table.to_owned(),
+        rows,
+        bytes,
+        input_bytes: 0,
+        files: vec![file_name],
+        elapsed: start.elapsed(),
+        read_time: written.timings.read,
+        write_time: written.timings.write,
+        malformed_rows: vec![],
+    }))
 }
 
-pub async fn convert_to_parquet(
-    benchmark: &dyn Tpc,
-    input_path: &str,
+/// Convert a table's input files to `{output_path}/{table}.{ext}` on the local file system
+async fn write_local_table(
+    ctx: &SessionContext,
+    table: &str,
+    file_vec: &[PathBuf],
     output_path: &str,
-) -> datafusion::error::Result<()> {
-    for table in benchmark.get_table_names() {
-        println!("Converting table {}", table);
-
-        let mut schema_builder = SchemaBuilder::from(benchmark.get_schema(table).fields);
-        schema_builder.push(Field::new("__placeholder", DataType::Utf8, true));
-        let schema = schema_builder.finish();
-
-        let file_ext = format!(".{}", benchmark.get_table_ext());
-        let options = CsvReadOptions::new()
-            .schema(&schema)
-            .has_header(false)
-            .delimiter(b'|')
-            .file_extension(&file_ext);
-
-        let path = format!("{}/{}.{}", input_path, table, benchmark.get_table_ext());
-        let path = Path::new(&path);
-        if !path.exists() {
-            panic!("path does not exist: {:?}", path);
-        }
-
-        // create output dir
-        let output_dir_name = format!("{}/{}.parquet", output_path, table);
-        let output_dir = Path::new(&output_dir_name);
-        if output_dir.exists() {
-            panic!("output dir already exists: {}", output_dir.display());
-        }
-        println!("Creating directory: {}", output_dir.display());
-        fs::create_dir(&output_dir)?;
-
-        let x = PathBuf::from(path);
-        let mut file_vec = vec![];
-        if x.is_dir() {
-            let files = fs::read_dir(path)?;
-            for file in files {
-                let file = file?;
-                file_vec.push(file);
-            }
-        }
-
-        let mut part = 0;
-        for file in &file_vec {
-            let stub = file.file_name().to_str().unwrap().to_owned();
-            let stub = &stub[0..stub.len() - 4]; // remove .dat or .tbl
-                                                 // write to temp dir that will contain nested dirs
-                                                 // example: /tmp/nation-temp.parquet/part-1.parquet/part-0.parquet
-            let output_parts_dir = format!("{}/{}-temp.parquet", output_dir.display(), stub);
-            println!("Writing {}", output_parts_dir);
-            let options = options.clone();
-            // async move {
-            convert_tbl(
-                &file.path(),
-                &output_parts_dir,
-                &options,
-                "parquet",
-                "snappy",
-                8192,
+    csv_options: &CsvReadOptions<'_>,
+    options: &ConversionOptions,
+    start: Instant,
+) -> Result<Option<TableStats>> {
+    let format = options.format;
+
+    // create output dir
+    let sink =
+        LocalSink::new(output_path, options.io_retry).with_io_limit(options.io_limit.clone());
+    let table_dir = format!("{}.{}", table, format.extension());
+    if !prepare_table_dir(&sink, &table_dir, options.resume_mode).await? {
+        return Ok(None);
+    }
+    let output_dir_name = format!("{}/{}", output_path, table_dir);
+    let output_dir = Path::new(&output_dir_name);
+
+    // convert files concurrently but move the results into place in input order, so that the
+    // part numbering doesn't depend on which conversion finishes first. Each temp dir is moved
+    // to a partition directory relative to the output dir, which is empty unless partitioning.
+    let target_partitions = options.table_target_partitions(table);
+    let mut written = Written::default();
+    let partitioning = options
+        .partition_by
+        .as_ref()
+        .and_then(|partitioning| Some((partitioning, partitioning.column(table)?)));
+    let mut converted = match (partitioning, target_partitions) {
+        (Some((partitioning, column)), _) => {
+            // the input is read once, and each partition is written to its own temp dir
+            let (partitions, partitions_written) = write_partitions(
+                ctx,
+                file_vec,
+                output_dir,
+                csv_options,
+                options,
+                partitioning,
+                column,
+                target_partitions.unwrap_or(1),
             )
             .await?;
-            // }
+            info!("Wrote {} partitions", partitions.len());
+            written += partitions_written;
+            stream::iter(partitions)
+                .map(|partition| {
+                    Ok::<_, TpcError>((partition.dir, partition.temp_dir, Written::default()))
+                })
+                .boxed()
+        }
+        (None, Some(partitions)) => {
+            // all of the input is written to one temp dir with a file per partition
+            let output_parts_dir = format!(
+                "{}/{}-temp.{}",
+                output_dir.display(),
+                table,
+                format.extension()
+            );
+            stream::once(async move {
+                options.check_cancelled()?;
+                debug!("Writing {}", output_parts_dir);
+                let timings = convert_repartitioned(
+                    ctx,
+                    file_vec,
+                    &output_parts_dir,
+                    csv_options,
+                    options,
+                    partitions,
+                )
+                .await?;
+                Ok::<_, TpcError>((String::new(), output_parts_dir, timings))
+            })
+            .boxed()
+        }
+        (None, None) => stream::iter(file_vec)
+            .map(|file| {
+                let stub = file_stub(file);
+                // write to temp dir that will contain nested dirs
+                // example: /tmp/nation-temp.parquet/part-1.parquet/part-0.parquet
+                let output_parts_dir = format!(
+                    "{}/{}-temp.{}",
+                    output_dir.display(),
+                    stub,
+                    format.extension()
+                );
+                let csv_options = csv_options.clone();
+                async move {
+                    options.check_cancelled()?;
+                    debug!("Writing {}", output_parts_dir);
+                    let timings =
+                        convert_file(ctx, file, &output_parts_dir, &csv_options, options).await?;
+                    Ok::<_, TpcError>((String::new(), output_parts_dir, timings))
+                }
+            })
+            .buffered(options.file_concurrency)
+            .boxed(),
+    };
+
+    let mut files = vec![];
+    let mut checksums = vec![];
+    let mut bytes = 0;
+    // parts are numbered from zero within each partition directory
+    let mut part_counts: HashMap<String, usize> = HashMap::new();
+    while let Some(result) = converted.next().await {
+        let (partition_dir, output_parts_dir, file_written) = match result {
+            Ok(converted) => converted,
+            Err(TpcError::Cancelled) => {
+                // let the conversions that are running finish before their output is removed
+                while converted.next().await.is_some() {}
+                return Err(TpcError::Cancelled);
+            }
+            Err(e) => return Err(e),
+        };
+        written += file_written;
+        sink.create_dir(&format!("{}/{}", table_dir, partition_dir))
+            .await?;
+        // the files DataFusion wrote are moved in order of their part numbers
+        for path in sorted_files(Path::new(&output_parts_dir))? {
+            let part = part_counts.entry(partition_dir.clone()).or_default();
+            let file_name = Path::new(&partition_dir)
+                .join(format!("part-{}.{}", part, format.extension()))
+                .display()
+                .to_string();
+            *part += 1;
+            let output_file = format!("{}/{}", table_dir, file_name);
+            sink.publish(&path, &output_file).await?;
+            let dest_path = &output_dir.join(&file_name);
+            if options.write_checksums {
+                checksums.push((file_name, sha256_file(dest_path)?));
+            }
+            let file_rows = written.file_rows.get(&path).copied().ok_or_else(|| {
+                TpcError::Conversion(format!("No row count for {}", path.display()))
+            })?;
+            let file_bytes = fs::metadata(dest_path)?.len();
+            send_progress(
+                options,
+                ProgressEvent::FileConverted {
+                    table: table.to_owned(),
+                    file: output_file.clone(),
+                    rows: file_rows,
+                    bytes: file_bytes,
+                },
+            );
+            files.push(output_file);
+            bytes += file_bytes;
+        }
+        debug!("Removing {}", output_parts_dir);
+        fs::remove_dir_all(Path::new(&output_parts_dir))?;
+    }
+
+    let checksums = options.write_checksums.then_some(checksums.as_slice());
+    finish_table_dir(&sink, &table_dir, checksums, options.write_success_marker).await?;
+
+    Ok(Some(TableStats {
+        table: table.to_owned(),
+        rows: written.rows,
+        bytes,
+        input_bytes: 0,
+        files,
+        elapsed: start.elapsed(),
+        read_time: written.timings.read,
+        write_time: written.timings.write,
+        malformed_rows: vec![],
+    }))
+}
 
-            let paths = fs::read_dir(&output_parts_dir)?;
-            for path in paths {
-                let path = path?;
-                let dest_file = format!("{}/part-{}.parquet", output_dir.display(), part);
-                part += 1;
-                let dest_path = Path::new(&dest_file);
-                move_or_copy(&path.path(), &dest_path)?;
+/// Register every converted table under `path` with the context, using the table name, so
+/// that the tables can be queried with SQL
+pub async fn register_tables(
+    ctx: &SessionContext,
+    benchmark: &dyn Tpc,
+    path: &str,
+    format: OutputFormat,
+) -> Result<()> {
+    let url = remote::parse_url(path)?;
+    if let Some(url) = &url {
+        remote::register_object_store(ctx, url)?;
+    }
+    for (table, schema) in benchmark.schemas()? {
+        // either a directory of parts or a single file
+        let file_name = format!("{}.{}", table, format.extension());
+        let mut table_path = format!("{}/{}", path.trim_end_matches('/'), file_name);
+        // local directories are detected automatically, but URL directories need a trailing slash
+        if let Some(url) = &url {
+            if remote::is_directory(url, &file_name).await? {
+                table_path.push('/');
+            }
+        }
+        match format {
+            OutputFormat::Csv => {
+                // converted CSV files have a header and no trailing placeholder column
+                let options = CsvReadOptions::new().schema(&schema).has_header(true);
+                ctx.register_csv(table, &table_path, options).await?
+            }
+            OutputFormat::Json => {
+                let options = NdJsonReadOptions::default().schema(&schema);
+                ctx.register_json(table, &table_path, options).await?
+            }
+            OutputFormat::Parquet => {
+                ctx.register_parquet(table, &table_path, ParquetReadOptions::default())
+                    .await?
+            }
+            OutputFormat::ArrowIpc => {
+                if url.is_some() {
+                    return Err(TpcError::InvalidOption(
+                        "Arrow IPC tables can only be registered from local paths".to_string(),
+                    ));
+                }
+                let provider = read_ipc_table(Path::new(&table_path))?;
+                ctx.register_table(table, Arc::new(provider))?;
             }
-            println!("Removing {}", output_parts_dir);
-            fs::remove_dir_all(Path::new(&output_parts_dir))?;
         }
     }
+    Ok(())
+}
 
+/// Write each table's Arrow schema to `{out_dir}/{table}.schema.json`
+pub fn write_schemas_json(benchmark: &dyn Tpc, out_dir: &str) -> Result<()> {
+    fs::create_dir_all(out_dir)?;
+    for (table, schema) in benchmark.schemas()? {
+        let json = serde_json::to_string_pretty(&schema)?;
+        fs::write(format!("{}/{}.schema.json", out_dir, table), json + "\n")?;
+    }
     Ok(())
 }
 
+/// Get the name of an input file without its extension and any `.gz` suffix. Numbered files
+/// such as `lineitem.tbl.1` keep their number (`lineitem-1`) so that stubs stay unique.
+fn file_stub(path: &Path) -> String {
+    let mut name = PathBuf::from(path.file_name().unwrap_or_default());
+    if name.extension() == Some(OsStr::new("gz")) {
+        name = PathBuf::from(name.file_stem().unwrap_or_default());
+    }
+    let part = name
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .filter(|ext| ext.chars().all(|c| c.is_ascii_digit()))
+        .map(|ext| ext.to_owned());
+    if part.is_some() {
+        name = PathBuf::from(name.file_stem().unwrap_or_default());
+    }
+    let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+    match part {
+        Some(part) => format!("{}-{}", stem, part),
+        None => stem.into_owned(),
+    }
+}
+
+/// Find a table's input files, failing if there are none. Directories can't be listed over
+/// HTTP, so with an HTTP or HTTPS input URL the input is the single file
+/// `{input_path}/{table}.{ext}`.
+fn table_inputs(benchmark: &dyn Tpc, table: &str, input_path: &str) -> Result<Vec<PathBuf>> {
+    if remote::parse_input_url(input_path)?.is_some() {
+        return Ok(vec![PathBuf::from(format!(
+            "{}/{}.{}",
+            input_path.trim_end_matches('/'),
+            table,
+            benchmark.get_table_ext()
+        ))]);
+    }
+    let path = table_input_path(benchmark, input_path, table);
+    let inputs = table_input_files(&path, benchmark.get_table_ext())?;
+    if !path.exists() && inputs.is_empty() {
+        return Err(TpcError::MissingPath {
+            description: format!("Input for table {}", table),
+            path,
+        });
+    }
+    Ok(inputs)
+}
+
+/// Get the input for a table, which is either a directory of files or a single file that may
+/// be gzip compressed
+pub(crate) fn table_input_path(benchmark: &dyn Tpc, input_path: &str, table: &str) -> PathBuf {
+    let path = PathBuf::from(format!(
+        "{}/{}.{}",
+        input_path,
+        table,
+        benchmark.get_table_ext()
+    ));
+    let gz_path = PathBuf::from(format!("{}.gz", path.display()));
+    // some generators write each table to a directory named after the table alone
+    let table_dir = Path::new(input_path).join(table);
+    if !path.exists() && gz_path.exists() {
+        gz_path
+    } else if !path.exists() && table_dir.is_dir() {
+        table_dir
+    } else {
+        path
+    }
+}
+
+/// List the files in a table's input, which is either a directory, a single file, or
+/// numbered files next to where the single file would be. The files in a directory and its
+/// subdirectories are sorted by name, with numbers in names compared by value. A directory
+/// named after the table alone may hold other files, so only the files in it with the table
+/// extension `ext` are input.
+pub(crate) fn table_input_files(path: &Path, ext: &str) -> std::io::Result<Vec<PathBuf>> {
+    if path.is_dir() {
+        let ext = match path.extension() {
+            Some(_) => None,
+            None => Some(ext),
+        };
+        nested_files(path, ext)
+    } else if path.exists() {
+        Ok(vec![path.to_path_buf()])
+    } else {
+        numbered_files(path)
+    }
+}
+
+/// List the files under a directory in sorted order, listing each subdirectory's files in the
+/// place of the subdirectory, and keeping only files with an extension such as `tbl` or
+/// `tbl.gz` if one is given
+fn nested_files(dir: &Path, ext: Option<&str>) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for path in sorted_files(dir)? {
+        if path.is_dir() {
+            files.extend(nested_files(&path, ext)?);
+        } else if ext.is_none_or(|ext| has_extension(&path, ext)) {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Check whether a file has an extension, optionally followed by `.gz`
+fn has_extension(path: &Path, ext: &str) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let name = name.strip_suffix(".gz").unwrap_or(&name);
+    name.strip_suffix(ext)
+        .is_some_and(|stem| stem.ends_with('.'))
+}
+
+/// List the files in a directory, sorted by name with numbers in names compared by value, so
+/// that the order does not depend on the file system
+fn sorted_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for file in fs::read_dir(dir)? {
+        files.push(file?.path());
+    }
+    files.sort_by_cached_key(|file| name_sort_key(file));
+    Ok(files)
+}
+
+/// Part of a file name, used to sort numbered files such as `part-2.tbl` before `part-10.tbl`
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum NameChunk {
+    /// Digits without leading zeros, compared by length first so that they sort by value
+    Number(usize, String),
+    Text(String),
+}
+
+fn name_sort_key(path: &Path) -> (Vec<NameChunk>, PathBuf) {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut chunks: Vec<NameChunk> = vec![];
+    for c in name.chars() {
+        match (c.is_ascii_digit(), chunks.last_mut()) {
+            (true, Some(NameChunk::Number(_, digits))) => digits.push(c),
+            (false, Some(NameChunk::Text(text))) => text.push(c),
+            (true, _) => chunks.push(NameChunk::Number(0, c.to_string())),
+            (false, _) => chunks.push(NameChunk::Text(c.to_string())),
+        }
+    }
+    for chunk in &mut chunks {
+        if let NameChunk::Number(len, digits) = chunk {
+            *digits = digits.trim_start_matches('0').to_string();
+            *len = digits.len();
+        }
+    }
+    // the full path breaks ties between names such as `part-1` and `part-01`
+    (chunks, path.to_path_buf())
+}
+
+/// Find the numbered files written by dbgen when generating partitions, such as
+/// `lineitem.tbl.1` and `lineitem.tbl.2` for `lineitem.tbl`, sorted by number. The files may
+/// be gzip compressed.
+fn numbered_files(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let name = match path.file_name() {
+        Some(name) => format!("{}.", name.to_string_lossy()),
+        None => return Ok(vec![]),
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut numbered = vec![];
+    for file in fs::read_dir(dir)? {
+        let file_name = file?.file_name().to_string_lossy().into_owned();
+        let number = file_name
+            .strip_prefix(&name)
+            .map(|suffix| suffix.strip_suffix(".gz").unwrap_or(suffix))
+            .and_then(|number| number.parse::<u64>().ok());
+        if let Some(number) = number {
+            numbered.push((number, path.with_file_name(&file_name)));
+        }
+    }
+    numbered.sort();
+    Ok(numbered.into_iter().map(|(_, file)| file).collect())
+}
+
+/// Move a file, copying it when the destination is on another device, retrying transient
+/// failures according to the policy
 pub(crate) fn move_or_copy(
     source_path: &Path,
     dest_path: &Path,
+    retry: Option<&RetryPolicy>,
+) -> std::result::Result<(), std::io::Error> {
+    with_retry(
+        retry,
+        || {
+            format!(
+                "Moving {} to {}",
+                source_path.display(),
+                dest_path.display()
+            )
+        },
+        || move_or_copy_once(source_path, dest_path),
+    )
+}
+
+fn move_or_copy_once(
+    source_path: &Path,
+    dest_path: &Path,
 ) -> std::result::Result<(), std::io::Error> {
-    if is_same_device(&source_path, &dest_path)? {
-        println!(
+    if is_same_device(source_path, dest_path)? {
+        debug!(
             "Moving {} to {}",
             source_path.display(),
             dest_path.display()
         );
-        fs::rename(&source_path, &dest_path)
+        fs::rename(source_path, dest_path)
     } else {
-        println!(
+        debug!(
             "Copying {} to {}",
             source_path.display(),
             dest_path.display()
         );
-        fs::copy(&source_path, &dest_path)?;
-        fs::remove_file(&source_path)
+        copy_into_place(source_path, dest_path)?;
+        fs::remove_file(source_path)
+    }
+}
+
+/// Copy a file to a temp file next to the destination and rename it into place, so that the
+/// destination only appears once it is complete. The temp file is removed if either step
+/// fails.
+fn copy_into_place(
+    source_path: &Path,
+    dest_path: &Path,
+) -> std::result::Result<(), std::io::Error> {
+    let file_name = dest_path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = dest_path.with_file_name(format!("{}.tmp", file_name));
+    let result = fs::copy(source_path, &temp_path).and_then(|_| fs::rename(&temp_path, dest_path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
     }
+    result
+}
+
+/// Get the directory a destination path will be created in, or `None` if it has no parent
+#[cfg(any(unix, windows))]
+fn dest_dir(path: &Path) -> Option<&Path> {
+    path.parent().map(|dir| {
+        if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        }
+    })
 }
 
 #[cfg(unix)]
 fn is_same_device(path1: &Path, path2: &Path) -> std::result::Result<bool, std::io::Error> {
     use std::os::unix::fs::MetadataExt;
+    // the device is unknown without a parent, so fall back to copying
+    let dir2 = match dest_dir(path2) {
+        Some(dir2) => dir2,
+        None => return Ok(false),
+    };
     let meta1 = fs::metadata(path1)?;
-    let meta2 = fs::metadata(path2.parent().unwrap())?;
+    let meta2 = fs::metadata(dir2)?;
     Ok(meta1.dev() == meta2.dev())
 }
 
 #[cfg(windows)]
 fn is_same_device(path1: &Path, path2: &Path) -> std::result::Result<bool, std::io::Error> {
     use std::os::windows::fs::MetadataExt;
+    // the volume is unknown without a parent, so fall back to copying
+    let dir2 = match dest_dir(path2) {
+        Some(dir2) => dir2,
+        None => return Ok(false),
+    };
     let meta1 = fs::metadata(path1)?;
-    let meta2 = fs::metadata(path2.parent().unwrap())?;
+    let meta2 = fs::metadata(dir2)?;
     Ok(meta1.volume_serial_number() == meta2.volume_serial_number())
 }
 
+#[cfg(not(any(unix, windows)))]
+fn is_same_device(_path1: &Path, _path2: &Path) -> std::result::Result<bool, std::io::Error> {
+    // there is no portable way to find the device, and copying always works
+    Ok(false)
+}
+
+/// Convert a single input file to a directory of files in the output format, returning the
+/// number of rows written
 pub async fn convert_tbl(
+    ctx: &SessionContext,
+    input_path: &Path,
+    output_filename: &str,
+    csv_options: &CsvReadOptions<'_>,
+    options: &ConversionOptions,
+) -> Result<u64> {
+    let written = convert_file(ctx, input_path, output_filename, csv_options, options).await?;
+    Ok(written.rows)
+}
+
+/// Convert a single input file, returning the number of rows written and the time spent
+/// reading and writing
+async fn convert_file(
+    ctx: &SessionContext,
     input_path: &Path,
     output_filename: &str,
-    options: &CsvReadOptions<'_>,
-    file_format: &str,
-    compression: &str,
-    batch_size: usize,
-) -> datafusion::error::Result<()> {
-    println!(
+    csv_options: &CsvReadOptions<'_>,
+    options: &ConversionOptions,
+) -> Result<Written> {
+    info!(
         "Converting '{}' to {}",
         input_path.display(),
         output_filename
@@ -177,57 +2517,651 @@ pub async fn convert_tbl(
 
     let start = Instant::now();
 
-    let config = SessionConfig::new().with_batch_size(batch_size);
-    let ctx = SessionContext::with_config(config);
+    let df = sort_rows(read_tbl(ctx, input_path, csv_options).await?, options)?;
+    let written = write_dataframe(df, output_filename, options).await?;
+
+    info!(
+        "Converted '{}' in {} ms",
+        input_path.display(),
+        start.elapsed().as_millis()
+    );
+
+    Ok(written)
+}
+
+/// Convert a table's input files to a directory containing `partitions` files
+async fn convert_repartitioned(
+    ctx: &SessionContext,
+    files: &[PathBuf],
+    output_filename: &str,
+    csv_options: &CsvReadOptions<'_>,
+    options: &ConversionOptions,
+    partitions: usize,
+) -> Result<Written> {
+    let start = Instant::now();
+
+    let df = sort_rows(read_tbl_files(ctx, files, csv_options).await?, options)?
+        .repartition(Partitioning::RoundRobinBatch(partitions))?;
+    let written = write_dataframe(df, output_filename, options).await?;
+
+    info!(
+        "Converted {} files into {} partitions in {} ms",
+        files.len(),
+        partitions,
+        start.elapsed().as_millis()
+    );
+
+    Ok(written)
+}
+
+/// Build a plan to read all of a table's input files
+pub(crate) async fn read_tbl_files(
+    ctx: &SessionContext,
+    files: &[PathBuf],
+    csv_options: &CsvReadOptions<'_>,
+) -> Result<DataFrame> {
+    let mut df: Option<DataFrame> = None;
+    for file in files {
+        let file_df = read_tbl(ctx, file, csv_options).await?;
+        df = Some(match df {
+            Some(df) => df.union(file_df)?,
+            None => file_df,
+        });
+    }
+    df.ok_or_else(|| TpcError::Conversion("No input files to read".to_string()))
+}
+
+/// Name of the empty field read after a trailing delimiter
+const TRAILING_FIELD: &str = "__trailing_delimiter";
+
+/// Metadata key of a file schema field holding the column's type as JSON, for a column that is
+/// read as a string and converted to its type after reading
+const DATA_TYPE_KEY: &str = "tpctools.data_type";
+
+/// Metadata key of a file schema field holding the value to read as null
+const NULL_VALUE_KEY: &str = "tpctools.null_value";
+
+/// Metadata key of a file schema field holding the format to parse a date or timestamp with
+const FORMAT_KEY: &str = "tpctools.format";
+
+/// Metadata key of a file schema field that is dropped after reading
+const DROP_KEY: &str = "tpctools.drop";
+
+/// Get the schema of the fields in each line of a TBL file. A trailing delimiter is read as an
+/// extra empty field after the table's columns, which is dropped after reading unless
+/// `drop_trailing_column` is `false`. Some columns are read as strings and converted to their
+/// types after reading. When a null value is set, every nullable column is read this way, and
+/// the null value is replaced with null before converting. When a date or timestamp format is
+/// set, the date or timestamp columns are read this way and parsed with the format.
+pub fn tbl_file_schema(schema: &Schema, options: &ConversionOptions) -> Result<Schema> {
+    let mut schema_builder = SchemaBuilder::new();
+    for field in schema.fields() {
+        let null_value = options.null_value.as_ref().filter(|_| field.is_nullable());
+        let format = match field.data_type() {
+            DataType::Date32 | DataType::Date64 => options.date_format.as_ref(),
+            DataType::Timestamp(_, _) => options.timestamp_format.as_ref(),
+            _ => None,
+        };
+        if null_value.is_none() && format.is_none() {
+            schema_builder.push(field.as_ref().clone());
+            continue;
+        }
+        let mut metadata = HashMap::from([(
+            DATA_TYPE_KEY.to_string(),
+            serde_json::to_string(field.data_type())?,
+        )]);
+        if let Some(null_value) = null_value {
+            metadata.insert(NULL_VALUE_KEY.to_string(), null_value.clone());
+        }
+        if let Some(format) = format {
+            metadata.insert(FORMAT_KEY.to_string(), format.clone());
+        }
+        schema_builder.push(
+            Field::new(field.name(), DataType::Utf8, field.is_nullable()).with_metadata(metadata),
+        );
+    }
+    if options.trailing_delimiter {
+        let mut field = Field::new(TRAILING_FIELD, DataType::Utf8, true);
+        if options.drop_trailing_column {
+            field = field.with_metadata(HashMap::from([(DROP_KEY.to_string(), String::new())]));
+        }
+        schema_builder.push(field);
+    }
+    Ok(schema_builder.finish())
+}
+
+/// Sort a table's rows by the sort columns, if there are any. Batches of the sorted rows are
+/// distributed to partitions in order, so each output file is sorted even when repartitioned.
+pub(crate) fn sort_rows(df: DataFrame, options: &ConversionOptions) -> Result<DataFrame> {
+    Ok(match &options.sort_columns {
+        Some(columns) => df.sort(
+            columns
+                .iter()
+                .map(|column| col(column.as_str()).sort(true, false))
+                .collect(),
+        )?,
+        None => df,
+    })
+}
+
+/// Options to read a benchmark's raw data files, which have no header, with a schema from
+/// [`tbl_file_schema`]
+pub fn csv_read_options<'a>(
+    benchmark: &'a dyn Tpc,
+    file_schema: &'a Schema,
+    delimiter: u8,
+) -> CsvReadOptions<'a> {
+    CsvReadOptions::new()
+        .schema(file_schema)
+        .has_header(false)
+        .delimiter(delimiter)
+        .file_extension(benchmark.get_table_ext())
+}
 
-    // build plan to read the TBL file
+/// Build a plan to read a TBL file, selecting the table's columns
+pub(crate) async fn read_tbl(
+    ctx: &SessionContext,
+    input_path: &Path,
+    csv_options: &CsvReadOptions<'_>,
+) -> Result<DataFrame> {
     let csv_filename = format!("{}", input_path.display());
-    let mut df = ctx.read_csv(&csv_filename, options.clone()).await?;
+    // the path is a single file, so read it whatever its extension is
+    let mut csv_options = csv_options.clone().file_extension("");
+    if input_path.extension() == Some(OsStr::new("gz")) {
+        csv_options = csv_options.file_compression_type(FileCompressionType::GZIP);
+    }
+    let file_schema = csv_options.schema;
+    let df = ctx.read_csv(&csv_filename, csv_options).await?;
 
-    let schema = df.schema();
-    // Select all apart from the padding column
-    let selection = df
-        .schema()
-        .fields()
-        .iter()
-        .take(schema.fields().len() - 1)
-        .map(|d| Expr::Column(d.qualified_column()))
-        .collect();
-
-    df = df.select(selection)?;
-
-    match file_format {
-        "csv" => df.write_csv(&output_filename).await?,
-        "parquet" => {
-            let compression = match compression {
-                "none" => Compression::UNCOMPRESSED,
-                "snappy" => Compression::SNAPPY,
-                // "brotli" => Compression::BROTLI,
-                // "gzip" => Compression::GZIP,
-                "lz4" => Compression::LZ4,
-                "lz0" => Compression::LZO,
-                // "zstd" => Compression::ZSTD,
-                other => {
-                    return Err(DataFusionError::NotImplemented(format!(
-                        "Invalid compression format: {}",
-                        other
-                    )))
-                }
-            };
-            let props = WriterProperties::builder()
-                .set_compression(compression)
-                .build();
+    let mut selection = vec![];
+    for field in df.schema().fields() {
+        let column = Expr::Column(field.qualified_column());
+        let metadata = file_schema
+            .and_then(|schema| schema.field_with_name(field.name()).ok())
+            .map(|file_field| file_field.metadata());
+        if metadata.is_some_and(|metadata| metadata.contains_key(DROP_KEY)) {
+            continue;
+        }
+        let data_type = match metadata.and_then(|metadata| metadata.get(DATA_TYPE_KEY)) {
+            Some(data_type) => serde_json::from_str::<DataType>(data_type)?,
+            None => {
+                selection.push(column);
+                continue;
+            }
+        };
+        let null_value = metadata.and_then(|metadata| metadata.get(NULL_VALUE_KEY));
+        let mut value = column;
+        if let Some(null_value) = null_value {
+            value = nullif(value, lit(null_value.as_str()));
+        }
+        // empty fields of other types are null, as they are when read with their type
+        if data_type != DataType::Utf8 && null_value.is_none_or(|value| !value.is_empty()) {
+            value = nullif(value, lit(""));
+        }
+        value = match (metadata.and_then(|m| m.get(FORMAT_KEY)), &data_type) {
+            (Some(format), DataType::Date32 | DataType::Date64) => parse_date(value, format),
+            (Some(format), DataType::Timestamp(_, _)) => parse_timestamp(value, format),
+            _ => value,
+        };
+        selection.push(cast(value, data_type).alias(field.name()));
+    }
+
+    Ok(df.select(selection)?)
+}
 
-            df.write_parquet(&output_filename, Some(props)).await?
+/// File format to write converted tables in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Csv,
+    /// Newline-delimited JSON, with one object per row
+    Json,
+    Parquet,
+    /// Arrow IPC files, also known as Feather files
+    ArrowIpc,
+}
+
+impl OutputFormat {
+    /// File extension used for output files and directories in this format
+    pub fn extension(&self) -> &str {
+        match self {
+            Self::Csv => "csv",
+            Self::Json => "json",
+            Self::Parquet => "parquet",
+            Self::ArrowIpc => "arrow",
         }
-        other => {
-            return Err(DataFusionError::NotImplemented(format!(
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = TpcError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "parquet" => Ok(Self::Parquet),
+            "arrow" | "ipc" => Ok(Self::ArrowIpc),
+            other => Err(TpcError::InvalidOption(format!(
                 "Invalid output format: {}",
                 other
-            )))
+            ))),
         }
     }
-    println!("Conversion completed in {} ms", start.elapsed().as_millis());
+}
 
-    Ok(())
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+/// Compression codec to use when writing Parquet files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionCodec {
+    None,
+    Snappy,
+    Lz4,
+    Lzo,
+    Zstd(i32),
+    Gzip(u32),
+    Brotli(u32),
+}
+
+impl CompressionCodec {
+    /// Get the equivalent Parquet compression, validating the compression level
+    pub fn to_parquet_compression(&self) -> Result<Compression> {
+        let compression = match self {
+            Self::None => Compression::UNCOMPRESSED,
+            Self::Snappy => Compression::SNAPPY,
+            Self::Lz4 => Compression::LZ4,
+            Self::Lzo => Compression::LZO,
+            Self::Zstd(level) => {
+                check_compression_level("zstd", *level as i64, ZSTD_LEVELS)?;
+                Compression::ZSTD(ZstdLevel::try_new(*level)?)
+            }
+            Self::Gzip(level) => {
+                check_compression_level("gzip", *level as i64, GZIP_LEVELS)?;
+                Compression::GZIP(GzipLevel::try_new(*level)?)
+            }
+            Self::Brotli(level) => {
+                check_compression_level("brotli", *level as i64, BROTLI_LEVELS)?;
+                Compression::BROTLI(BrotliLevel::try_new(*level)?)
+            }
+        };
+        Ok(compression)
+    }
+}
+
+impl FromStr for CompressionCodec {
+    type Err = TpcError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        // codecs that support it accept an optional level, e.g. "zstd:9"
+        let (name, level) = match s.split_once(':') {
+            Some((name, level)) => (name, Some(level)),
+            None => (s, None),
+        };
+        let codec = match (name, level) {
+            ("none", None) => Self::None,
+            ("snappy", None) => Self::Snappy,
+            ("lz4", None) => Self::Lz4,
+            ("lzo", None) | ("lz0", None) => Self::Lzo,
+            ("zstd", _) => Self::Zstd(parse_level(
+                name,
+                level,
+                ZSTD_LEVELS,
+                ZstdLevel::default().compression_level(),
+            )?),
+            ("gzip", _) => Self::Gzip(parse_level(
+                name,
+                level,
+                GZIP_LEVELS,
+                GzipLevel::default().compression_level(),
+            )?),
+            ("brotli", _) => Self::Brotli(parse_level(
+                name,
+                level,
+                BROTLI_LEVELS,
+                BrotliLevel::default().compression_level(),
+            )?),
+            _ => {
+                return Err(TpcError::InvalidOption(format!(
+                    "Invalid compression format: {}",
+                    s
+                )))
+            }
+        };
+        // validate the level now rather than when the first file is written
+        codec.to_parquet_compression()?;
+        Ok(codec)
+    }
+}
+
+impl fmt::Display for CompressionCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Snappy => write!(f, "snappy"),
+            Self::Lz4 => write!(f, "lz4"),
+            Self::Lzo => write!(f, "lzo"),
+            Self::Zstd(level) => write!(f, "zstd:{}", level),
+            Self::Gzip(level) => write!(f, "gzip:{}", level),
+            Self::Brotli(level) => write!(f, "brotli:{}", level),
+        }
+    }
+}
+
+/// Parse a column's compression codec, such as `l_comment=zstd:3`
+pub fn parse_column_compression(s: &str) -> Result<(String, CompressionCodec)> {
+    match s.split_once('=') {
+        Some((column, codec)) if !column.is_empty() => Ok((column.to_string(), codec.parse()?)),
+        _ => Err(TpcError::InvalidOption(format!(
+            "Invalid column compression: {} (expected column=codec)",
+            s
+        ))),
+    }
+}
+
+/// Parse the number of files to write for a table, such as `lineitem=64`
+pub fn parse_table_partitions(s: &str) -> Result<(String, usize)> {
+    match s.split_once('=') {
+        Some((table, partitions)) if !table.is_empty() => match partitions.parse() {
+            Ok(partitions) => Ok((table.to_string(), partitions)),
+            Err(_) => Err(TpcError::InvalidOption(format!(
+                "Invalid number of partitions for table {}: {}",
+                table, partitions
+            ))),
+        },
+        _ => Err(TpcError::InvalidOption(format!(
+            "Invalid table partitions: {} (expected table=partitions)",
+            s
+        ))),
+    }
+}
+
+/// Parse the level of Parquet column statistics to write (none, chunk, or page)
+pub fn parse_statistics(s: &str) -> Result<EnabledStatistics> {
+    match s {
+        "none" => Ok(EnabledStatistics::None),
+        "chunk" => Ok(EnabledStatistics::Chunk),
+        "page" => Ok(EnabledStatistics::Page),
+        other => Err(TpcError::InvalidOption(format!(
+            "Invalid statistics level: {}",
+            other
+        ))),
+    }
+}
+
+/// Parse the version of the Parquet format to write (1.0 or 2.0)
+pub fn parse_writer_version(s: &str) -> Result<WriterVersion> {
+    match s {
+        "1.0" => Ok(WriterVersion::PARQUET_1_0),
+        "2.0" => Ok(WriterVersion::PARQUET_2_0),
+        other => Err(TpcError::InvalidOption(format!(
+            "Invalid Parquet writer version: {}",
+            other
+        ))),
+    }
+}
+
+/// Parse a single-byte field delimiter, accepting `\t` or `tab` for tab-separated input
+pub fn parse_delimiter(s: &str) -> Result<u8> {
+    match s {
+        "\\t" | "tab" => Ok(b'\t'),
+        s if s.len() == 1 => Ok(s.as_bytes()[0]),
+        other => Err(TpcError::InvalidOption(format!(
+            "Delimiter must be a single byte: {}",
+            other
+        ))),
+    }
+}
+
+/// Compression levels accepted by gzip
+const GZIP_LEVELS: RangeInclusive<i64> = 0..=9;
+/// Compression levels accepted by zstd
+const ZSTD_LEVELS: RangeInclusive<i64> = 1..=22;
+/// Compression levels accepted by brotli
+const BROTLI_LEVELS: RangeInclusive<i64> = 0..=11;
+
+/// Parse an optional compression level, checking that it is in the codec's range
+fn parse_level<T: TryFrom<i64>>(
+    codec: &str,
+    level: Option<&str>,
+    allowed: RangeInclusive<i64>,
+    default: T,
+) -> Result<T> {
+    let level = match level {
+        Some(level) => level.parse::<i64>().map_err(|_| {
+            TpcError::InvalidOption(format!("Invalid {} compression level: {}", codec, level))
+        })?,
+        None => return Ok(default),
+    };
+    check_compression_level(codec, level, allowed.clone())?;
+    T::try_from(level).map_err(|_| TpcError::InvalidCompressionLevel {
+        codec: codec.to_string(),
+        level,
+        allowed,
+    })
+}
+
+/// Check that a compression level is in the range that the codec accepts
+fn check_compression_level(codec: &str, level: i64, allowed: RangeInclusive<i64>) -> Result<()> {
+    if allowed.contains(&level) {
+        Ok(())
+    } else {
+        Err(TpcError::InvalidCompressionLevel {
+            codec: codec.to_string(),
+            level,
+            allowed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_stub_strips_extensions() {
+        assert_eq!(file_stub(Path::new("/data/lineitem.tbl")), "lineitem");
+        assert_eq!(file_stub(Path::new("/data/store_sales.dat")), "store_sales");
+        assert_eq!(file_stub(Path::new("/data/orders.tbl.gz")), "orders");
+        assert_eq!(file_stub(Path::new("part-1.csv")), "part-1");
+    }
+
+    #[test]
+    fn file_stub_keeps_part_numbers() {
+        assert_eq!(file_stub(Path::new("lineitem.tbl.1")), "lineitem-1");
+        assert_eq!(file_stub(Path::new("lineitem.tbl.12.gz")), "lineitem-12");
+    }
+
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn dest_dir_falls_back_for_paths_without_a_directory() {
+        assert_eq!(dest_dir(Path::new("/")), None);
+        assert_eq!(dest_dir(Path::new("nation.parquet")), Some(Path::new(".")));
+        assert_eq!(
+            dest_dir(Path::new("/tmp/nation.parquet")),
+            Some(Path::new("/tmp"))
+        );
+    }
+
+    #[test]
+    fn same_device_is_unknown_for_a_root_destination() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("part-0.parquet");
+        fs::write(&source, b"data").unwrap();
+        assert!(!is_same_device(&source, Path::new("/")).unwrap());
+    }
+
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn same_device_for_a_destination_that_does_not_exist_yet() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("part-0.parquet");
+        fs::write(&source, b"data").unwrap();
+        let dest = dir.path().join("part-1.parquet");
+        assert!(is_same_device(&source, &dest).unwrap());
+    }
+
+    #[test]
+    fn copy_into_place_replaces_the_destination() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("part-0.parquet");
+        let dest = dir.path().join("out.parquet");
+        fs::write(&source, b"new").unwrap();
+        fs::write(&dest, b"old").unwrap();
+        copy_into_place(&source, &dest).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"new");
+        assert!(!dir.path().join("out.parquet.tmp").exists());
+    }
+
+    #[test]
+    fn failed_copy_keeps_the_source_and_leaves_no_partial_file() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("part-0.parquet");
+        fs::write(&source, b"data").unwrap();
+
+        // the copy itself fails when the destination directory is missing
+        let dest = dir.path().join("missing").join("out.parquet");
+        assert!(copy_into_place(&source, &dest).is_err());
+        assert!(!dest.exists());
+
+        // the rename fails after the copy when the destination is a directory with files
+        let dest = dir.path().join("out.parquet");
+        fs::create_dir(&dest).unwrap();
+        fs::write(dest.join("part-0.parquet"), b"other").unwrap();
+        assert!(copy_into_place(&source, &dest).is_err());
+        assert!(dest.is_dir());
+        assert!(!dir.path().join("out.parquet.tmp").exists());
+
+        assert_eq!(fs::read(&source).unwrap(), b"data");
+    }
+
+    /// Check that a codec parses, or fails with the level and range it accepts
+    fn check_level(codec: &str, level: i64, allowed: RangeInclusive<i64>) {
+        let result = format!("{}:{}", codec, level).parse::<CompressionCodec>();
+        if allowed.contains(&level) {
+            let parsed = result.unwrap();
+            assert_eq!(parsed.to_string(), format!("{}:{}", codec, level));
+            parsed.to_parquet_compression().unwrap();
+        } else {
+            match result {
+                Err(TpcError::InvalidCompressionLevel {
+                    codec: name,
+                    level: found,
+                    allowed: range,
+                }) => {
+                    assert_eq!(name, codec);
+                    assert_eq!(found, level);
+                    assert_eq!(range, allowed);
+                }
+                other => panic!("{}:{} parsed as {:?}", codec, level, other),
+            }
+        }
+    }
+
+    #[test]
+    fn gzip_level_boundaries() {
+        for level in [0, 9, 10] {
+            check_level("gzip", level, GZIP_LEVELS);
+        }
+    }
+
+    #[test]
+    fn zstd_level_boundaries() {
+        for level in [0, 1, 22, 23] {
+            check_level("zstd", level, ZSTD_LEVELS);
+        }
+    }
+
+    #[test]
+    fn brotli_level_boundaries() {
+        for level in [0, 11, 12] {
+            check_level("brotli", level, BROTLI_LEVELS);
+        }
+    }
+
+    #[test]
+    fn levels_are_checked_when_converting_to_parquet() {
+        assert!(matches!(
+            CompressionCodec::Zstd(23).to_parquet_compression(),
+            Err(TpcError::InvalidCompressionLevel { level: 23, .. })
+        ));
+        assert!(matches!(
+            CompressionCodec::Gzip(10).to_parquet_compression(),
+            Err(TpcError::InvalidCompressionLevel { level: 10, .. })
+        ));
+        assert!(matches!(
+            CompressionCodec::Brotli(12).to_parquet_compression(),
+            Err(TpcError::InvalidCompressionLevel { level: 12, .. })
+        ));
+    }
+
+    #[test]
+    fn file_schema_reads_trailing_delimiter_as_dropped_field() {
+        let schema = tpch::TpcH::new().get_schema("nation").unwrap();
+        let file_schema = tbl_file_schema(&schema, &ConversionOptions::default()).unwrap();
+        assert_eq!(file_schema.fields().len(), schema.fields().len() + 1);
+        let trailing = file_schema.fields().last().unwrap();
+        assert_eq!(trailing.name(), TRAILING_FIELD);
+        assert!(trailing.metadata().contains_key(DROP_KEY));
+    }
+
+    #[test]
+    fn file_schema_without_trailing_delimiter_matches_table() {
+        let schema = tpch::TpcH::new().get_schema("nation").unwrap();
+        let options = ConversionOptions::builder()
+            .trailing_delimiter(false)
+            .build();
+        assert_eq!(tbl_file_schema(&schema, &options).unwrap(), schema);
+    }
+
+    #[test]
+    fn file_stub_handles_short_names() {
+        assert_eq!(file_stub(Path::new("a.tbl")), "a");
+        assert_eq!(file_stub(Path::new("ab")), "ab");
+        assert_eq!(file_stub(Path::new("x.gz")), "x");
+    }
+
+    #[test]
+    fn expected_row_counts_at_fractional_scales() {
+        let tpch = tpch::TpcH::new();
+        assert_eq!(tpch.get_expected_row_count("orders", 1.0), Some(1_500_000));
+        assert_eq!(tpch.get_expected_row_count("orders", 0.01), Some(15_000));
+        assert_eq!(tpch.get_expected_row_count("supplier", 0.1), Some(1_000));
+        assert_eq!(tpch.get_expected_row_count("nation", 0.01), Some(25));
+        let tpcds = tpcds::TpcDs::new();
+        assert_eq!(
+            tpcds.estimated_row_count("store_sales", 0.5),
+            Some(1_440_000)
+        );
+    }
+
+    #[test]
+    fn scales_dbgen_cannot_generate_are_rejected() {
+        let tpch = tpch::TpcH::new();
+        for scale in [1.5, 0.0005, 0.0125, 0.0, -1.0, f64::NAN] {
+            assert!(tpch.validate_scale(scale).is_err(), "{}", scale);
+            assert_eq!(
+                tpch.get_expected_row_count("orders", scale),
+                None,
+                "{}",
+                scale
+            );
+            assert_eq!(
+                tpch.estimated_row_count("lineitem", scale),
+                None,
+                "{}",
+                scale
+            );
+        }
+        assert!(tpch.validate_scale(0.001).is_ok());
+        assert_eq!(tpch.get_expected_row_count("supplier", 0.001), Some(10));
+        assert!(matches!(
+            estimate_output_bytes(&tpch, 2.5, CompressionCodec::Snappy),
+            Err(TpcError::InvalidOption(_))
+        ));
+        assert!(tpcds::TpcDs::new().validate_scale(0.0).is_err());
+    }
 }
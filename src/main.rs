@@ -10,14 +10,26 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::io::Result;
+use std::io::IsTerminal;
 use std::path::PathBuf;
+use std::time::Duration;
 
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use datafusion::parquet::file::properties::{EnabledStatistics, WriterVersion};
 use structopt::StructOpt;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tracing::{debug, error, info};
+use tracing_subscriber::EnvFilter;
 
-use tpctools::tpcds::TpcDs;
-use tpctools::tpch::TpcH;
-use tpctools::{convert_to_parquet, Tpc};
+use tpctools::{
+    benchmark_by_name, compact, convert_to_parquet, diff, estimate_output_bytes, generate,
+    generate_and_convert, generate_ddl, load_schema_overrides, parse_column_compression,
+    parse_delimiter, parse_statistics, parse_table_partitions, parse_writer_version, query_sql,
+    repartition, run_query, verify, verify_checksums, write_schemas_json, CancellationToken,
+    CompressionCodec, ConversionOptions, ConversionReport, DateGranularity, DatePartitioning,
+    GenerateOptions, MalformedRowMode, OutputFormat, PipelineOptions, ProgressEvent, Result,
+    ResumeMode, RetryPolicy, SqlDialect, Tpc, TpcError,
+};
 
 #[derive(Debug, StructOpt)]
 struct GenerateOpt {
@@ -27,12 +39,21 @@ struct GenerateOpt {
 
     /// Scale factor
     #[structopt(short, long)]
-    scale: usize,
+    scale: f64,
+
+    /// Generate data at a scale factor the benchmark does not allow, such as 0.01
+    #[structopt(long)]
+    force_scale: bool,
 
     /// Number of partitions to generate in parallel
     #[structopt(short, long)]
     partitions: usize,
 
+    /// Maximum number of generator processes to run at the same time, instead of one per
+    /// partition
+    #[structopt(long)]
+    concurrency: Option<usize>,
+
     /// Path to tpcds-kit
     #[structopt(short, long, parse(from_os_str))]
     generator_path: PathBuf,
@@ -40,6 +61,19 @@ struct GenerateOpt {
     /// Output path
     #[structopt(short, long, parse(from_os_str))]
     output: PathBuf,
+
+    /// Number of times to retry moving or copying a file that fails with a transient error,
+    /// such as on a network file system
+    #[structopt(long, default_value = "0")]
+    io_retries: usize,
+
+    /// Milliseconds to wait before the first retry, doubling before each retry after it
+    #[structopt(long, default_value = "100")]
+    io_retry_backoff_ms: u64,
+
+    /// Only log errors
+    #[structopt(short, long)]
+    quiet: bool,
 }
 
 #[derive(Debug, StructOpt)]
@@ -55,6 +89,460 @@ struct ConvertOpt {
     /// Output path
     #[structopt(parse(from_os_str), required = true, short = "o", long = "output")]
     output_path: PathBuf,
+
+    /// Output format (csv, json, parquet, or arrow)
+    #[structopt(short, long, default_value = "parquet")]
+    format: OutputFormat,
+
+    /// Parquet compression codec (none, snappy, lz4, lzo, or gzip/brotli/zstd with an optional level such as
+    /// zstd:9), snappy by default. Other formats are written uncompressed.
+    #[structopt(short, long)]
+    compression: Option<CompressionCodec>,
+
+    /// Comma-separated list of Parquet columns to compress with another codec, such as
+    /// l_comment=zstd:3
+    #[structopt(long, use_delimiter = true, parse(try_from_str = parse_column_compression))]
+    column_compression: Vec<(String, CompressionCodec)>,
+
+    /// How to handle existing output directories (fail, overwrite, or skip completed tables)
+    #[structopt(long, default_value = "fail")]
+    resume_mode: ResumeMode,
+
+    /// Number of tables to convert concurrently
+    #[structopt(long, default_value = "1")]
+    concurrency: usize,
+
+    /// Number of files to convert concurrently within each table
+    #[structopt(long, default_value = "1")]
+    file_concurrency: usize,
+
+    /// Maximum number of output files to move or copy into place at the same time, such as 1
+    /// for spinning disks
+    #[structopt(long)]
+    io_concurrency: Option<usize>,
+
+    /// Number of rows per record batch
+    #[structopt(long, default_value = "8192")]
+    batch_size: usize,
+
+    /// Maximum memory in bytes to use for sorting and repartitioning, spilling to disk or
+    /// failing beyond it
+    #[structopt(long)]
+    memory_limit_bytes: Option<usize>,
+
+    /// Maximum number of rows per Parquet row group
+    #[structopt(long)]
+    max_row_group_size: Option<usize>,
+
+    /// Maximum size in bytes of a Parquet data page
+    #[structopt(long)]
+    data_page_size_limit: Option<usize>,
+
+    /// Maximum size in bytes of a Parquet dictionary page
+    #[structopt(long)]
+    dictionary_page_size_limit: Option<usize>,
+
+    /// Disable Parquet dictionary encoding
+    #[structopt(long)]
+    disable_dictionary: bool,
+
+    /// Comma-separated list of columns to write without dictionary encoding
+    #[structopt(long, use_delimiter = true)]
+    disable_dictionary_columns: Vec<String>,
+
+    /// Level of Parquet column statistics to write (none, chunk, or page)
+    #[structopt(long, default_value = "page", parse(try_from_str = parse_statistics))]
+    statistics: EnabledStatistics,
+
+    /// Version of the Parquet format to write (1.0 or 2.0)
+    #[structopt(long, default_value = "1.0", parse(try_from_str = parse_writer_version))]
+    writer_version: WriterVersion,
+
+    /// Write Parquet bloom filters for every column
+    #[structopt(long)]
+    bloom_filters: bool,
+
+    /// Comma-separated list of columns to write Parquet bloom filters for
+    #[structopt(long, use_delimiter = true)]
+    bloom_filter_columns: Vec<String>,
+
+    /// False positive probability of the bloom filters, between 0 and 1
+    #[structopt(long)]
+    bloom_filter_fpp: Option<f64>,
+
+    /// Record the benchmark, scale factor, partitions, compression, and tpctools version in the
+    /// metadata of each Parquet file
+    #[structopt(long)]
+    footer_metadata: bool,
+
+    /// Field delimiter used in the input files, which is detected from the first lines of each
+    /// table's input when not set
+    #[structopt(long, parse(try_from_str = parse_delimiter))]
+    delimiter: Option<u8>,
+
+    /// Input lines do not end with a delimiter after the last field
+    #[structopt(long)]
+    no_trailing_delimiter: bool,
+
+    /// Write the field after the trailing delimiter as an extra __trailing_delimiter column
+    #[structopt(long)]
+    keep_trailing_column: bool,
+
+    /// Field value to read as null in nullable columns, such as \N or NULL, or "" for empty
+    /// fields
+    #[structopt(long)]
+    null_value: Option<String>,
+
+    /// Check every input line for the table's number of fields, and fail (reporting every
+    /// malformed line) or skip the lines that don't have it
+    #[structopt(long)]
+    malformed_rows: Option<MalformedRowMode>,
+
+    /// Format of date columns when they are not YYYY-MM-DD, such as %m/%d/%Y
+    #[structopt(long)]
+    date_format: Option<String>,
+
+    /// Format of timestamp columns, such as "%Y-%m-%d %H:%M:%S"
+    #[structopt(long)]
+    timestamp_format: Option<String>,
+
+    /// JSON file with schemas to read tables with instead of the benchmark's, keyed by table
+    /// name, each in the format written by the schemas command
+    #[structopt(long, parse(from_os_str))]
+    schema_overrides: Option<PathBuf>,
+
+    /// Comma-separated list of tables to convert, instead of every table in the benchmark
+    #[structopt(long, use_delimiter = true)]
+    tables: Vec<String>,
+
+    /// Check that each converted table has as many rows as its input
+    #[structopt(long)]
+    verify_row_counts: bool,
+
+    /// Check that the first Parquet file of each converted table has the table's columns
+    #[structopt(long)]
+    verify_schema: bool,
+
+    /// Print the rows and input megabytes converted per second for each table at the end
+    #[structopt(long)]
+    throughput: bool,
+
+    /// Scale factor the input was generated at, used to check row counts against the benchmark
+    /// specification
+    #[structopt(long)]
+    scale: Option<f64>,
+
+    /// Number of partitions the input was generated with, recorded in the manifest
+    #[structopt(long)]
+    partitions: Option<usize>,
+
+    /// Write a manifest.json listing the converted files to the output path
+    #[structopt(long)]
+    write_manifest: bool,
+
+    /// Write a checksums.sha256 file to each table's output directory
+    #[structopt(long)]
+    write_checksums: bool,
+
+    /// Don't write the empty _SUCCESS file that marks each table's output directory as
+    /// complete, which resuming with --resume-mode skip relies on
+    #[structopt(long)]
+    no_success_marker: bool,
+
+    /// Write a {table}.sql file with a CREATE TABLE statement in this SQL dialect (ansi, spark,
+    /// or duckdb) next to each converted table
+    #[structopt(long)]
+    write_ddl: Option<SqlDialect>,
+
+    /// Number of files to write for each table, instead of one per input file
+    #[structopt(long)]
+    target_partitions: Option<usize>,
+
+    /// Comma-separated list of numbers of files to write for particular tables, instead of
+    /// --target-partitions, such as lineitem=64,nation=1
+    #[structopt(long, use_delimiter = true, parse(try_from_str = parse_table_partitions))]
+    table_partitions: Vec<(String, usize)>,
+
+    /// Write each table to a single file instead of a directory of parts
+    #[structopt(long)]
+    single_file: bool,
+
+    /// Date column to partition each table that has it by, in Hive-style directories, or
+    /// comma-separated table=column pairs such as store_sales=ss_sold_date_sk
+    #[structopt(long)]
+    partition_by: Option<String>,
+
+    /// Granularity of the date partitions (year or month)
+    #[structopt(long, default_value = "year")]
+    partition_granularity: DateGranularity,
+
+    /// Maximum number of rows per output file, splitting larger files
+    #[structopt(long)]
+    max_rows_per_file: Option<usize>,
+
+    /// Comma-separated list of columns to sort the rows of each output file by
+    #[structopt(long, use_delimiter = true)]
+    sort_by: Vec<String>,
+
+    /// Number of times to retry moving or copying a file that fails with a transient error,
+    /// such as on a network file system
+    #[structopt(long, default_value = "0")]
+    io_retries: usize,
+
+    /// Milliseconds to wait before the first retry, doubling before each retry after it
+    #[structopt(long, default_value = "100")]
+    io_retry_backoff_ms: u64,
+
+    /// Remove each table's input files once it is converted
+    #[structopt(long)]
+    delete_source_on_success: bool,
+
+    /// List the files that would be converted without converting them
+    #[structopt(long)]
+    dry_run: bool,
+
+    /// Only log errors
+    #[structopt(short, long)]
+    quiet: bool,
+}
+
+#[derive(Debug, StructOpt)]
+struct VerifyOpt {
+    /// TPC benchmark to use (tpcds or tpch)
+    #[structopt(short, long)]
+    benchmark: String,
+
+    /// Path to csv files
+    #[structopt(parse(from_os_str), required = true, short = "i", long = "input")]
+    input_path: PathBuf,
+
+    /// Path to converted files
+    #[structopt(parse(from_os_str), required = true, short = "o", long = "output")]
+    output_path: PathBuf,
+
+    /// Format of the converted files (csv, json, parquet, or arrow)
+    #[structopt(short, long, default_value = "parquet")]
+    format: OutputFormat,
+
+    /// Field delimiter used in the input files, which is detected from the first lines of each
+    /// table's input when not set
+    #[structopt(long, parse(try_from_str = parse_delimiter))]
+    delimiter: Option<u8>,
+
+    /// Input lines do not end with a delimiter after the last field
+    #[structopt(long)]
+    no_trailing_delimiter: bool,
+
+    /// Field value to read as null in nullable columns, such as \N or NULL, or "" for empty
+    /// fields
+    #[structopt(long)]
+    null_value: Option<String>,
+
+    /// Check every input line for the table's number of fields, and fail (reporting every
+    /// malformed line) or skip the lines that don't have it
+    #[structopt(long)]
+    malformed_rows: Option<MalformedRowMode>,
+
+    /// Format of date columns when they are not YYYY-MM-DD, such as %m/%d/%Y
+    #[structopt(long)]
+    date_format: Option<String>,
+
+    /// Format of timestamp columns, such as "%Y-%m-%d %H:%M:%S"
+    #[structopt(long)]
+    timestamp_format: Option<String>,
+
+    /// JSON file with schemas to read tables with instead of the benchmark's, keyed by table
+    /// name, each in the format written by the schemas command
+    #[structopt(long, parse(from_os_str))]
+    schema_overrides: Option<PathBuf>,
+
+    /// Comma-separated list of tables to verify, instead of every table in the benchmark
+    #[structopt(long, use_delimiter = true)]
+    tables: Vec<String>,
+
+    /// Only log errors
+    #[structopt(short, long)]
+    quiet: bool,
+}
+
+#[derive(Debug, StructOpt)]
+struct VerifyChecksumsOpt {
+    /// Path to converted files
+    #[structopt(parse(from_os_str), required = true, short = "o", long = "output")]
+    output_path: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct CompactOpt {
+    /// Path to converted files
+    #[structopt(parse(from_os_str), required = true, short = "o", long = "output")]
+    output_path: PathBuf,
+
+    /// Table to compact
+    #[structopt(short, long)]
+    table: String,
+
+    /// Approximate size of each compacted file in bytes
+    #[structopt(long, default_value = "268435456")]
+    target_file_bytes: u64,
+}
+
+#[derive(Debug, StructOpt)]
+struct RepartitionOpt {
+    /// Path to converted files
+    #[structopt(parse(from_os_str), required = true, short = "o", long = "output")]
+    output_path: PathBuf,
+
+    /// Table to repartition
+    #[structopt(short, long)]
+    table: String,
+
+    /// Number of files to write
+    #[structopt(long)]
+    target_partitions: usize,
+}
+
+#[derive(Debug, StructOpt)]
+struct DdlOpt {
+    /// TPC benchmark to use (tpcds or tpch)
+    #[structopt(short, long)]
+    benchmark: String,
+
+    /// SQL dialect (ansi, spark, or duckdb)
+    #[structopt(short, long, default_value = "ansi")]
+    dialect: SqlDialect,
+}
+
+#[derive(Debug, StructOpt)]
+struct EstimateOpt {
+    /// TPC benchmark to use (tpcds or tpch)
+    #[structopt(short, long)]
+    benchmark: String,
+
+    /// Scale factor
+    #[structopt(short, long)]
+    scale: f64,
+
+    /// Compression codec (none, snappy, lz4, lzo, or gzip/brotli/zstd with an optional level such as zstd:9)
+    #[structopt(short, long, default_value = "snappy")]
+    compression: CompressionCodec,
+}
+
+#[derive(Debug, StructOpt)]
+struct DiffOpt {
+    /// TPC benchmark to use (tpcds or tpch)
+    #[structopt(short, long)]
+    benchmark: String,
+
+    /// Path to the first set of converted Parquet files
+    #[structopt(parse(from_os_str))]
+    path_a: PathBuf,
+
+    /// Path to the second set of converted Parquet files
+    #[structopt(parse(from_os_str))]
+    path_b: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct QueryOpt {
+    /// TPC benchmark to use (tpcds or tpch)
+    #[structopt(short, long)]
+    benchmark: String,
+
+    /// Path to the converted Parquet files
+    #[structopt(
+        parse(from_os_str),
+        required_unless = "print-sql",
+        short = "p",
+        long = "path"
+    )]
+    path: Option<PathBuf>,
+
+    /// Number of the query to run, such as 1 for TPC-H query 1
+    // `-q` is `--quiet` in the other commands
+    #[structopt(short = "Q", long)]
+    query: usize,
+
+    /// Print the SQL of the query instead of running it
+    #[structopt(long)]
+    print_sql: bool,
+}
+
+#[derive(Debug, StructOpt)]
+struct GenerateAndConvertOpt {
+    /// TPC benchmark to use (tpcds or tpch)
+    #[structopt(short, long)]
+    benchmark: String,
+
+    /// Scale factor
+    #[structopt(short, long)]
+    scale: f64,
+
+    /// Generate data at a scale factor the benchmark does not allow, such as 0.01
+    #[structopt(long)]
+    force_scale: bool,
+
+    /// Number of partitions to generate in parallel
+    #[structopt(short, long)]
+    partitions: usize,
+
+    /// Maximum number of generator processes to run at the same time, instead of one per
+    /// partition
+    #[structopt(long)]
+    concurrency: Option<usize>,
+
+    /// Path to tpcds-kit
+    #[structopt(short, long, parse(from_os_str))]
+    generator_path: PathBuf,
+
+    /// Directory to generate the data to before converting it
+    #[structopt(short, long, parse(from_os_str))]
+    work_dir: PathBuf,
+
+    /// Output path
+    #[structopt(short, long, parse(from_os_str))]
+    output: PathBuf,
+
+    /// Output format (csv, json, parquet, or arrow)
+    #[structopt(short, long, default_value = "parquet")]
+    format: OutputFormat,
+
+    /// Parquet compression codec (none, snappy, lz4, lzo, or gzip/brotli/zstd with an optional level such as
+    /// zstd:9), snappy by default. Other formats are written uncompressed.
+    #[structopt(short, long)]
+    compression: Option<CompressionCodec>,
+
+    /// How to handle existing output directories (fail, overwrite, or skip completed tables)
+    #[structopt(long, default_value = "fail")]
+    resume_mode: ResumeMode,
+
+    /// Keep the generated files after converting them
+    #[structopt(long)]
+    keep_generated: bool,
+
+    /// Comma-separated list of tables to convert, instead of every table in the benchmark. The
+    /// generated files of the other tables are kept.
+    #[structopt(long, use_delimiter = true)]
+    tables: Vec<String>,
+
+    /// Print the rows and input megabytes converted per second for each table at the end
+    #[structopt(long)]
+    throughput: bool,
+
+    /// Only log errors
+    #[structopt(short, long)]
+    quiet: bool,
+}
+
+#[derive(Debug, StructOpt)]
+struct SchemasOpt {
+    /// TPC benchmark to use (tpcds or tpch)
+    #[structopt(short, long)]
+    benchmark: String,
+
+    /// Directory to write the schema files to
+    #[structopt(short, long, parse(from_os_str))]
+    output: PathBuf,
 }
 
 #[derive(Debug, StructOpt)]
@@ -62,58 +550,471 @@ struct ConvertOpt {
     name = "tpctools",
     about = "Tools for generating and converting TPC data sets."
 )]
+// parsed once, so the size of the convert options doesn't matter
+#[allow(clippy::large_enum_variant)]
 enum Opt {
     Generate(GenerateOpt),
     Convert(ConvertOpt),
+    /// Generate data and convert it, removing the generated files once it is converted
+    GenerateAndConvert(GenerateAndConvertOpt),
+    /// Compare converted tables with the files they were converted from
+    Verify(VerifyOpt),
+    /// Check converted files against the checksums written when they were converted
+    VerifyChecksums(VerifyChecksumsOpt),
+    /// Compare the converted Parquet tables under two paths
+    Diff(DiffOpt),
+    /// Run one of the benchmark's standard queries against converted Parquet files
+    Query(QueryOpt),
+    /// Rewrite a converted Parquet table into fewer, larger files
+    Compact(CompactOpt),
+    /// Rewrite a converted Parquet table into a given number of files
+    Repartition(RepartitionOpt),
+    /// Print CREATE TABLE statements for the benchmark tables
+    Ddl(DdlOpt),
+    /// Write the schema of each benchmark table to a JSON file
+    Schemas(SchemasOpt),
+    /// Estimate the size of the converted Parquet output, for provisioning disk
+    Estimate(EstimateOpt),
+}
+
+impl Opt {
+    fn quiet(&self) -> bool {
+        match self {
+            Opt::Generate(opt) => opt.quiet,
+            Opt::Convert(opt) => opt.quiet,
+            Opt::GenerateAndConvert(opt) => opt.quiet,
+            Opt::Verify(opt) => opt.quiet,
+            Opt::VerifyChecksums(_)
+            | Opt::Diff(_)
+            | Opt::Query(_)
+            | Opt::Compact(_)
+            | Opt::Repartition(_)
+            | Opt::Ddl(_)
+            | Opt::Schemas(_)
+            | Opt::Estimate(_) => false,
+        }
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    match Opt::from_args() {
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run() -> Result<()> {
+    let opt = Opt::from_args();
+    init_logging(opt.quiet());
+
+    match opt {
         Opt::Generate(opt) => {
             let scale = opt.scale;
             let partitions = opt.partitions;
 
             if !opt.generator_path.exists() {
-                panic!(
-                    "generator path does not exist: {}",
-                    opt.generator_path.display()
-                )
+                return Err(TpcError::MissingPath {
+                    description: "Generator path".to_string(),
+                    path: opt.generator_path,
+                });
             }
 
             if !opt.output.exists() {
-                panic!("output path does not exist: {}", opt.output.display())
+                return Err(TpcError::MissingPath {
+                    description: "Output path".to_string(),
+                    path: opt.output,
+                });
             }
 
             let generator_path = format!("{}", opt.generator_path.display());
             let output_path_str = format!("{}", opt.output.display());
 
-            let tpc = create_benchmark(&opt.benchmark);
+            let tpc = create_benchmark(&opt.benchmark)?;
 
-            tpc.generate(scale, partitions, &generator_path, &output_path_str)?;
+            let options = GenerateOptions {
+                concurrency: opt.concurrency,
+                force_scale: opt.force_scale,
+                io_retry: io_retry(opt.io_retries, opt.io_retry_backoff_ms),
+            };
+            generate(
+                tpc.as_ref(),
+                scale,
+                partitions,
+                &generator_path,
+                &output_path_str,
+                &options,
+            )?;
         }
         Opt::Convert(opt) => {
-            let tpc = create_benchmark(&opt.benchmark);
-            match convert_to_parquet(
+            let tpc = create_benchmark(&opt.benchmark)?;
+            let mut builder = ConversionOptions::builder()
+                .format(opt.format)
+                .column_compression(opt.column_compression)
+                .resume_mode(opt.resume_mode)
+                .concurrency(opt.concurrency)
+                .file_concurrency(opt.file_concurrency)
+                .batch_size(opt.batch_size)
+                .dictionary_enabled(!opt.disable_dictionary)
+                .dictionary_disabled_columns(opt.disable_dictionary_columns)
+                .statistics(opt.statistics)
+                .writer_version(opt.writer_version)
+                .bloom_filter_enabled(opt.bloom_filters)
+                .bloom_filter_columns(opt.bloom_filter_columns)
+                .footer_metadata(opt.footer_metadata)
+                .trailing_delimiter(!opt.no_trailing_delimiter)
+                .dry_run(opt.dry_run)
+                .verify_row_counts(opt.verify_row_counts)
+                .verify_schema(opt.verify_schema)
+                .write_manifest(opt.write_manifest)
+                .write_checksums(opt.write_checksums)
+                .write_success_marker(!opt.no_success_marker)
+                .single_file(opt.single_file)
+                .delete_source_on_success(opt.delete_source_on_success)
+                .drop_trailing_column(!opt.keep_trailing_column);
+            if let Some(delimiter) = opt.delimiter {
+                builder = builder.delimiter(delimiter);
+            }
+            if let Some(null_value) = opt.null_value {
+                builder = builder.null_value(null_value);
+            }
+            if let Some(mode) = opt.malformed_rows {
+                builder = builder.malformed_rows(mode);
+            }
+            if let Some(date_format) = opt.date_format {
+                builder = builder.date_format(date_format);
+            }
+            if let Some(timestamp_format) = opt.timestamp_format {
+                builder = builder.timestamp_format(timestamp_format);
+            }
+            if let Some(scale) = opt.scale {
+                builder = builder.scale_factor(scale);
+            }
+            if let Some(partitions) = opt.partitions {
+                builder = builder.partitions(partitions);
+            }
+            if let Some(codec) = opt.compression {
+                builder = builder.compression(codec);
+            }
+            if let Some(dialect) = opt.write_ddl {
+                builder = builder.write_ddl(dialect);
+            }
+            if let Some(target_partitions) = opt.target_partitions {
+                builder = builder.target_partitions(target_partitions);
+            }
+            if !opt.table_partitions.is_empty() {
+                builder = builder.table_partitions(opt.table_partitions.into_iter().collect());
+            }
+            if let Some(partition_by) = opt.partition_by {
+                builder = builder.partition_by(DatePartitioning::parse(
+                    tpc.as_ref(),
+                    &partition_by,
+                    opt.partition_granularity,
+                )?);
+            }
+            if let Some(max_row_group_size) = opt.max_row_group_size {
+                builder = builder.max_row_group_size(max_row_group_size);
+            }
+            if let Some(fpp) = opt.bloom_filter_fpp {
+                builder = builder.bloom_filter_fpp(fpp);
+            }
+            if let Some(data_page_size_limit) = opt.data_page_size_limit {
+                builder = builder.data_page_size_limit(data_page_size_limit);
+            }
+            if let Some(dictionary_page_size_limit) = opt.dictionary_page_size_limit {
+                builder = builder.dictionary_page_size_limit(dictionary_page_size_limit);
+            }
+            if !opt.tables.is_empty() {
+                builder = builder.tables(opt.tables);
+            }
+            if let Some(path) = opt.schema_overrides {
+                builder = builder.schema_override(load_schema_overrides(&path)?);
+            }
+            if let Some(max_rows_per_file) = opt.max_rows_per_file {
+                builder = builder.max_rows_per_file(max_rows_per_file);
+            }
+            if let Some(memory_limit_bytes) = opt.memory_limit_bytes {
+                builder = builder.memory_limit_bytes(memory_limit_bytes);
+            }
+            if !opt.sort_by.is_empty() {
+                builder = builder.sort_columns(opt.sort_by);
+            }
+            if let Some(io_retry) = io_retry(opt.io_retries, opt.io_retry_backoff_ms) {
+                builder = builder.io_retry(io_retry);
+            }
+            if let Some(io_concurrency) = opt.io_concurrency {
+                builder = builder.io_concurrency(io_concurrency);
+            }
+            let (progress, events) = mpsc::unbounded_channel();
+            let logger = tokio::spawn(log_progress(events));
+            // stop on Ctrl-C, removing the output of the tables that aren't complete
+            let cancellation = CancellationToken::new();
+            let token = cancellation.clone();
+            tokio::spawn(async move {
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    info!("Cancelling conversion");
+                    token.cancel();
+                }
+            });
+            let options = builder
+                .progress(progress)
+                .cancellation_token(cancellation)
+                .build();
+            let result = convert_to_parquet(
                 tpc.as_ref(),
                 opt.input_path.as_path().to_str().unwrap(),
                 opt.output_path.as_path().to_str().unwrap(),
+                &options,
             )
-            .await
-            {
-                Ok(_) => {}
-                Err(e) => println!("{:?}", e),
+            .await;
+            // closes the channel so that the logger finishes
+            drop(options);
+            let _ = logger.await;
+            print_conversion_result(result, opt.throughput)?;
+        }
+        Opt::GenerateAndConvert(opt) => {
+            if !opt.generator_path.exists() {
+                return Err(TpcError::MissingPath {
+                    description: "Generator path".to_string(),
+                    path: opt.generator_path,
+                });
+            }
+            let tpc = create_benchmark(&opt.benchmark)?;
+            let (progress, events) = mpsc::unbounded_channel();
+            let logger = tokio::spawn(log_progress(events));
+            let cancellation = CancellationToken::new();
+            let token = cancellation.clone();
+            tokio::spawn(async move {
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    info!("Cancelling conversion");
+                    token.cancel();
+                }
+            });
+            let mut convert = ConversionOptions::builder()
+                .format(opt.format)
+                .resume_mode(opt.resume_mode)
+                .progress(progress)
+                .cancellation_token(cancellation);
+            if let Some(codec) = opt.compression {
+                convert = convert.compression(codec);
+            }
+            if !opt.tables.is_empty() {
+                convert = convert.tables(opt.tables);
             }
+            let options = PipelineOptions {
+                generate: GenerateOptions {
+                    concurrency: opt.concurrency,
+                    force_scale: opt.force_scale,
+                    ..Default::default()
+                },
+                convert: convert.build(),
+                keep_generated: opt.keep_generated,
+            };
+            let result = generate_and_convert(
+                tpc.into(),
+                opt.scale,
+                opt.partitions,
+                opt.generator_path.as_path().to_str().unwrap(),
+                opt.work_dir.as_path().to_str().unwrap(),
+                opt.output.as_path().to_str().unwrap(),
+                &options,
+            )
+            .await;
+            // closes the channel so that the logger finishes
+            drop(options);
+            let _ = logger.await;
+            print_conversion_result(result, opt.throughput)?;
+        }
+        Opt::Verify(opt) => {
+            let tpc = create_benchmark(&opt.benchmark)?;
+            let mut builder = ConversionOptions::builder()
+                .format(opt.format)
+                .trailing_delimiter(!opt.no_trailing_delimiter);
+            if let Some(delimiter) = opt.delimiter {
+                builder = builder.delimiter(delimiter);
+            }
+            if let Some(null_value) = opt.null_value {
+                builder = builder.null_value(null_value);
+            }
+            if let Some(mode) = opt.malformed_rows {
+                builder = builder.malformed_rows(mode);
+            }
+            if let Some(date_format) = opt.date_format {
+                builder = builder.date_format(date_format);
+            }
+            if let Some(timestamp_format) = opt.timestamp_format {
+                builder = builder.timestamp_format(timestamp_format);
+            }
+            if !opt.tables.is_empty() {
+                builder = builder.tables(opt.tables);
+            }
+            if let Some(path) = opt.schema_overrides {
+                builder = builder.schema_override(load_schema_overrides(&path)?);
+            }
+            let report = verify(
+                tpc.as_ref(),
+                opt.input_path.as_path().to_str().unwrap(),
+                opt.output_path.as_path().to_str().unwrap(),
+                &builder.build(),
+            )
+            .await?;
+            for table in &report.tables {
+                if table.passed() {
+                    info!("{}: passed", table.table);
+                } else {
+                    for discrepancy in &table.discrepancies {
+                        error!("{}: {}", table.table, discrepancy);
+                    }
+                }
+            }
+            if !report.passed() {
+                let failed = report.tables.iter().filter(|t| !t.passed()).count();
+                return Err(TpcError::Conversion(format!(
+                    "Verification failed for {} of {} tables",
+                    failed,
+                    report.tables.len()
+                )));
+            }
+        }
+        Opt::VerifyChecksums(opt) => {
+            verify_checksums(opt.output_path.as_path().to_str().unwrap())?;
+        }
+        Opt::Diff(opt) => {
+            let tpc = create_benchmark(&opt.benchmark)?;
+            let report = diff(
+                opt.path_a.as_path().to_str().unwrap(),
+                opt.path_b.as_path().to_str().unwrap(),
+                tpc.as_ref(),
+            )
+            .await?;
+            for table in &report.tables {
+                if table.is_equal() {
+                    info!("{}: equal", table.table);
+                } else {
+                    for difference in &table.differences {
+                        error!("{}: {}", table.table, difference);
+                    }
+                }
+            }
+            if !report.is_equal() {
+                let different = report.tables.iter().filter(|t| !t.is_equal()).count();
+                return Err(TpcError::Conversion(format!(
+                    "{} of {} tables differ",
+                    different,
+                    report.tables.len()
+                )));
+            }
+        }
+        Opt::Query(opt) => {
+            let tpc = create_benchmark(&opt.benchmark)?;
+            if opt.print_sql {
+                let sql = query_sql(tpc.as_ref(), opt.query).ok_or_else(|| {
+                    TpcError::InvalidOption(format!(
+                        "No query {} for {}",
+                        opt.query,
+                        tpc.get_name()
+                    ))
+                })?;
+                print!("{}", sql);
+            } else {
+                let path = opt.path.as_deref().and_then(|p| p.to_str()).unwrap();
+                let batches = run_query(tpc.as_ref(), path, opt.query).await?;
+                // each statement of a query with more than one has its own columns
+                for batches in batches.chunk_by(|a, b| a.schema() == b.schema()) {
+                    println!("{}", pretty_format_batches(batches)?);
+                }
+            }
+        }
+        Opt::Compact(opt) => {
+            let output_path = opt.output_path.as_path().to_str().unwrap();
+            compact(output_path, &opt.table, opt.target_file_bytes).await?;
+        }
+        Opt::Repartition(opt) => {
+            let output_path = opt.output_path.as_path().to_str().unwrap();
+            repartition(output_path, &opt.table, opt.target_partitions).await?;
+        }
+        Opt::Ddl(opt) => {
+            let tpc = create_benchmark(&opt.benchmark)?;
+            print!("{}", generate_ddl(tpc.as_ref(), opt.dialect)?);
+        }
+        Opt::Estimate(opt) => {
+            let tpc = create_benchmark(&opt.benchmark)?;
+            let bytes = estimate_output_bytes(tpc.as_ref(), opt.scale, opt.compression)?;
+            println!(
+                "Estimated output size: {} bytes ({:.1} GiB)",
+                bytes,
+                bytes as f64 / (1u64 << 30) as f64
+            );
+        }
+        Opt::Schemas(opt) => {
+            let tpc = create_benchmark(&opt.benchmark)?;
+            write_schemas_json(tpc.as_ref(), &format!("{}", opt.output.display()))?;
         }
     }
 
     Ok(())
 }
 
-fn create_benchmark(name: &str) -> Box<dyn Tpc> {
-    match name {
-        "tpcds" | "tpc-ds" => Box::new(TpcDs::new()),
-        "tpch" | "tpc-h" => Box::new(TpcH::new()),
-        _ => panic!("invalid benchmark name"),
+fn create_benchmark(name: &str) -> Result<Box<dyn Tpc>> {
+    benchmark_by_name(name)
+        .ok_or_else(|| TpcError::InvalidOption(format!("Invalid benchmark name: {}", name)))
+}
+
+/// Get the policy for retrying file operations, which are only tried once without retries
+fn io_retry(retries: usize, backoff_ms: u64) -> Option<RetryPolicy> {
+    (retries > 0).then(|| RetryPolicy::new(retries + 1, Duration::from_millis(backoff_ms)))
+}
+
+/// Print the throughput of a conversion when requested, passing on the error it failed with
+fn print_conversion_result(result: Result<ConversionReport>, throughput: bool) -> Result<()> {
+    let report = result?;
+    if throughput {
+        print!("{}", report.throughput_summary());
     }
+    Ok(())
+}
+
+/// Log each table as it finishes converting
+async fn log_progress(mut events: UnboundedReceiver<ProgressEvent>) {
+    while let Some(event) = events.recv().await {
+        match event {
+            ProgressEvent::FileConverted {
+                table,
+                file,
+                rows,
+                bytes,
+            } => debug!(
+                "Wrote {} for {}: {} rows ({} bytes)",
+                file, table, rows, bytes
+            ),
+            ProgressEvent::TableFinished(stats) => info!(
+                "Converted {}: {} rows in {} files ({} bytes) in {} ms (read {} ms, write {} ms)",
+                stats.table,
+                stats.rows,
+                stats.files.len(),
+                stats.bytes,
+                stats.elapsed.as_millis(),
+                stats.read_time.as_millis(),
+                stats.write_time.as_millis()
+            ),
+            ProgressEvent::TableStarted { .. } | ProgressEvent::TableSkipped { .. } => {}
+        }
+    }
+}
+
+/// Log to stdout at info level, or at the level given by `RUST_LOG`. Quiet mode only logs
+/// errors.
+fn init_logging(quiet: bool) {
+    let filter = if quiet {
+        EnvFilter::new("error")
+    } else {
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"))
+    };
+    // logs go to stderr so that the output of commands such as query can be redirected
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_target(false)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .init();
 }
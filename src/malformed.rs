@@ -0,0 +1,197 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Finding input lines with the wrong number of fields, which the CSV reader can only fail on
+//! without saying where they are
+
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use flate2::read::GzDecoder;
+use tempfile::TempDir;
+use tracing::{debug, info};
+
+use crate::error::{Result, TpcError};
+
+/// What to do with input lines that have the wrong number of fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MalformedRowMode {
+    /// Fail before converting a table with any malformed rows, reporting every one of them
+    Fail,
+    /// Convert the other rows, reporting the malformed rows in the table's statistics
+    Skip,
+}
+
+impl FromStr for MalformedRowMode {
+    type Err = TpcError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(Self::Fail),
+            "skip" => Ok(Self::Skip),
+            other => Err(TpcError::InvalidOption(format!(
+                "Invalid malformed row mode: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// An input line that could not be read as a row of its table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MalformedRow {
+    pub file: PathBuf,
+    /// Line number in the file, starting from 1
+    pub line: u64,
+    pub reason: String,
+}
+
+impl fmt::Display for MalformedRow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file.display(), self.line, self.reason)
+    }
+}
+
+/// A table's input files with any malformed rows removed, which may be copies in a temporary
+/// directory that is removed when this is dropped
+pub(crate) struct CheckedInput {
+    pub files: Vec<PathBuf>,
+    pub malformed_rows: Vec<MalformedRow>,
+    _dir: Option<TempDir>,
+}
+
+/// Check every line of a table's input files for `fields` delimited fields, counting the empty
+/// field after a trailing delimiter. Empty lines are left for the reader to skip. With
+/// [`MalformedRowMode::Skip`], each file with malformed rows is replaced by a copy without them.
+pub(crate) fn check_input(
+    table: &str,
+    files: &[PathBuf],
+    fields: usize,
+    delimiter: u8,
+    mode: MalformedRowMode,
+) -> Result<CheckedInput> {
+    info!("Checking input rows");
+    let mut dir: Option<TempDir> = None;
+    let mut checked = vec![];
+    let mut malformed_rows = vec![];
+    for (i, file) in files.iter().enumerate() {
+        let lines = malformed_lines(file, fields, delimiter)?;
+        if lines.is_empty() || mode == MalformedRowMode::Fail {
+            checked.push(file.clone());
+        } else {
+            let dir = match &mut dir {
+                Some(dir) => dir,
+                None => dir.insert(tempfile::Builder::new().prefix("tpctools-").tempdir()?),
+            };
+            // numbered directories keep files with the same name apart, and the copies keep
+            // the name without `.gz` so that the output is numbered the same
+            let copy_dir = dir.path().join(i.to_string());
+            fs::create_dir(&copy_dir)?;
+            let name = file.file_name().unwrap_or_default();
+            let name = match file.extension() {
+                Some(ext) if ext == OsStr::new("gz") => file.file_stem().unwrap_or(name),
+                _ => name,
+            };
+            let copy = copy_dir.join(name);
+            debug!(
+                "Copying {} without malformed rows to {}",
+                file.display(),
+                copy.display()
+            );
+            copy_without_lines(file, &copy, &lines)?;
+            checked.push(copy);
+        }
+        malformed_rows.extend(lines.into_iter().map(|(line, reason)| MalformedRow {
+            file: file.clone(),
+            line,
+            reason,
+        }));
+    }
+    if mode == MalformedRowMode::Fail && !malformed_rows.is_empty() {
+        return Err(TpcError::MalformedRows {
+            table: table.to_owned(),
+            rows: malformed_rows,
+        });
+    }
+    Ok(CheckedInput {
+        files: checked,
+        malformed_rows,
+        _dir: dir,
+    })
+}
+
+/// Find the numbers of the lines of a file without `fields` fields, with the reason for each
+fn malformed_lines(file: &Path, fields: usize, delimiter: u8) -> Result<Vec<(u64, String)>> {
+    let mut malformed = vec![];
+    let mut number = 0;
+    for_each_line(file, |line| {
+        number += 1;
+        if !line.is_empty() {
+            let found = line.iter().filter(|&&b| b == delimiter).count() + 1;
+            if found != fields {
+                malformed.push((
+                    number,
+                    format!("expected {} fields but found {}", fields, found),
+                ));
+            }
+        }
+        Ok(())
+    })?;
+    Ok(malformed)
+}
+
+/// Copy a file, decompressing it if it is gzip compressed, leaving out the given lines
+fn copy_without_lines(file: &Path, copy: &Path, lines: &[(u64, String)]) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(copy)?);
+    let mut malformed = lines.iter().map(|(line, _)| *line).peekable();
+    let mut number = 0;
+    for_each_line(file, |line| {
+        number += 1;
+        if malformed.peek() == Some(&number) {
+            malformed.next();
+        } else {
+            writer.write_all(line)?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    })?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Call a function with each line of a file, without its line ending
+fn for_each_line(file: &Path, mut f: impl FnMut(&[u8]) -> Result<()>) -> Result<()> {
+    let reader: Box<dyn Read> = if file.extension() == Some(OsStr::new("gz")) {
+        Box::new(GzDecoder::new(File::open(file)?))
+    } else {
+        Box::new(File::open(file)?)
+    };
+    let mut reader = BufReader::new(reader);
+    let mut line = vec![];
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(());
+        }
+        if line.ends_with(b"\n") {
+            line.pop();
+        }
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+        f(&line)?;
+    }
+}
@@ -0,0 +1,75 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A machine-readable listing of the files written by a conversion
+
+use datafusion::prelude::SessionContext;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+use crate::sink::output_sink;
+use crate::{ConversionOptions, ConversionReport};
+
+/// Name of the manifest file written to the output path
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// The files written by a conversion and the options used to write them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format: String,
+    pub compression: String,
+    /// Scale factor the input was generated at, if known
+    pub scale_factor: Option<f64>,
+    /// Number of partitions the input was generated with, if known
+    pub partitions: Option<usize>,
+    pub tables: Vec<ManifestTable>,
+}
+
+/// The files written for one table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestTable {
+    pub table: String,
+    /// Output files, relative to the output path
+    pub files: Vec<String>,
+    pub rows: u64,
+    pub bytes: u64,
+}
+
+impl Manifest {
+    /// Create the manifest for a conversion
+    pub fn new(report: &ConversionReport, options: &ConversionOptions) -> Self {
+        Self {
+            format: options.format.to_string(),
+            compression: options.codec().to_string(),
+            scale_factor: options.scale_factor,
+            partitions: options.partitions,
+            tables: report
+                .per_table
+                .iter()
+                .map(|stats| ManifestTable {
+                    table: stats.table.clone(),
+                    files: stats.files.clone(),
+                    rows: stats.rows,
+                    bytes: stats.bytes,
+                })
+                .collect(),
+        }
+    }
+
+    /// Write the manifest to `{output_path}/manifest.json`
+    pub async fn write(&self, output_path: &str) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)? + "\n";
+        output_sink(&SessionContext::new(), output_path)?
+            .put(MANIFEST_FILE, json.into_bytes())
+            .await
+    }
+}
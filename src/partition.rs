@@ -0,0 +1,401 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hive-style partitioning of converted tables by a date column
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use datafusion::arrow::array::{Array, Int32Array, UInt32Array};
+use datafusion::arrow::compute::take;
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::prelude::*;
+use tracing::debug;
+
+use crate::error::{Result, TpcError};
+use crate::writer::{next_batch, PartWriter, Written};
+use crate::{read_tbl_files, ConversionOptions, OutputFormat, Tpc};
+
+/// Directory name used by Hive for rows whose partition value is null
+const NULL_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// Granularity of date partitions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateGranularity {
+    /// One directory per year, such as `year=1998`
+    Year,
+    /// One directory per month, such as `year=1998/month=7`
+    Month,
+}
+
+impl FromStr for DateGranularity {
+    type Err = TpcError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "year" => Ok(Self::Year),
+            "month" => Ok(Self::Month),
+            other => Err(TpcError::InvalidOption(format!(
+                "Invalid date granularity: {}",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for DateGranularity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Year => write!(f, "year"),
+            Self::Month => write!(f, "month"),
+        }
+    }
+}
+
+/// Partition tables' output into directories by the year or month of a date column
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatePartitioning {
+    /// Date column of each partitioned table, keyed by table name. Other tables are written
+    /// without partitions. A column is either a `Date32` column or an `Int32` TPC-DS date key
+    /// ending in `_date_sk`, such as `ss_sold_date_sk`.
+    pub columns: HashMap<String, String>,
+    pub granularity: DateGranularity,
+}
+
+/// One output directory of a partitioned table, written to a temp directory
+pub(crate) struct WrittenPartition {
+    /// Directory relative to the table's output directory, such as `year=1998`
+    pub dir: String,
+    /// Temp directory containing the partition's files
+    pub temp_dir: String,
+}
+
+/// The files being written for one partition directory
+struct PartitionFiles {
+    key: Vec<Option<i32>>,
+    partition: WrittenPartition,
+    /// One writer per file, which batches are spread over in turn
+    writers: Vec<PartWriter>,
+    next: usize,
+}
+
+/// Julian day number of 1970-01-01. TPC-DS date keys are Julian day numbers, so that
+/// `d_date_sk` 2415022 is 1900-01-02, and subtracting this gives a `Date32`.
+const UNIX_EPOCH_JULIAN_DAY: i32 = 2_440_588;
+
+impl DatePartitioning {
+    /// Partition every table of the benchmark that has the column by it
+    pub fn by_column(
+        benchmark: &dyn Tpc,
+        column: &str,
+        granularity: DateGranularity,
+    ) -> Result<Self> {
+        let mut columns = HashMap::new();
+        for (table, schema) in benchmark.schemas()? {
+            if schema.field_with_name(column).is_ok() {
+                columns.insert(table.to_string(), column.to_string());
+            }
+        }
+        if columns.is_empty() {
+            return Err(TpcError::InvalidOption(format!(
+                "Partition column {} is not a column of any {} table",
+                column,
+                benchmark.get_name()
+            )));
+        }
+        Ok(Self {
+            columns,
+            granularity,
+        })
+    }
+
+    /// Parse partition columns from either one column name, which partitions every table that
+    /// has the column as in [`DatePartitioning::by_column`], or a comma-separated list of
+    /// `table=column` pairs, such as `store_sales=ss_sold_date_sk,web_sales=ws_sold_date_sk`
+    pub fn parse(benchmark: &dyn Tpc, s: &str, granularity: DateGranularity) -> Result<Self> {
+        if !s.contains('=') {
+            return Self::by_column(benchmark, s, granularity);
+        }
+        let columns = s
+            .split(',')
+            .map(|pair| match pair.split_once('=') {
+                Some((table, column)) if !table.is_empty() && !column.is_empty() => {
+                    Ok((table.to_string(), column.to_string()))
+                }
+                _ => Err(TpcError::InvalidOption(format!(
+                    "Invalid partition column: {} (expected table=column)",
+                    pair
+                ))),
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            columns,
+            granularity,
+        })
+    }
+
+    /// The partition column of a table, if the table is partitioned
+    pub(crate) fn column(&self, table: &str) -> Option<&str> {
+        self.columns.get(table).map(String::as_str)
+    }
+
+    /// Check that a partitioned table's column is a date column or a TPC-DS date key
+    pub(crate) fn validate(&self, table: &str, schema: &Schema) -> Result<()> {
+        let column = match self.column(table) {
+            Some(column) => column,
+            None => return Ok(()),
+        };
+        let field = schema.field_with_name(column).map_err(|_| {
+            TpcError::InvalidOption(format!(
+                "Partition column {} is not a column of table {}",
+                column, table
+            ))
+        })?;
+        if !matches!(field.data_type(), DataType::Date32) && !is_date_key(field) {
+            return Err(TpcError::InvalidOption(format!(
+                "Partition column {} of table {} is not a date column or a date key",
+                column, table
+            )));
+        }
+        Ok(())
+    }
+
+    /// Pattern matching the partition directories, such as `year=*/month=*`
+    pub(crate) fn dir_pattern(&self) -> String {
+        self.key_names()
+            .iter()
+            .map(|name| format!("{}=*", name))
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// Names of the partition keys, from the outermost directory inwards
+    fn key_names(&self) -> &'static [&'static str] {
+        match self.granularity {
+            DateGranularity::Year => &["year"],
+            DateGranularity::Month => &["year", "month"],
+        }
+    }
+
+    /// Expressions of the partition keys of a column, from the outermost directory inwards
+    fn keys(&self, field: &Field) -> Vec<Expr> {
+        let date = if is_date_key(field) {
+            cast(
+                col(field.name().as_str()) - lit(UNIX_EPOCH_JULIAN_DAY),
+                DataType::Date32,
+            )
+        } else {
+            col(field.name().as_str())
+        };
+        self.key_names()
+            .iter()
+            .map(|part| cast(date_part(lit(*part), date.clone()), DataType::Int32))
+            .collect()
+    }
+
+    /// Directory of a partition, such as `year=1998/month=7`
+    fn dir(&self, key: &[Option<i32>]) -> String {
+        self.key_names()
+            .iter()
+            .zip(key)
+            .map(|(name, value)| match value {
+                Some(value) => format!("{}={}", name, value),
+                None => format!("{}={}", name, NULL_PARTITION),
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+/// Convert a table's input to a temp directory in `output_dir` for each partition, with
+/// `partitions` files each, in one pass over the input. The rows are sorted by the partition
+/// keys, and then by the sort columns, so that each partition's files are written and closed in
+/// turn and only one partition's writers hold row groups in memory. Returns the partitions in
+/// order of their keys.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn write_partitions(
+    ctx: &SessionContext,
+    files: &[PathBuf],
+    output_dir: &Path,
+    csv_options: &CsvReadOptions<'_>,
+    options: &ConversionOptions,
+    partitioning: &DatePartitioning,
+    column: &str,
+    partitions: usize,
+) -> Result<(Vec<WrittenPartition>, Written)> {
+    let df = read_tbl_files(ctx, files, csv_options).await?;
+    let field = df
+        .schema()
+        .field_with_unqualified_name(column)?
+        .field()
+        .clone();
+    // the keys are computed once, as extra columns that aren't written
+    let columns: Vec<usize> = (0..df.schema().fields().len()).collect();
+    let mut select: Vec<Expr> = df
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| Expr::Column(field.qualified_column()))
+        .collect();
+    let key_names = partitioning.key_names();
+    select.extend(
+        partitioning
+            .keys(&field)
+            .into_iter()
+            .zip(key_names)
+            .map(|(expr, name)| expr.alias(*name)),
+    );
+    let sort = key_names
+        .iter()
+        .copied()
+        .chain(options.sort_columns.iter().flatten().map(String::as_str))
+        .map(|column| col(column).sort(true, false))
+        .collect();
+    let df = df.select(select)?.sort(sort)?;
+
+    let props = match options.format {
+        OutputFormat::Parquet => Some(options.writer_properties()?),
+        OutputFormat::Csv | OutputFormat::Json | OutputFormat::ArrowIpc => None,
+    };
+    let mut stream = df.execute_stream().await?;
+    let schema = Arc::new(stream.schema().project(&columns)?);
+    let mut open: Option<PartitionFiles> = None;
+    let mut finished: Vec<WrittenPartition> = vec![];
+    let mut count = 0;
+    let mut new_partition = |key: Vec<Option<i32>>| -> Result<PartitionFiles> {
+        let temp_dir = format!(
+            "{}/partition-{}-temp.{}",
+            output_dir.display(),
+            count,
+            options.format.extension()
+        );
+        count += 1;
+        fs::create_dir_all(&temp_dir)?;
+        let writers = (0..partitions)
+            .map(|part| {
+                PartWriter::try_new(
+                    PathBuf::from(&temp_dir),
+                    part,
+                    options.format,
+                    Arc::clone(&schema),
+                    props.clone(),
+                    options.max_rows_per_file,
+                )
+            })
+            .collect::<Result<_>>()?;
+        let dir = partitioning.dir(&key);
+        debug!("Writing {} to {}", dir, temp_dir);
+        Ok(PartitionFiles {
+            key,
+            partition: WrittenPartition { dir, temp_dir },
+            writers,
+            next: 0,
+        })
+    };
+
+    let mut written = Written::default();
+    while let Some(batch) = next_batch(&mut stream, &mut written.timings).await? {
+        options.check_cancelled()?;
+        let start = Instant::now();
+        written.rows += batch.num_rows() as u64;
+        let values = batch.project(&columns)?;
+        for (key, indices) in group_rows(&batch, columns.len())? {
+            // the sorted rows of a new partition come after all of the rows of the last one
+            let files = match &mut open {
+                Some(files) if files.key == key => files,
+                _ => {
+                    if let Some(files) = open.take() {
+                        finished.push(files.close(&mut written)?);
+                    }
+                    open.insert(new_partition(key)?)
+                }
+            };
+            let rows = RecordBatch::try_new(
+                Arc::clone(&schema),
+                values
+                    .columns()
+                    .iter()
+                    .map(|column| take(column, &indices, None))
+                    .collect::<std::result::Result<_, _>>()?,
+            )?;
+            files.writers[files.next].write(rows)?;
+            files.next = (files.next + 1) % partitions;
+        }
+        written.timings.write += start.elapsed();
+    }
+    // an empty table still gets one directory of files, at the top of the output directory, so
+    // that readers can find its schema
+    if open.is_none() && finished.is_empty() {
+        open = Some(new_partition(vec![])?);
+    }
+    let start = Instant::now();
+    if let Some(files) = open {
+        finished.push(files.close(&mut written)?);
+    }
+    written.timings.write += start.elapsed();
+    Ok((finished, written))
+}
+
+impl PartitionFiles {
+    /// Close the files, adding their row counts to `written`
+    fn close(self, written: &mut Written) -> Result<WrittenPartition> {
+        for writer in self.writers {
+            written.file_rows.extend(writer.close()?);
+        }
+        Ok(self.partition)
+    }
+}
+
+/// Group the rows of a batch by the partition keys in its columns from `first_key`, in order of
+/// each key's first row
+fn group_rows(
+    batch: &RecordBatch,
+    first_key: usize,
+) -> Result<Vec<(Vec<Option<i32>>, UInt32Array)>> {
+    let keys = batch.columns()[first_key..]
+        .iter()
+        .map(|array| {
+            array
+                .as_any()
+                .downcast_ref::<Int32Array>()
+                .ok_or_else(|| TpcError::Conversion("Partition key is not Int32".to_string()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut groups: Vec<(Vec<Option<i32>>, Vec<u32>)> = vec![];
+    let mut group_of: HashMap<Vec<Option<i32>>, usize> = HashMap::new();
+    for row in 0..batch.num_rows() {
+        let key: Vec<Option<i32>> = keys
+            .iter()
+            .map(|array| (!array.is_null(row)).then(|| array.value(row)))
+            .collect();
+        let group = *group_of.entry(key.clone()).or_insert_with(|| {
+            groups.push((key, vec![]));
+            groups.len() - 1
+        });
+        groups[group].1.push(row as u32);
+    }
+    Ok(groups
+        .into_iter()
+        .map(|(key, rows)| (key, UInt32Array::from(rows)))
+        .collect())
+}
+
+/// Whether a column is a TPC-DS date key, such as `ss_sold_date_sk`
+fn is_date_key(field: &Field) -> bool {
+    field.data_type() == &DataType::Int32 && field.name().ends_with("_date_sk")
+}
@@ -0,0 +1,108 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Generating a benchmark's data and converting it in one step
+
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use tracing::info;
+
+use crate::error::{Result, TpcError};
+use crate::{
+    convert_to_parquet, generate, ConversionOptions, ConversionReport, GenerateOptions, Tpc,
+};
+
+/// Options for [`generate_and_convert`]
+#[derive(Debug, Clone, Default)]
+pub struct PipelineOptions {
+    pub generate: GenerateOptions,
+    /// Options for converting the generated data. The scale factor and number of partitions
+    /// are set from the generated data when they are not set.
+    pub convert: ConversionOptions,
+    /// Keep the generated files in the work directory after converting them, instead of
+    /// removing them
+    pub keep_generated: bool,
+}
+
+/// Generate a benchmark's data into `work_dir` and convert it to `output_path`, then remove the
+/// generated files of the converted tables unless `keep_generated` is set. The generated files
+/// are kept when the conversion fails or is a dry run, so that it can be run again with
+/// [`convert_to_parquet`]. The generator is run on a blocking thread with a reference to the
+/// benchmark.
+pub async fn generate_and_convert(
+    benchmark: Arc<dyn Tpc>,
+    scale: f64,
+    partitions: usize,
+    generator_path: &str,
+    work_dir: &str,
+    output_path: &str,
+    options: &PipelineOptions,
+) -> Result<ConversionReport> {
+    options.generate.validate()?;
+    options.convert.validate()?;
+    let tables = options.convert.selected_tables(benchmark.as_ref())?;
+    fs::create_dir_all(work_dir)?;
+    generate_blocking(
+        Arc::clone(&benchmark),
+        scale,
+        partitions,
+        generator_path,
+        work_dir,
+        &options.generate,
+    )
+    .await?;
+
+    let mut convert = options.convert.clone();
+    convert.scale_factor = convert.scale_factor.or(Some(scale));
+    convert.partitions = convert.partitions.or(Some(partitions));
+    let report = convert_to_parquet(benchmark.as_ref(), work_dir, output_path, &convert).await?;
+
+    if !options.keep_generated && !convert.dry_run {
+        for table in tables {
+            let dir = format!("{}/{}.{}", work_dir, table, benchmark.get_table_ext());
+            if Path::new(&dir).exists() {
+                info!("Removing generated files: {}", dir);
+                fs::remove_dir_all(&dir)?;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Run the generator, which blocks until it finishes, on a blocking thread so that it doesn't
+/// hold up the runtime
+async fn generate_blocking(
+    benchmark: Arc<dyn Tpc>,
+    scale: f64,
+    partitions: usize,
+    generator_path: &str,
+    work_dir: &str,
+    options: &GenerateOptions,
+) -> Result<()> {
+    let generator_path = generator_path.to_string();
+    let work_dir = work_dir.to_string();
+    let options = options.clone();
+    tokio::task::spawn_blocking(move || {
+        generate(
+            benchmark.as_ref(),
+            scale,
+            partitions,
+            &generator_path,
+            &work_dir,
+            &options,
+        )
+    })
+    .await
+    .map_err(|e| TpcError::Generator(format!("Generator task failed: {}", e)))?
+}
@@ -0,0 +1,51 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Progress events sent while converting tables
+
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{ConversionOptions, TableStats};
+
+/// Sends progress events as a conversion runs
+pub type ProgressSender = UnboundedSender<ProgressEvent>;
+
+/// Progress of a conversion, sent to [`ConversionOptions::progress`]
+#[derive(Debug, Clone)]
+pub enum ProgressEvent {
+    /// A table started converting
+    TableStarted {
+        table: String,
+        /// Number of input files
+        input_files: usize,
+    },
+    /// An output file of a table was written
+    FileConverted {
+        table: String,
+        /// Output file, relative to the output path
+        file: String,
+        rows: u64,
+        bytes: u64,
+    },
+    /// A table was skipped because a previous run already converted it
+    TableSkipped { table: String },
+    /// A table finished converting
+    TableFinished(TableStats),
+}
+
+/// Send an event if the options have a progress sender. Events are dropped once the receiver
+/// is closed, so a consumer that stops listening doesn't fail the conversion.
+pub(crate) fn send_progress(options: &ConversionOptions, event: ProgressEvent) {
+    if let Some(progress) = &options.progress {
+        let _ = progress.send(event);
+    }
+}
@@ -0,0 +1,72 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Running the benchmarks' standard queries against converted tables
+
+use std::sync::Arc;
+
+use datafusion::arrow::datatypes::Schema;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::DataFusionError;
+use datafusion::prelude::SessionContext;
+use datafusion::sql::parser::DFParser;
+use tracing::info;
+
+use crate::error::{Result, TpcError};
+use crate::{register_tables, OutputFormat, Tpc};
+
+/// Get the SQL of one of the benchmark's standard queries, numbered from 1 as in the
+/// specification, or `None` if the benchmark has no such query. Statements are separated by `;`.
+pub fn query_sql(benchmark: &dyn Tpc, query_id: usize) -> Option<&'static str> {
+    let queries = benchmark.queries();
+    query_id
+        .checked_sub(1)
+        .and_then(|i| queries.get(i))
+        .copied()
+}
+
+/// Register the converted Parquet tables under `data_path` and run one of the benchmark's
+/// standard queries, numbered from 1 as in the specification. The results of a query with more
+/// than one statement are returned one statement after another, and a statement without rows
+/// returns an empty batch with its schema.
+pub async fn run_query(
+    benchmark: &dyn Tpc,
+    data_path: &str,
+    query_id: usize,
+) -> Result<Vec<RecordBatch>> {
+    let sql = query_sql(benchmark, query_id).ok_or_else(|| {
+        TpcError::InvalidOption(format!(
+            "Invalid query for {}: {} (valid queries are 1-{})",
+            benchmark.get_name(),
+            query_id,
+            benchmark.queries().len()
+        ))
+    })?;
+    let ctx = SessionContext::new();
+    register_tables(&ctx, benchmark, data_path, OutputFormat::Parquet).await?;
+    info!("Running query {}", query_id);
+    let mut batches = vec![];
+    // a few queries, such as TPC-DS query 14, are made up of more than one statement
+    let statements = DFParser::parse_sql(sql).map_err(DataFusionError::from)?;
+    for statement in statements {
+        let plan = ctx.state().statement_to_plan(statement).await?;
+        let df = ctx.execute_logical_plan(plan).await?;
+        let schema = Arc::new(Schema::from(df.schema()));
+        let statement_batches = df.collect().await?;
+        // a statement without rows still returns its columns
+        if statement_batches.is_empty() {
+            batches.push(RecordBatch::new_empty(schema));
+        }
+        batches.extend(statement_batches);
+    }
+    Ok(batches)
+}
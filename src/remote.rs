@@ -0,0 +1,473 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Writing converted tables to object stores such as S3 and GCS, and reading input over HTTP
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use datafusion::arrow::csv::WriterBuilder;
+use datafusion::arrow::datatypes::{Schema, SchemaRef};
+use datafusion::arrow::ipc::writer::FileWriter;
+use datafusion::arrow::json::LineDelimitedWriter;
+use datafusion::datasource::object_store::ObjectStoreUrl;
+use datafusion::logical_expr::Partitioning;
+use datafusion::parquet::arrow::AsyncArrowWriter;
+use datafusion::physical_plan::SendableRecordBatchStream;
+use datafusion::prelude::*;
+use futures::{stream, StreamExt, TryStreamExt};
+#[cfg(feature = "s3")]
+use object_store::aws::AmazonS3Builder;
+#[cfg(feature = "gcs")]
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::local::LocalFileSystem;
+use object_store::path::Path as ObjectPath;
+use object_store::ObjectStore;
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+use url::Url;
+
+use crate::checksum::HashingWriter;
+use crate::error::{Result, TpcError};
+#[cfg(feature = "http")]
+use crate::http::HttpInputStore;
+use crate::progress::send_progress;
+use crate::sink::{finish_table_dir, prepare_table_dir, ObjectStoreSink};
+use crate::writer::{json_batch, next_batch, Timings};
+use crate::{
+    collect_converted, read_tbl, read_tbl_files, sort_rows, ConversionOptions, OutputFormat,
+    ProgressEvent, ResumeMode, TableStats,
+};
+
+/// Size of the buffer used to stage Parquet data before uploading it
+const BUFFER_SIZE: usize = 8 * 1024 * 1024;
+
+/// Parse a path as an object store URL, returning `None` for local paths
+pub(crate) fn parse_url(path: &str) -> Result<Option<Url>> {
+    if !path.contains("://") {
+        return Ok(None);
+    }
+    Url::parse(path)
+        .map(Some)
+        .map_err(|e| TpcError::InvalidOption(format!("Invalid URL {}: {}", path, e)))
+}
+
+/// Parse the input path as an HTTP or HTTPS URL, returning `None` for local paths. Input is
+/// not read from other object stores.
+pub(crate) fn parse_input_url(path: &str) -> Result<Option<Url>> {
+    match parse_url(path)? {
+        Some(url) if url.scheme() == "http" || url.scheme() == "https" => Ok(Some(url)),
+        Some(url) => Err(TpcError::InvalidOption(format!(
+            "Unsupported input URL: {}",
+            url
+        ))),
+        None => Ok(None),
+    }
+}
+
+/// Get the size of the object at a URL from the object store registered for it
+pub(crate) async fn object_size(ctx: &SessionContext, url: &Url) -> Result<u64> {
+    let store = ctx
+        .runtime_env()
+        .object_store(ObjectStoreUrl::parse(&url[..url::Position::BeforePath])?)?;
+    let path = ObjectPath::from_url_path(url.path())
+        .map_err(|e| TpcError::InvalidOption(format!("Invalid input URL: {}", e)))?;
+    let meta = store.head(&path).await?;
+    Ok(meta.size as u64)
+}
+
+/// Create the object store for a URL and register it with the context
+pub(crate) fn register_object_store(
+    ctx: &SessionContext,
+    url: &Url,
+) -> Result<Arc<dyn ObjectStore>> {
+    let store: Arc<dyn ObjectStore> = match url.scheme() {
+        "file" => Arc::new(LocalFileSystem::new()),
+        #[cfg(feature = "s3")]
+        "s3" => Arc::new(
+            AmazonS3Builder::from_env()
+                .with_bucket_name(url.host_str().unwrap_or_default())
+                .build()?,
+        ),
+        #[cfg(feature = "gcs")]
+        "gs" => Arc::new(
+            GoogleCloudStorageBuilder::from_env()
+                .with_bucket_name(url.host_str().unwrap_or_default())
+                .build()?,
+        ),
+        #[cfg(feature = "http")]
+        "http" | "https" => Arc::new(HttpInputStore::new(url)?),
+        other => {
+            return Err(TpcError::InvalidOption(format!(
+                "Unsupported object store: {}",
+                other
+            )))
+        }
+    };
+    ctx.runtime_env()
+        .register_object_store(url, Arc::clone(&store));
+    Ok(store)
+}
+
+/// Check whether `{output_url}/{name}` is a directory of objects rather than a single object
+pub(crate) async fn is_directory(output_url: &Url, name: &str) -> Result<bool> {
+    let ctx = SessionContext::new();
+    let store = register_object_store(&ctx, output_url)?;
+    let location = output_path(output_url)?.child(name);
+    let objects: Vec<_> = store.list(Some(&location)).await?.try_collect().await?;
+    Ok(objects.iter().any(|meta| meta.location != location))
+}
+
+/// Get the object store path of an output URL
+pub(crate) fn output_path(output_url: &Url) -> Result<ObjectPath> {
+    ObjectPath::from_url_path(output_url.path())
+        .map_err(|e| TpcError::InvalidOption(format!("Invalid output URL: {}", e)))
+}
+
+/// Get the URL of an object in the object store of an output URL, for messages
+pub(crate) fn object_url(output_url: &Url, location: &ObjectPath) -> String {
+    format!("{}/{}", &output_url[..url::Position::BeforePath], location)
+}
+
+/// Convert a table's input files and upload them directly to `{output_url}/{table}.{ext}`,
+/// without staging the output on local disk
+pub(crate) async fn convert_table(
+    ctx: &SessionContext,
+    table: &str,
+    files: &[PathBuf],
+    output_url: &Url,
+    csv_options: &CsvReadOptions<'_>,
+    options: &ConversionOptions,
+    start: Instant,
+) -> Result<Option<TableStats>> {
+    let format = options.format;
+    let store = register_object_store(ctx, output_url)?;
+
+    if options.single_file {
+        let location = output_path(output_url)?.child(format!("{}.{}", table, format.extension()));
+        let uploaded = match upload_table_file(
+            ctx,
+            &store,
+            files,
+            output_url,
+            &location,
+            csv_options,
+            options,
+        )
+        .await?
+        {
+            Some(uploaded) => uploaded,
+            None => return Ok(None),
+        };
+        let file = location.filename().unwrap_or_default().to_string();
+        file_progress(table, &file, &uploaded, options);
+        return Ok(Some(TableStats {
+            table: table.to_owned(),
+            rows: uploaded.rows,
+            bytes: uploaded.bytes,
+            input_bytes: 0,
+            files: vec![file],
+            elapsed: start.elapsed(),
+            read_time: uploaded.timings.read,
+            write_time: uploaded.timings.write,
+            malformed_rows: vec![],
+        }));
+    }
+
+    let sink = ObjectStoreSink::new(Arc::clone(&store), output_url)?;
+    let table_dir = format!("{}.{}", table, format.extension());
+    if !prepare_table_dir(&sink, &table_dir, options.resume_mode).await? {
+        return Ok(None);
+    }
+    let output_dir = output_path(output_url)?.child(table_dir.as_str());
+
+    let written = match options.table_target_partitions(table) {
+        Some(partitions) => {
+            // read all of the input and upload one object per partition
+            let df = sort_rows(read_tbl_files(ctx, files, csv_options).await?, options)?
+                .repartition(Partitioning::RoundRobinBatch(partitions))?;
+            let schema = Arc::new(Schema::from(df.schema()));
+            let partitions = df.execute_stream_partitioned().await?;
+            let converted = stream::iter(partitions.into_iter().enumerate())
+                .map(|(part, batches)| {
+                    let file_name = format!("part-{}.{}", part, format.extension());
+                    let location = output_dir.child(file_name.as_str());
+                    let store = &store;
+                    let schema = Arc::clone(&schema);
+                    async move {
+                        options.check_cancelled()?;
+                        info!("Writing {}", location);
+                        let uploaded =
+                            upload_batches(store, schema, batches, &location, options).await?;
+                        let file = format!("{}.{}/{}", table, format.extension(), file_name);
+                        file_progress(table, &file, &uploaded, options);
+                        Ok::<_, TpcError>(uploaded)
+                    }
+                })
+                .buffered(options.file_concurrency);
+            collect_converted(converted).await?
+        }
+        // each input file becomes one object, numbered in input order
+        None => {
+            let converted = stream::iter(files.iter().enumerate())
+                .map(|(part, file)| {
+                    let file_name = format!("part-{}.{}", part, format.extension());
+                    let location = output_dir.child(file_name.as_str());
+                    let store = &store;
+                    async move {
+                        options.check_cancelled()?;
+                        info!("Writing {}", location);
+                        let uploaded =
+                            upload_tbl(ctx, store, file, &location, csv_options, options).await?;
+                        let file = format!("{}.{}/{}", table, format.extension(), file_name);
+                        file_progress(table, &file, &uploaded, options);
+                        Ok::<_, TpcError>(uploaded)
+                    }
+                })
+                .buffered(options.file_concurrency);
+            collect_converted(converted).await?
+        }
+    };
+    let file_names: Vec<String> = (0..written.len())
+        .map(|part| format!("part-{}.{}", part, format.extension()))
+        .collect();
+
+    let checksums: Vec<_> = file_names
+        .iter()
+        .cloned()
+        .zip(written.iter().map(|file| file.sha256.clone()))
+        .collect();
+    let checksums = options.write_checksums.then_some(checksums.as_slice());
+    finish_table_dir(&sink, &table_dir, checksums, options.write_success_marker).await?;
+
+    let mut timings = Timings::default();
+    for file in &written {
+        timings += file.timings;
+    }
+    Ok(Some(TableStats {
+        table: table.to_owned(),
+        rows: written.iter().map(|file| file.rows).sum(),
+        bytes: written.iter().map(|file| file.bytes).sum(),
+        input_bytes: 0,
+        files: file_names
+            .iter()
+            .map(|file_name| format!("{}.{}/{}", table, format.extension(), file_name))
+            .collect(),
+        elapsed: start.elapsed(),
+        read_time: timings.read,
+        write_time: timings.write,
+        malformed_rows: vec![],
+    }))
+}
+
+/// Convert a table's input files and upload them to a single object, returning `None` if the
+/// table was skipped
+async fn upload_table_file(
+    ctx: &SessionContext,
+    store: &Arc<dyn ObjectStore>,
+    files: &[PathBuf],
+    output_url: &Url,
+    location: &ObjectPath,
+    csv_options: &CsvReadOptions<'_>,
+    options: &ConversionOptions,
+) -> Result<Option<UploadedFile>> {
+    // multipart uploads only become visible once complete, so an existing object is never
+    // partial
+    match store.head(location).await {
+        Ok(_) => match options.resume_mode {
+            ResumeMode::Fail => {
+                return Err(TpcError::Conversion(format!(
+                    "Output file already exists: {}",
+                    object_url(output_url, location)
+                )));
+            }
+            ResumeMode::Skip => {
+                info!("Skipping table which is already converted");
+                return Ok(None);
+            }
+            ResumeMode::Overwrite => {
+                info!("Replacing existing output: {}", location);
+            }
+        },
+        Err(object_store::Error::NotFound { .. }) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    info!("Writing {}", location);
+    let df = sort_rows(read_tbl_files(ctx, files, csv_options).await?, options)?;
+    let schema = Arc::new(Schema::from(df.schema()));
+    let batches = df.execute_stream().await?;
+    upload_batches(store, schema, batches, location, options)
+        .await
+        .map(Some)
+}
+
+/// Details of an uploaded object
+struct UploadedFile {
+    rows: u64,
+    bytes: u64,
+    sha256: String,
+    timings: Timings,
+}
+
+/// Send the progress event for an uploaded object
+fn file_progress(table: &str, file: &str, uploaded: &UploadedFile, options: &ConversionOptions) {
+    send_progress(
+        options,
+        ProgressEvent::FileConverted {
+            table: table.to_owned(),
+            file: file.to_owned(),
+            rows: uploaded.rows,
+            bytes: uploaded.bytes,
+        },
+    );
+}
+
+/// Convert a single input file and stream the output to one object
+async fn upload_tbl(
+    ctx: &SessionContext,
+    store: &Arc<dyn ObjectStore>,
+    input_path: &Path,
+    location: &ObjectPath,
+    csv_options: &CsvReadOptions<'_>,
+    options: &ConversionOptions,
+) -> Result<UploadedFile> {
+    let start = Instant::now();
+
+    let df = sort_rows(read_tbl(ctx, input_path, csv_options).await?, options)?;
+    let schema = Arc::new(Schema::from(df.schema()));
+    let batches = df.execute_stream().await?;
+    let uploaded = upload_batches(store, schema, batches, location, options).await?;
+
+    info!(
+        "Converted '{}' in {} ms",
+        input_path.display(),
+        start.elapsed().as_millis()
+    );
+    Ok(uploaded)
+}
+
+/// Write a stream of record batches to one object
+async fn upload_batches(
+    store: &Arc<dyn ObjectStore>,
+    schema: SchemaRef,
+    mut batches: SendableRecordBatchStream,
+    location: &ObjectPath,
+    options: &ConversionOptions,
+) -> Result<UploadedFile> {
+    let mut rows = 0;
+    let mut timings = Timings::default();
+    let (multipart_id, writer) = store.put_multipart(location).await?;
+    let mut writer = HashingWriter::new(writer);
+    let result = async {
+        match options.format {
+            OutputFormat::Csv => {
+                let mut has_headers = true;
+                while let Some(batch) = next_batch(&mut batches, &mut timings).await? {
+                    let start = Instant::now();
+                    rows += batch.num_rows() as u64;
+                    let mut buffer = Vec::new();
+                    WriterBuilder::new()
+                        .has_headers(has_headers)
+                        .build(&mut buffer)
+                        .write(&batch)?;
+                    has_headers = false;
+                    writer.write_all(&buffer).await?;
+                    timings.write += start.elapsed();
+                }
+            }
+            OutputFormat::Json => {
+                while let Some(batch) = next_batch(&mut batches, &mut timings).await? {
+                    let start = Instant::now();
+                    rows += batch.num_rows() as u64;
+                    let mut json_writer = LineDelimitedWriter::new(Vec::new());
+                    json_writer.write(json_batch(&batch)?)?;
+                    json_writer.finish()?;
+                    writer.write_all(&json_writer.into_inner()).await?;
+                    timings.write += start.elapsed();
+                }
+            }
+            OutputFormat::ArrowIpc => {
+                let buffer = SharedBuffer::default();
+                let mut ipc_writer = FileWriter::try_new(buffer.clone(), &schema)?;
+                while let Some(batch) = next_batch(&mut batches, &mut timings).await? {
+                    let start = Instant::now();
+                    rows += batch.num_rows() as u64;
+                    ipc_writer.write(&batch)?;
+                    writer.write_all(&buffer.take()).await?;
+                    timings.write += start.elapsed();
+                }
+                let start = Instant::now();
+                ipc_writer.finish()?;
+                writer.write_all(&buffer.take()).await?;
+                timings.write += start.elapsed();
+            }
+            OutputFormat::Parquet => {
+                let props = options.writer_properties()?;
+                let mut parquet_writer =
+                    AsyncArrowWriter::try_new(&mut writer, schema, BUFFER_SIZE, Some(props))?;
+                while let Some(batch) = next_batch(&mut batches, &mut timings).await? {
+                    let start = Instant::now();
+                    rows += batch.num_rows() as u64;
+                    parquet_writer.write(&batch).await?;
+                    timings.write += start.elapsed();
+                }
+                let start = Instant::now();
+                parquet_writer.close().await?;
+                timings.write += start.elapsed();
+            }
+        }
+        // completes the multipart upload
+        let start = Instant::now();
+        writer.shutdown().await?;
+        timings.write += start.elapsed();
+        Ok::<_, TpcError>(())
+    }
+    .await;
+
+    if result.is_err() {
+        store.abort_multipart(location, &multipart_id).await?;
+    }
+    result?;
+
+    let bytes = store.head(location).await?.size as u64;
+    Ok(UploadedFile {
+        rows,
+        bytes,
+        sha256: writer.sha256(),
+        timings,
+    })
+}
+
+/// Buffer that a synchronous writer writes into while its output is taken and uploaded
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    /// Take the bytes written so far
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
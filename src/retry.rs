@@ -0,0 +1,154 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Retrying file operations that fail transiently, as they can on network file systems
+
+use std::io::{self, ErrorKind};
+use std::thread;
+use std::time::Duration;
+
+use tracing::warn;
+
+use crate::error::{Result, TpcError};
+
+/// How to retry moving and copying files when they fail with a transient error, such as
+/// `EAGAIN` or `EBUSY`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of times to try each operation, including the first
+    pub max_attempts: usize,
+    /// Time to wait before the first retry, which doubles before each retry after it
+    pub initial_backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: usize, initial_backoff: Duration) -> Self {
+        Self {
+            max_attempts,
+            initial_backoff,
+        }
+    }
+
+    /// Check that the policy is usable before any work is done
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.max_attempts == 0 {
+            return Err(TpcError::InvalidOption(
+                "IO retry attempts must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Run a file operation, retrying it according to the policy while it fails with a transient
+/// error. Without a policy it is only tried once. It sleeps between attempts, so async code
+/// should call it from a blocking task.
+pub(crate) fn with_retry<T>(
+    policy: Option<&RetryPolicy>,
+    description: impl Fn() -> String,
+    mut op: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let max_attempts = policy.map_or(1, |policy| policy.max_attempts);
+    let mut backoff = policy.map_or(Duration::ZERO, |policy| policy.initial_backoff);
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Err(e) if attempt < max_attempts && is_transient(&e) => {
+                warn!(
+                    "{} failed on attempt {} of {}, retrying in {:?}: {}",
+                    description(),
+                    attempt,
+                    max_attempts,
+                    backoff,
+                    e
+                );
+                thread::sleep(backoff);
+                backoff = backoff.saturating_mul(2);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+            | ErrorKind::ResourceBusy
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run an operation that fails with `ResourceBusy` a number of times before succeeding,
+    /// returning its result and the number of attempts
+    fn run_failing(policy: Option<&RetryPolicy>, failures: usize) -> (io::Result<usize>, usize) {
+        let mut attempts = 0;
+        let result = with_retry(
+            policy,
+            || "Test operation".to_string(),
+            || {
+                attempts += 1;
+                if attempts <= failures {
+                    Err(io::Error::from(ErrorKind::ResourceBusy))
+                } else {
+                    Ok(attempts)
+                }
+            },
+        );
+        (result, attempts)
+    }
+
+    #[test]
+    fn transient_failures_are_retried() {
+        let policy = RetryPolicy::new(4, Duration::from_millis(1));
+        let (result, attempts) = run_failing(Some(&policy), 3);
+        assert_eq!(result.unwrap(), 4);
+        assert_eq!(attempts, 4);
+    }
+
+    #[test]
+    fn retries_stop_after_the_last_attempt() {
+        let policy = RetryPolicy::new(3, Duration::from_millis(1));
+        let (result, attempts) = run_failing(Some(&policy), 3);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ResourceBusy);
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn operations_are_tried_once_without_a_policy() {
+        let (result, attempts) = run_failing(None, 1);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ResourceBusy);
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn other_errors_are_not_retried() {
+        let policy = RetryPolicy::new(3, Duration::from_millis(1));
+        let mut attempts = 0;
+        let result: io::Result<()> = with_retry(
+            Some(&policy),
+            || "Test operation".to_string(),
+            || {
+                attempts += 1;
+                Err(io::Error::from(ErrorKind::NotFound))
+            },
+        );
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(attempts, 1);
+    }
+}
@@ -0,0 +1,181 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Table schemas that replace the benchmark's own, for input from nonstandard generators
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+use datafusion::arrow::datatypes::Schema;
+use flate2::read::GzDecoder;
+
+use crate::error::{Result, TpcError};
+
+/// Load schema overrides from a JSON object with a schema for each table, each in the format
+/// written by `write_schemas_json`, such as `{"nation": {"fields": [...]}}`
+pub fn load_schema_overrides(path: &Path) -> Result<HashMap<String, Schema>> {
+    if !path.exists() {
+        return Err(TpcError::MissingPath {
+            description: "Schema overrides file".to_string(),
+            path: path.to_path_buf(),
+        });
+    }
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+/// Check that the first line of a table's first non-empty input file has a field for each
+/// column of the table's schema
+pub(crate) fn check_field_count(
+    table: &str,
+    schema: &Schema,
+    files: &[impl AsRef<Path>],
+    delimiter: u8,
+    trailing_delimiter: bool,
+) -> Result<()> {
+    for file in files {
+        let file = file.as_ref();
+        let line = match first_line(file)? {
+            Some(line) => line,
+            None => continue,
+        };
+        let mut fields = line.split(delimiter as char).count();
+        if trailing_delimiter && line.ends_with(delimiter as char) {
+            fields -= 1;
+        }
+        if fields != schema.fields().len() {
+            return Err(TpcError::InvalidOption(format!(
+                "Schema for table {} has {} columns but {} has {} fields",
+                table,
+                schema.fields().len(),
+                file.display(),
+                fields
+            )));
+        }
+        return Ok(());
+    }
+    Ok(())
+}
+
+/// Detect the field delimiter of a table's input from the first lines of its first non-empty
+/// file. Lines containing a pipe are pipe-delimited, and otherwise tabs or commas are used if
+/// every sampled line has the same number of them. Returns `None` if no delimiter is found.
+pub(crate) fn detect_delimiter(files: &[impl AsRef<Path>]) -> Result<Option<u8>> {
+    for file in files {
+        let lines = first_lines(file.as_ref(), DELIMITER_SAMPLE_LINES)?;
+        if lines.is_empty() {
+            continue;
+        }
+        if lines[0].contains('|') {
+            return Ok(Some(b'|'));
+        }
+        for delimiter in ['\t', ','] {
+            let count = lines[0].matches(delimiter).count();
+            if count > 0
+                && lines
+                    .iter()
+                    .all(|line| line.matches(delimiter).count() == count)
+            {
+                return Ok(Some(delimiter as u8));
+            }
+        }
+        return Ok(None);
+    }
+    Ok(None)
+}
+
+/// Number of lines sampled when detecting a delimiter
+const DELIMITER_SAMPLE_LINES: usize = 10;
+
+/// Read the first line of a file that may be gzip compressed, or `None` if it is empty
+fn first_line(path: &Path) -> Result<Option<String>> {
+    Ok(first_lines(path, 1)?.into_iter().next())
+}
+
+/// Read up to `count` lines from the start of a file that may be gzip compressed, stopping at
+/// the first empty line
+fn first_lines(path: &Path, count: usize) -> Result<Vec<String>> {
+    let file = File::open(path)?;
+    let reader: Box<dyn Read> = if path.extension() == Some(OsStr::new("gz")) {
+        Box::new(GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    let mut reader = BufReader::new(reader);
+    let mut lines = vec![];
+    while lines.len() < count {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let line = line.trim_end_matches(&['\r', '\n'][..]);
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line.to_string());
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ConversionOptions;
+
+    /// Write an input file into a directory, returning its path
+    fn write_input(dir: &tempfile::TempDir, name: &str, contents: &str) -> std::path::PathBuf {
+        let file = dir.path().join(name);
+        fs::write(&file, contents).unwrap();
+        file
+    }
+
+    #[test]
+    fn pipe_tab_and_comma_delimiters_are_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        for (contents, expected) in [
+            ("1|a,b|x\n2|c|\n", b'|'),
+            ("1\ta\tx\n2\tb\ty\n", b'\t'),
+            ("1,a,x\n2,b,y\n", b','),
+        ] {
+            let file = write_input(&dir, "nation.tbl", contents);
+            assert_eq!(detect_delimiter(&[file]).unwrap(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn empty_files_are_passed_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let empty = write_input(&dir, "nation.tbl.1", "");
+        let file = write_input(&dir, "nation.tbl.2", "1,a\n");
+        assert_eq!(detect_delimiter(&[empty, file]).unwrap(), Some(b','));
+    }
+
+    #[test]
+    fn inconsistent_counts_detect_no_delimiter() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = write_input(&dir, "nation.tbl", "1,a,x\n2,b\n3\tc,y\n");
+        assert_eq!(detect_delimiter(&[file]).unwrap(), None);
+        let file = write_input(&dir, "region.tbl", "no delimiters\n");
+        assert_eq!(detect_delimiter(&[file]).unwrap(), None);
+    }
+
+    #[test]
+    fn explicit_delimiter_turns_detection_off() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = vec![write_input(&dir, "nation.tbl", "1\ta\tx\n2\tb\ty\n")];
+        let options = ConversionOptions::default();
+        assert_eq!(options.input_delimiter("nation", &files).unwrap(), b'\t');
+        let options = ConversionOptions::builder().delimiter(b',').build();
+        assert_eq!(options.input_delimiter("nation", &files).unwrap(), b',');
+    }
+}
@@ -0,0 +1,319 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Destinations for converted output, so that moving files into place, writing marker files,
+//! and cleaning up work the same way on local disk and in object stores
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use datafusion::prelude::SessionContext;
+use futures::TryStreamExt;
+use object_store::path::Path as ObjectPath;
+use object_store::ObjectStore;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+use tracing::{debug, info};
+use url::Url;
+
+use crate::checksum::{format_checksums, CHECKSUM_FILE};
+use crate::error::{Result, TpcError};
+use crate::{move_or_copy, remote, ResumeMode, RetryPolicy, SUCCESS_MARKER};
+
+/// Size of the chunks that staged files are uploaded in
+const UPLOAD_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Limit on the number of output files moved or copied into place at the same time, which is
+/// shared by every clone so that the tables converted at the same time share it
+#[derive(Debug, Clone)]
+pub struct IoLimit {
+    permits: usize,
+    semaphore: Arc<Semaphore>,
+}
+
+impl IoLimit {
+    pub fn new(permits: usize) -> Self {
+        Self {
+            permits,
+            semaphore: Arc::new(Semaphore::new(permits)),
+        }
+    }
+
+    /// Get the maximum number of files moved or copied at the same time
+    pub fn permits(&self) -> usize {
+        self.permits
+    }
+}
+
+/// Where a conversion's output is finalized. Paths are relative to the root of the sink and
+/// use `/` as the separator.
+#[async_trait]
+pub(crate) trait OutputSink: Send + Sync {
+    /// Describe a path for messages, such as `/data/nation.parquet`
+    fn describe(&self, path: &str) -> String;
+
+    /// Check whether a file exists at a path, or a directory with anything in it
+    async fn exists(&self, path: &str) -> Result<bool>;
+
+    /// Create a directory and its parents, which object stores don't need
+    async fn create_dir(&self, path: &str) -> Result<()>;
+
+    /// Move a complete file from local disk to a path, so that it never appears partially
+    /// written
+    async fn publish(&self, staged: &Path, path: &str) -> Result<()>;
+
+    /// Write a small file, such as a marker or checksums
+    async fn put(&self, path: &str, data: Vec<u8>) -> Result<()>;
+
+    /// Remove a file or a directory and everything in it, if it exists
+    async fn remove_all(&self, path: &str) -> Result<()>;
+}
+
+/// Output in a directory on local disk
+pub(crate) struct LocalSink {
+    root: PathBuf,
+    /// Policy for retrying files that fail to be published
+    retry: Option<RetryPolicy>,
+    io_limit: Option<IoLimit>,
+}
+
+impl LocalSink {
+    pub(crate) fn new(root: impl Into<PathBuf>, retry: Option<RetryPolicy>) -> Self {
+        Self {
+            root: root.into(),
+            retry,
+            io_limit: None,
+        }
+    }
+
+    /// Limit the number of files published at the same time with this and other sinks
+    pub(crate) fn with_io_limit(mut self, io_limit: Option<IoLimit>) -> Self {
+        self.io_limit = io_limit;
+        self
+    }
+
+    fn path(&self, path: &str) -> PathBuf {
+        self.root.join(path)
+    }
+}
+
+#[async_trait]
+impl OutputSink for LocalSink {
+    fn describe(&self, path: &str) -> String {
+        self.path(path).display().to_string()
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool> {
+        Ok(self.path(path).exists())
+    }
+
+    async fn create_dir(&self, path: &str) -> Result<()> {
+        let dir = self.path(path);
+        debug!("Creating directory: {}", dir.display());
+        fs::create_dir_all(dir)?;
+        Ok(())
+    }
+
+    async fn publish(&self, staged: &Path, path: &str) -> Result<()> {
+        let _permit = match &self.io_limit {
+            Some(io_limit) => Some(
+                io_limit
+                    .semaphore
+                    .acquire()
+                    .await
+                    .map_err(|e| TpcError::Conversion(e.to_string()))?,
+            ),
+            None => None,
+        };
+        // a rename when the staged file is on the same device, which keeps local output fast.
+        // Copies and retry backoff block, so they run off the runtime's threads.
+        let staged = staged.to_path_buf();
+        let dest = self.path(path);
+        let retry = self.retry;
+        tokio::task::spawn_blocking(move || move_or_copy(&staged, &dest, retry.as_ref()))
+            .await
+            .map_err(|e| TpcError::Conversion(format!("Publishing task failed: {}", e)))??;
+        Ok(())
+    }
+
+    async fn put(&self, path: &str, data: Vec<u8>) -> Result<()> {
+        fs::write(self.path(path), data)?;
+        Ok(())
+    }
+
+    async fn remove_all(&self, path: &str) -> Result<()> {
+        let path = self.path(path);
+        if path.is_dir() {
+            fs::remove_dir_all(path)?;
+        } else if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Output under a prefix of an object store
+pub(crate) struct ObjectStoreSink {
+    store: Arc<dyn ObjectStore>,
+    /// Output URL, which paths are relative to
+    url: Url,
+    root: ObjectPath,
+}
+
+impl ObjectStoreSink {
+    pub(crate) fn new(store: Arc<dyn ObjectStore>, url: &Url) -> Result<Self> {
+        Ok(Self {
+            store,
+            url: url.clone(),
+            root: remote::output_path(url)?,
+        })
+    }
+
+    fn location(&self, path: &str) -> ObjectPath {
+        path.split('/')
+            .filter(|part| !part.is_empty())
+            .fold(self.root.clone(), |location, part| location.child(part))
+    }
+
+    /// Upload a local file with a multipart upload, which is aborted if the upload fails
+    async fn upload(&self, staged: &Path, location: &ObjectPath) -> Result<()> {
+        let (multipart_id, mut writer) = self.store.put_multipart(location).await?;
+        let result = async {
+            let mut file = File::open(staged)?;
+            let mut buffer = vec![0; UPLOAD_CHUNK_SIZE];
+            loop {
+                let len = file.read(&mut buffer)?;
+                if len == 0 {
+                    break;
+                }
+                writer.write_all(&buffer[..len]).await?;
+            }
+            writer.shutdown().await?;
+            Ok::<_, TpcError>(())
+        }
+        .await;
+        if result.is_err() {
+            self.store.abort_multipart(location, &multipart_id).await?;
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl OutputSink for ObjectStoreSink {
+    fn describe(&self, path: &str) -> String {
+        remote::object_url(&self.url, &self.location(path))
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool> {
+        let location = self.location(path);
+        if self.store.head(&location).await.is_ok() {
+            return Ok(true);
+        }
+        let mut objects = self.store.list(Some(&location)).await?;
+        Ok(objects.try_next().await?.is_some())
+    }
+
+    async fn create_dir(&self, _path: &str) -> Result<()> {
+        Ok(())
+    }
+
+    async fn publish(&self, staged: &Path, path: &str) -> Result<()> {
+        let location = self.location(path);
+        debug!("Uploading {} to {}", staged.display(), location);
+        self.upload(staged, &location).await?;
+        fs::remove_file(staged)?;
+        Ok(())
+    }
+
+    async fn put(&self, path: &str, data: Vec<u8>) -> Result<()> {
+        self.store.put(&self.location(path), data.into()).await?;
+        Ok(())
+    }
+
+    async fn remove_all(&self, path: &str) -> Result<()> {
+        let location = self.location(path);
+        let objects: Vec<_> = self
+            .store
+            .list(Some(&location))
+            .await?
+            .try_collect()
+            .await?;
+        for meta in objects {
+            self.store.delete(&meta.location).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Create the sink for a local output path or an object store URL
+pub(crate) fn output_sink(ctx: &SessionContext, output_path: &str) -> Result<Box<dyn OutputSink>> {
+    Ok(match remote::parse_url(output_path)? {
+        Some(url) => Box::new(ObjectStoreSink::new(
+            remote::register_object_store(ctx, &url)?,
+            &url,
+        )?),
+        None => Box::new(LocalSink::new(output_path, None)),
+    })
+}
+
+/// Prepare a table's output directory according to the resume mode, returning `false` if the
+/// table was already converted and should be skipped
+pub(crate) async fn prepare_table_dir(
+    sink: &dyn OutputSink,
+    dir: &str,
+    resume_mode: ResumeMode,
+) -> Result<bool> {
+    if sink.exists(dir).await? {
+        match resume_mode {
+            ResumeMode::Fail => {
+                return Err(TpcError::Conversion(format!(
+                    "Output directory already exists: {}",
+                    sink.describe(dir)
+                )));
+            }
+            ResumeMode::Skip if sink.exists(&format!("{}/{}", dir, SUCCESS_MARKER)).await? => {
+                info!("Skipping table which is already converted");
+                return Ok(false);
+            }
+            _ => {
+                info!("Removing existing output: {}", sink.describe(dir));
+                sink.remove_all(dir).await?;
+            }
+        }
+    }
+    sink.create_dir(dir).await?;
+    Ok(true)
+}
+
+/// Write a table's checksums, if any, and when `success_marker` is set the marker that shows
+/// the table is complete so that it can be skipped when resuming
+pub(crate) async fn finish_table_dir(
+    sink: &dyn OutputSink,
+    dir: &str,
+    checksums: Option<&[(String, String)]>,
+    success_marker: bool,
+) -> Result<()> {
+    if let Some(checksums) = checksums {
+        let path = format!("{}/{}", dir, CHECKSUM_FILE);
+        sink.put(&path, format_checksums(checksums).into_bytes())
+            .await?;
+    }
+    if success_marker {
+        sink.put(&format!("{}/{}", dir, SUCCESS_MARKER), Vec::new())
+            .await?;
+    }
+    Ok(())
+}
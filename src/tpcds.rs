@@ -13,13 +13,19 @@
 use async_trait::async_trait;
 use datafusion::arrow::datatypes::{DataType, Field, Schema};
 use std::fs;
-use std::io::Result;
 use std::path::Path;
 use std::process::Command;
-use std::thread;
 use std::time::Instant;
+use tracing::{debug, info};
 
-use crate::{move_or_copy, Tpc};
+use crate::error::{Result, TpcError};
+use crate::generate::{check_scale, find_generator, run_generators};
+use crate::{move_or_copy, unknown_table, GenerateOptions, Tpc};
+
+/// Scale factors allowed by the TPC-DS specification
+const SCALE_FACTORS: [f64; 9] = [
+    1.0, 10.0, 100.0, 300.0, 1000.0, 3000.0, 10000.0, 30000.0, 100000.0,
+];
 
 pub struct TpcDs {}
 
@@ -29,75 +35,111 @@ impl TpcDs {
     }
 }
 
+impl Default for TpcDs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[async_trait]
 impl Tpc for TpcDs {
     fn generate(
         &self,
-        scale: usize,
+        scale: f64,
         partitions: usize,
         generator_path: &str,
         output_path: &str,
+        options: &GenerateOptions,
     ) -> Result<()> {
-        let mut handles = vec![];
+        options.validate()?;
+        self.validate_scale(scale)?;
+        check_scale("TPC-DS", scale, &SCALE_FACTORS, options)?;
+        let dsdgen = find_generator(generator_path, "dsdgen", "tpcds.idx")?;
+
+        // dsdgen runs from its own directory, so the output path must be absolute
+        if !Path::new(output_path).exists() {
+            debug!("Creating directory {}", output_path);
+            fs::create_dir_all(output_path)?;
+        }
+        let output_path = fs::canonicalize(output_path)?
+            .to_string_lossy()
+            .into_owned();
 
         let start = Instant::now();
 
+        let mut commands = vec![];
         for i in 1..=partitions {
-            let generator_path = generator_path.to_owned();
-            let output_path = output_path.to_owned();
-            let scale = scale;
-            let partitions = partitions;
-            handles.push(thread::spawn(move || {
-                let output = Command::new("./dsdgen")
-                    .current_dir(generator_path.clone())
-                    .arg("-FORCE")
-                    .arg("-DIR")
-                    .arg(output_path)
-                    .arg("-SCALE")
-                    .arg(format!("{}", scale))
+            let mut command = Command::new(&dsdgen);
+            command
+                .current_dir(generator_path)
+                .arg("-FORCE")
+                .arg("-DIR")
+                .arg(&output_path)
+                .arg("-SCALE")
+                .arg(format!("{}", scale));
+            // dsdgen only accepts -PARALLEL with more than one partition
+            if partitions > 1 {
+                command
                     .arg("-CHILD")
                     .arg(format!("{}", i))
                     .arg("-PARALLEL")
-                    .arg(format!("{}", partitions))
-                    .output();
-
-                match output {
-                    Ok(x) => println!("{:?}", x),
-                    Err(e) => println!(
-                        "Failed to build dsdgen command with path {}: {:?}",
-                        generator_path, e
-                    ),
-                }
-            }));
-        }
-
-        // wait for all threads to finish
-        for h in handles {
-            h.join().unwrap();
+                    .arg(format!("{}", partitions));
+            }
+            commands.push((format!("partition {} of {}", i, partitions), command));
         }
+        run_generators(commands, options.concurrency)?;
 
         let duration = start.elapsed();
 
-        println!(
+        info!(
             "Generated TPC-DS data at scale factor {} with {} partitions in: {:?}",
             scale, partitions, duration
         );
 
         let tables = self.get_table_names();
 
+        let mut missing = vec![];
         for table in &tables {
             let output_dir = format!("{}/{}.dat", output_path, table);
+            // without partitions, dsdgen writes each table to the path of its output directory
+            let unpartitioned = Path::new(&output_dir);
+            let unpartitioned_temp = format!("{}.tmp", output_dir);
+            if unpartitioned.is_file() {
+                fs::rename(unpartitioned, &unpartitioned_temp)?;
+            }
             if !Path::new(&output_dir).exists() {
-                println!("Creating directory {}", output_dir);
+                debug!("Creating directory {}", output_dir);
                 fs::create_dir(&output_dir)?;
             }
+            if Path::new(&unpartitioned_temp).exists() {
+                let filename2 = format!("{}/part-1.dat", output_dir);
+                move_or_copy(
+                    Path::new(&unpartitioned_temp),
+                    Path::new(&filename2),
+                    options.io_retry.as_ref(),
+                )?;
+            }
             for i in 1..=partitions {
                 let filename = format!("{}/{}_{}_{}.dat", output_path, table, i, partitions);
                 let filename2 = format!("{}/part-{}.dat", output_dir, i);
                 if Path::new(&filename).exists() {
-                    move_or_copy(&Path::new(&filename), &Path::new(&filename2))?;
+                    move_or_copy(
+                        Path::new(&filename),
+                        Path::new(&filename2),
+                        options.io_retry.as_ref(),
+                    )?;
                 }
             }
+            if fs::read_dir(&output_dir)?.next().is_none() {
+                missing.push(*table);
+            }
+        }
+
+        if !missing.is_empty() {
+            return Err(TpcError::Generator(format!(
+                "dsdgen did not generate data for tables: {}",
+                missing.join(", ")
+            )));
         }
 
         Ok(())
@@ -132,8 +174,31 @@ impl Tpc for TpcDs {
         ]
     }
 
-    fn get_schema(&self, table: &str) -> Schema {
-        match table {
+    fn estimated_row_count(&self, table: &str, scale: f64) -> Option<u64> {
+        self.validate_scale(scale).ok()?;
+        let scaled = |rows: u64| (rows as f64 * scale).round() as u64;
+        match self.resolve_table_name(table)? {
+            "date_dim" => Some(73_049),
+            "time_dim" => Some(86_400),
+            "customer_demographics" => Some(1_920_800),
+            "household_demographics" => Some(7_200),
+            "income_band" => Some(20),
+            "ship_mode" => Some(20),
+            // the fact tables grow linearly with the scale factor
+            "store_sales" => Some(scaled(2_880_000)),
+            "catalog_sales" => Some(scaled(1_440_000)),
+            "web_sales" => Some(scaled(720_000)),
+            "store_returns" => Some(scaled(288_000)),
+            "catalog_returns" => Some(scaled(144_000)),
+            "web_returns" => Some(scaled(72_000)),
+            // the dimension tables grow sublinearly, and inventory depends on the number of
+            // items and warehouses
+            _ => None,
+        }
+    }
+
+    fn get_schema(&self, table: &str) -> Result<Schema> {
+        let schema = match self.resolve_table_name(table).unwrap_or(table) {
             "customer_address" => Schema::new(vec![
                 Field::new("ca_address_sk", DataType::Int32, false),
                 Field::new("ca_address_id", DataType::Utf8, false),
@@ -353,7 +418,7 @@ impl Tpc for TpcDs {
                 Field::new("c_birth_country", DataType::Utf8, true),
                 Field::new("c_login", DataType::Utf8, true),
                 Field::new("c_email_address", DataType::Utf8, true),
-                Field::new("c_last_review_date_sk", DataType::Utf8, true),
+                Field::new("c_last_review_date_sk", DataType::Int32, true),
             ]),
 
             "web_site" => Schema::new(vec![
@@ -631,15 +696,191 @@ impl Tpc for TpcDs {
                 Field::new("ss_net_profit", make_decimal_type(7, 2), true),
             ]),
 
-            _ => panic!(),
-        }
+            _ => return Err(unknown_table(table, &self.get_table_names())),
+        };
+        Ok(schema)
     }
 
     fn get_table_ext(&self) -> &str {
         "dat"
     }
+
+    fn get_name(&self) -> &str {
+        "tpcds"
+    }
+
+    fn queries(&self) -> &[&'static str] {
+        &QUERIES
+    }
 }
 
+/// The TPC-DS queries, with the substitution parameters that the specification uses to
+/// validate query results
+const QUERIES: [&str; 99] = [
+    include_str!("../queries/tpcds/q1.sql"),
+    include_str!("../queries/tpcds/q2.sql"),
+    include_str!("../queries/tpcds/q3.sql"),
+    include_str!("../queries/tpcds/q4.sql"),
+    include_str!("../queries/tpcds/q5.sql"),
+    include_str!("../queries/tpcds/q6.sql"),
+    include_str!("../queries/tpcds/q7.sql"),
+    include_str!("../queries/tpcds/q8.sql"),
+    include_str!("../queries/tpcds/q9.sql"),
+    include_str!("../queries/tpcds/q10.sql"),
+    include_str!("../queries/tpcds/q11.sql"),
+    include_str!("../queries/tpcds/q12.sql"),
+    include_str!("../queries/tpcds/q13.sql"),
+    include_str!("../queries/tpcds/q14.sql"),
+    include_str!("../queries/tpcds/q15.sql"),
+    include_str!("../queries/tpcds/q16.sql"),
+    include_str!("../queries/tpcds/q17.sql"),
+    include_str!("../queries/tpcds/q18.sql"),
+    include_str!("../queries/tpcds/q19.sql"),
+    include_str!("../queries/tpcds/q20.sql"),
+    include_str!("../queries/tpcds/q21.sql"),
+    include_str!("../queries/tpcds/q22.sql"),
+    include_str!("../queries/tpcds/q23.sql"),
+    include_str!("../queries/tpcds/q24.sql"),
+    include_str!("../queries/tpcds/q25.sql"),
+    include_str!("../queries/tpcds/q26.sql"),
+    include_str!("../queries/tpcds/q27.sql"),
+    include_str!("../queries/tpcds/q28.sql"),
+    include_str!("../queries/tpcds/q29.sql"),
+    include_str!("../queries/tpcds/q30.sql"),
+    include_str!("../queries/tpcds/q31.sql"),
+    include_str!("../queries/tpcds/q32.sql"),
+    include_str!("../queries/tpcds/q33.sql"),
+    include_str!("../queries/tpcds/q34.sql"),
+    include_str!("../queries/tpcds/q35.sql"),
+    include_str!("../queries/tpcds/q36.sql"),
+    include_str!("../queries/tpcds/q37.sql"),
+    include_str!("../queries/tpcds/q38.sql"),
+    include_str!("../queries/tpcds/q39.sql"),
+    include_str!("../queries/tpcds/q40.sql"),
+    include_str!("../queries/tpcds/q41.sql"),
+    include_str!("../queries/tpcds/q42.sql"),
+    include_str!("../queries/tpcds/q43.sql"),
+    include_str!("../queries/tpcds/q44.sql"),
+    include_str!("../queries/tpcds/q45.sql"),
+    include_str!("../queries/tpcds/q46.sql"),
+    include_str!("../queries/tpcds/q47.sql"),
+    include_str!("../queries/tpcds/q48.sql"),
+    include_str!("../queries/tpcds/q49.sql"),
+    include_str!("../queries/tpcds/q50.sql"),
+    include_str!("../queries/tpcds/q51.sql"),
+    include_str!("../queries/tpcds/q52.sql"),
+    include_str!("../queries/tpcds/q53.sql"),
+    include_str!("../queries/tpcds/q54.sql"),
+    include_str!("../queries/tpcds/q55.sql"),
+    include_str!("../queries/tpcds/q56.sql"),
+    include_str!("../queries/tpcds/q57.sql"),
+    include_str!("../queries/tpcds/q58.sql"),
+    include_str!("../queries/tpcds/q59.sql"),
+    include_str!("../queries/tpcds/q60.sql"),
+    include_str!("../queries/tpcds/q61.sql"),
+    include_str!("../queries/tpcds/q62.sql"),
+    include_str!("../queries/tpcds/q63.sql"),
+    include_str!("../queries/tpcds/q64.sql"),
+    include_str!("../queries/tpcds/q65.sql"),
+    include_str!("../queries/tpcds/q66.sql"),
+    include_str!("../queries/tpcds/q67.sql"),
+    include_str!("../queries/tpcds/q68.sql"),
+    include_str!("../queries/tpcds/q69.sql"),
+    include_str!("../queries/tpcds/q70.sql"),
+    include_str!("../queries/tpcds/q71.sql"),
+    include_str!("../queries/tpcds/q72.sql"),
+    include_str!("../queries/tpcds/q73.sql"),
+    include_str!("../queries/tpcds/q74.sql"),
+    include_str!("../queries/tpcds/q75.sql"),
+    include_str!("../queries/tpcds/q76.sql"),
+    include_str!("../queries/tpcds/q77.sql"),
+    include_str!("../queries/tpcds/q78.sql"),
+    include_str!("../queries/tpcds/q79.sql"),
+    include_str!("../queries/tpcds/q80.sql"),
+    include_str!("../queries/tpcds/q81.sql"),
+    include_str!("../queries/tpcds/q82.sql"),
+    include_str!("../queries/tpcds/q83.sql"),
+    include_str!("../queries/tpcds/q84.sql"),
+    include_str!("../queries/tpcds/q85.sql"),
+    include_str!("../queries/tpcds/q86.sql"),
+    include_str!("../queries/tpcds/q87.sql"),
+    include_str!("../queries/tpcds/q88.sql"),
+    include_str!("../queries/tpcds/q89.sql"),
+    include_str!("../queries/tpcds/q90.sql"),
+    include_str!("../queries/tpcds/q91.sql"),
+    include_str!("../queries/tpcds/q92.sql"),
+    include_str!("../queries/tpcds/q93.sql"),
+    include_str!("../queries/tpcds/q94.sql"),
+    include_str!("../queries/tpcds/q95.sql"),
+    include_str!("../queries/tpcds/q96.sql"),
+    include_str!("../queries/tpcds/q97.sql"),
+    include_str!("../queries/tpcds/q98.sql"),
+    include_str!("../queries/tpcds/q99.sql"),
+];
+
 fn make_decimal_type(p: u8, s: i8) -> DataType {
     DataType::Decimal128(p, s)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Number of columns of each table in the TPC-DS specification
+    const SPEC_COLUMN_COUNTS: [(&str, usize); 24] = [
+        ("call_center", 31),
+        ("catalog_page", 9),
+        ("catalog_returns", 27),
+        ("catalog_sales", 34),
+        ("customer", 18),
+        ("customer_address", 13),
+        ("customer_demographics", 9),
+        ("date_dim", 28),
+        ("household_demographics", 5),
+        ("income_band", 3),
+        ("inventory", 4),
+        ("item", 22),
+        ("promotion", 19),
+        ("reason", 3),
+        ("ship_mode", 6),
+        ("store", 29),
+        ("store_returns", 20),
+        ("store_sales", 23),
+        ("time_dim", 10),
+        ("warehouse", 14),
+        ("web_page", 14),
+        ("web_returns", 24),
+        ("web_sales", 34),
+        ("web_site", 26),
+    ];
+
+    #[test]
+    fn every_table_has_the_spec_columns() {
+        let benchmark = TpcDs::new();
+        let names = benchmark.get_table_names();
+        assert_eq!(names.len(), SPEC_COLUMN_COUNTS.len());
+        for (table, columns) in SPEC_COLUMN_COUNTS {
+            assert!(names.contains(&table), "missing table {}", table);
+            let schema = benchmark.get_schema(table).unwrap();
+            assert_eq!(schema.fields().len(), columns, "columns of {}", table);
+        }
+    }
+
+    #[test]
+    fn surrogate_keys_are_integers() {
+        let benchmark = TpcDs::new();
+        for table in benchmark.get_table_names() {
+            for field in benchmark.get_schema(table).unwrap().fields() {
+                if field.name().ends_with("_sk") {
+                    assert!(
+                        matches!(field.data_type(), DataType::Int32 | DataType::Int64),
+                        "{}.{} is {:?}",
+                        table,
+                        field.name(),
+                        field.data_type()
+                    );
+                }
+            }
+        }
+    }
+}
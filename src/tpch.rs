@@ -11,16 +11,42 @@
 // limitations under the License.
 
 use std::fs;
-use std::io::Result;
 use std::path::Path;
 use std::process::Command;
-use std::thread;
 use std::time::Instant;
 
 use async_trait::async_trait;
 use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use tracing::{debug, info};
 
-use crate::{move_or_copy, Tpc};
+use crate::error::{Result, TpcError};
+use crate::generate::{check_positive_scale, check_scale, find_generator, run_generators};
+use crate::{move_or_copy, unknown_table, GenerateOptions, Tpc};
+
+/// Scale factors allowed by the TPC-H specification
+const SCALE_FACTORS: [f64; 10] = [
+    1.0, 10.0, 30.0, 100.0, 300.0, 1000.0, 3000.0, 10000.0, 30000.0, 100000.0,
+];
+
+/// Get the number of rows dbgen writes for a table that has `base` rows at scale factor 1.
+/// dbgen takes scale factors below 1 in thousandths, writing at least one row, and truncates
+/// larger ones to whole numbers, so it can't generate other scale factors exactly.
+fn scaled_rows(base: u64, scale: f64) -> Option<u64> {
+    if !(scale.is_finite() && scale > 0.0) {
+        return None;
+    }
+    if scale < 1.0 {
+        let thousandths = (scale * 1000.0).round();
+        if thousandths < 1.0 || (scale * 1000.0 - thousandths).abs() > 1e-6 {
+            return None;
+        }
+        Some((base * thousandths as u64 / 1000).max(1))
+    } else if scale.fract() == 0.0 {
+        Some(base * scale as u64)
+    } else {
+        None
+    }
+}
 
 pub struct TpcH {}
 
@@ -30,61 +56,58 @@ impl TpcH {
     }
 }
 
+impl Default for TpcH {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[async_trait]
 impl Tpc for TpcH {
     fn generate(
         &self,
-        scale: usize,
+        scale: f64,
         partitions: usize,
         generator_path: &str,
         output_path: &str,
+        options: &GenerateOptions,
     ) -> Result<()> {
-        let mut handles = vec![];
+        options.validate()?;
+        self.validate_scale(scale)?;
+        check_scale("TPC-H", scale, &SCALE_FACTORS, options)?;
+        let dbgen = find_generator(generator_path, "dbgen", "dists.dss")?;
 
         let start = Instant::now();
 
+        let mut commands = vec![];
         if partitions == 1 {
-            let generator_path = generator_path.to_owned();
-            handles.push(thread::spawn(move || {
-                println!("Generating partition 1 of 1 ...");
-                let output = Command::new("./dbgen")
+            let mut command = Command::new(&dbgen);
+            command
+                .current_dir(generator_path)
+                .arg("-f")
+                .arg("-s")
+                .arg(format!("{}", scale));
+            commands.push(("partition 1 of 1".to_string(), command));
+        } else {
+            for i in 1..=partitions {
+                let mut command = Command::new(&dbgen);
+                command
                     .current_dir(generator_path)
                     .arg("-f")
                     .arg("-s")
                     .arg(format!("{}", scale))
-                    .output()
-                    .expect("failed to generate data");
-                println!("{:?}", output);
-            }));
-        } else {
-            for i in 1..=partitions {
-                let generator_path = generator_path.to_owned();
-                handles.push(thread::spawn(move || {
-                    println!("Generating partition {} of {} ...", i, partitions);
-                    let output = Command::new("./dbgen")
-                        .current_dir(generator_path)
-                        .arg("-f")
-                        .arg("-s")
-                        .arg(format!("{}", scale))
-                        .arg("-C")
-                        .arg(format!("{}", partitions))
-                        .arg("-S")
-                        .arg(format!("{}", i))
-                        .output()
-                        .expect("failed to generate data");
-                    println!("{:?}", output);
-                }));
+                    .arg("-C")
+                    .arg(format!("{}", partitions))
+                    .arg("-S")
+                    .arg(format!("{}", i));
+                commands.push((format!("partition {} of {}", i, partitions), command));
             }
         }
-
-        // wait for all threads to finish
-        for h in handles {
-            h.join().unwrap();
-        }
+        run_generators(commands, options.concurrency)?;
 
         let duration = start.elapsed();
 
-        println!(
+        info!(
             "Generated TPC-H data at scale factor {} with {} partitions in: {:?}",
             scale, partitions, duration
         );
@@ -94,35 +117,38 @@ impl Tpc for TpcH {
         ];
 
         if !Path::new(&output_path).exists() {
-            println!("Creating directory {}", output_path);
-            fs::create_dir(&output_path)?;
+            debug!("Creating directory {}", output_path);
+            fs::create_dir(output_path)?;
         }
 
         for table in &tables {
             let output_dir = format!("{}/{}.tbl", output_path, table);
             if !Path::new(&output_dir).exists() {
-                println!("Creating directory {}", output_dir);
+                debug!("Creating directory {}", output_dir);
                 fs::create_dir(&output_dir)?;
             }
 
+            // dbgen writes unpartitioned tables such as nation and region to a single file
             let filename = format!("{}/{}.tbl", generator_path, table);
-            let filename2 = format!("{}/part-0.dat", output_dir);
+            let filename2 = format!("{}/part-0.tbl", output_dir);
             if Path::new(&filename).exists() {
-                move_or_copy(&Path::new(&filename), &Path::new(&filename2))?;
+                move_or_copy(
+                    Path::new(&filename),
+                    Path::new(&filename2),
+                    options.io_retry.as_ref(),
+                )?;
             }
 
-            if partitions == 1 {
-                let filename = format!("{}/{}.tbl", generator_path, table);
-                let filename2 = format!("{}/part-0.tbl", output_dir);
-                if Path::new(&filename).exists() {
-                    move_or_copy(&Path::new(&filename), &Path::new(&filename2))?;
-                }
-            } else {
+            if partitions > 1 {
                 for i in 1..=partitions {
                     let filename = format!("{}/{}.tbl.{}", generator_path, table, i);
                     let filename2 = format!("{}/part-{}.tbl", output_dir, i);
                     if Path::new(&filename).exists() {
-                        move_or_copy(&Path::new(&filename), &Path::new(&filename2))?;
+                        move_or_copy(
+                            Path::new(&filename),
+                            Path::new(&filename2),
+                            options.io_retry.as_ref(),
+                        )?;
                     }
                 }
             }
@@ -137,12 +163,56 @@ impl Tpc for TpcH {
         ]
     }
 
-    fn get_schema(&self, table: &str) -> Schema {
+    fn get_table_aliases(&self) -> Vec<(&str, &str)> {
+        vec![
+            ("order", "orders"),
+            ("line_item", "lineitem"),
+            ("part_supp", "partsupp"),
+        ]
+    }
+
+    fn validate_scale(&self, scale: f64) -> Result<()> {
+        check_positive_scale(scale)?;
+        if scaled_rows(1, scale).is_none() {
+            return Err(TpcError::InvalidOption(format!(
+                "dbgen can only generate scale factors below 1 in thousandths, such as 0.01, \
+                 and whole scale factors, got {}",
+                scale
+            )));
+        }
+        Ok(())
+    }
+
+    fn get_expected_row_count(&self, table: &str, scale: f64) -> Option<u64> {
+        scaled_rows(1, scale)?;
+        match self.resolve_table_name(table)? {
+            "region" => Some(5),
+            "nation" => Some(25),
+            "supplier" => scaled_rows(10_000, scale),
+            "customer" => scaled_rows(150_000, scale),
+            "part" => scaled_rows(200_000, scale),
+            "partsupp" => scaled_rows(800_000, scale),
+            "orders" => scaled_rows(1_500_000, scale),
+            // the number of line items per order is random
+            _ => None,
+        }
+    }
+
+    fn estimated_row_count(&self, table: &str, scale: f64) -> Option<u64> {
+        match self.resolve_table_name(table)? {
+            // an average of four line items per order
+            "lineitem" => scaled_rows(6_000_000, scale),
+            table => self.get_expected_row_count(table, scale),
+        }
+    }
+
+    fn get_schema(&self, table: &str) -> Result<Schema> {
         // note that the schema intentionally uses signed integers so that any generated Parquet
         // files can also be used to benchmark tools that only support signed integers, such as
-        // Apache Spark
+        // Apache Spark. Monetary and quantity columns are DECIMAL(15,2), matching the DDL that
+        // ships with dbgen, so that query results match the reference answers exactly.
 
-        match table {
+        let schema = match self.resolve_table_name(table).unwrap_or(table) {
             "part" => Schema::new(vec![
                 Field::new("p_partkey", DataType::Int64, false),
                 Field::new("p_name", DataType::Utf8, false),
@@ -151,7 +221,7 @@ impl Tpc for TpcH {
                 Field::new("p_type", DataType::Utf8, false),
                 Field::new("p_size", DataType::Int32, false),
                 Field::new("p_container", DataType::Utf8, false),
-                Field::new("p_retailprice", DataType::Decimal128(11, 2), false),
+                Field::new("p_retailprice", DataType::Decimal128(15, 2), false),
                 Field::new("p_comment", DataType::Utf8, false),
             ]),
 
@@ -161,7 +231,7 @@ impl Tpc for TpcH {
                 Field::new("s_address", DataType::Utf8, false),
                 Field::new("s_nationkey", DataType::Int64, false),
                 Field::new("s_phone", DataType::Utf8, false),
-                Field::new("s_acctbal", DataType::Decimal128(11, 2), false),
+                Field::new("s_acctbal", DataType::Decimal128(15, 2), false),
                 Field::new("s_comment", DataType::Utf8, false),
             ]),
 
@@ -169,7 +239,7 @@ impl Tpc for TpcH {
                 Field::new("ps_partkey", DataType::Int64, false),
                 Field::new("ps_suppkey", DataType::Int64, false),
                 Field::new("ps_availqty", DataType::Int32, false),
-                Field::new("ps_supplycost", DataType::Decimal128(11, 2), false),
+                Field::new("ps_supplycost", DataType::Decimal128(15, 2), false),
                 Field::new("ps_comment", DataType::Utf8, false),
             ]),
 
@@ -179,7 +249,7 @@ impl Tpc for TpcH {
                 Field::new("c_address", DataType::Utf8, false),
                 Field::new("c_nationkey", DataType::Int64, false),
                 Field::new("c_phone", DataType::Utf8, false),
-                Field::new("c_acctbal", DataType::Decimal128(11, 2), false),
+                Field::new("c_acctbal", DataType::Decimal128(15, 2), false),
                 Field::new("c_mktsegment", DataType::Utf8, false),
                 Field::new("c_comment", DataType::Utf8, false),
             ]),
@@ -188,7 +258,7 @@ impl Tpc for TpcH {
                 Field::new("o_orderkey", DataType::Int64, false),
                 Field::new("o_custkey", DataType::Int64, false),
                 Field::new("o_orderstatus", DataType::Utf8, false),
-                Field::new("o_totalprice", DataType::Decimal128(11, 2), false),
+                Field::new("o_totalprice", DataType::Decimal128(15, 2), false),
                 Field::new("o_orderdate", DataType::Date32, false),
                 Field::new("o_orderpriority", DataType::Utf8, false),
                 Field::new("o_clerk", DataType::Utf8, false),
@@ -201,10 +271,10 @@ impl Tpc for TpcH {
                 Field::new("l_partkey", DataType::Int64, false),
                 Field::new("l_suppkey", DataType::Int64, false),
                 Field::new("l_linenumber", DataType::Int32, false),
-                Field::new("l_quantity", DataType::Decimal128(11, 2), false),
-                Field::new("l_extendedprice", DataType::Decimal128(11, 2), false),
-                Field::new("l_discount", DataType::Decimal128(11, 2), false),
-                Field::new("l_tax", DataType::Decimal128(11, 2), false),
+                Field::new("l_quantity", DataType::Decimal128(15, 2), false),
+                Field::new("l_extendedprice", DataType::Decimal128(15, 2), false),
+                Field::new("l_discount", DataType::Decimal128(15, 2), false),
+                Field::new("l_tax", DataType::Decimal128(15, 2), false),
                 Field::new("l_returnflag", DataType::Utf8, false),
                 Field::new("l_linestatus", DataType::Utf8, false),
                 Field::new("l_shipdate", DataType::Date32, false),
@@ -228,11 +298,47 @@ impl Tpc for TpcH {
                 Field::new("r_comment", DataType::Utf8, false),
             ]),
 
-            _ => unimplemented!(),
-        }
+            _ => return Err(unknown_table(table, &self.get_table_names())),
+        };
+        Ok(schema)
     }
 
     fn get_table_ext(&self) -> &str {
         "tbl"
     }
+
+    fn get_name(&self) -> &str {
+        "tpch"
+    }
+
+    fn queries(&self) -> &[&'static str] {
+        &QUERIES
+    }
 }
+
+/// The TPC-H queries, with the substitution parameters that the specification uses to
+/// validate query results
+const QUERIES: [&str; 22] = [
+    include_str!("../queries/tpch/q1.sql"),
+    include_str!("../queries/tpch/q2.sql"),
+    include_str!("../queries/tpch/q3.sql"),
+    include_str!("../queries/tpch/q4.sql"),
+    include_str!("../queries/tpch/q5.sql"),
+    include_str!("../queries/tpch/q6.sql"),
+    include_str!("../queries/tpch/q7.sql"),
+    include_str!("../queries/tpch/q8.sql"),
+    include_str!("../queries/tpch/q9.sql"),
+    include_str!("../queries/tpch/q10.sql"),
+    include_str!("../queries/tpch/q11.sql"),
+    include_str!("../queries/tpch/q12.sql"),
+    include_str!("../queries/tpch/q13.sql"),
+    include_str!("../queries/tpch/q14.sql"),
+    include_str!("../queries/tpch/q15.sql"),
+    include_str!("../queries/tpch/q16.sql"),
+    include_str!("../queries/tpch/q17.sql"),
+    include_str!("../queries/tpch/q18.sql"),
+    include_str!("../queries/tpch/q19.sql"),
+    include_str!("../queries/tpch/q20.sql"),
+    include_str!("../queries/tpch/q21.sql"),
+    include_str!("../queries/tpch/q22.sql"),
+];
@@ -0,0 +1,195 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checking previously converted tables against their input
+
+use std::path::Path;
+use std::sync::Arc;
+
+use datafusion::common::ScalarValue;
+use datafusion::prelude::*;
+use tracing::{info, info_span, Instrument};
+
+use crate::error::{Result, TpcError};
+use crate::ipc::read_ipc_table;
+use crate::malformed::{check_input, MalformedRowMode};
+use crate::{
+    csv_read_options, extract_input, read_tbl_files, table_input_files, table_input_path,
+    tbl_file_schema, ConversionOptions, OutputFormat, Tpc,
+};
+
+/// Result of verifying converted tables against their input
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// Result for each table, in benchmark table order
+    pub tables: Vec<TableVerification>,
+}
+
+impl VerifyReport {
+    /// Check whether every table matched its input
+    pub fn passed(&self) -> bool {
+        self.tables.iter().all(|table| table.passed())
+    }
+}
+
+/// Result of verifying one converted table
+#[derive(Debug, Clone)]
+pub struct TableVerification {
+    pub table: String,
+    /// Differences found between the input and the converted output
+    pub discrepancies: Vec<String>,
+}
+
+impl TableVerification {
+    /// Check whether the table matched its input
+    pub fn passed(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+}
+
+/// Compare the row count and the non-null count, minimum, and maximum of every column of each
+/// converted table under `output_path` with the input files it was converted from
+pub async fn verify(
+    benchmark: &dyn Tpc,
+    input_path: &str,
+    output_path: &str,
+    options: &ConversionOptions,
+) -> Result<VerifyReport> {
+    options.validate()?;
+    let ctx = options.session_context()?;
+    let selected = options.selected_tables(benchmark)?;
+    let extracted = extract_input(benchmark, input_path, &selected)?;
+    let extracted_path = extracted
+        .as_ref()
+        .map(|dir| dir.path().display().to_string());
+    let input_path = extracted_path.as_deref().unwrap_or(input_path);
+
+    let mut tables = vec![];
+    for table in selected {
+        let discrepancies = verify_table(&ctx, benchmark, table, input_path, output_path, options)
+            .instrument(info_span!("table", table))
+            .await?;
+        tables.push(TableVerification {
+            table: table.to_owned(),
+            discrepancies,
+        });
+    }
+    Ok(VerifyReport { tables })
+}
+
+async fn verify_table(
+    ctx: &SessionContext,
+    benchmark: &dyn Tpc,
+    table: &str,
+    input_path: &str,
+    output_path: &str,
+    options: &ConversionOptions,
+) -> Result<Vec<String>> {
+    let input = table_input_path(benchmark, input_path, table);
+    let files = table_input_files(&input, benchmark.get_table_ext())?;
+    if !input.exists() && files.is_empty() {
+        return Ok(vec![format!("Input does not exist: {}", input.display())]);
+    }
+    let output = format!("{}/{}.{}", output_path, table, options.format.extension());
+    if !Path::new(&output).exists() {
+        return Ok(vec![format!("Output does not exist: {}", output)]);
+    }
+
+    let schema = options.table_schema(benchmark, table)?;
+    let file_schema = tbl_file_schema(&schema, options)?;
+    let delimiter = options.input_delimiter(table, &files)?;
+    let csv_options = csv_read_options(benchmark, &file_schema, delimiter);
+
+    if files.is_empty() {
+        return Ok(vec![format!("Input has no files: {}", input.display())]);
+    }
+    // compare the output with the rows that were converted when skipping malformed rows
+    let checked = match options.malformed_rows {
+        Some(mode) => {
+            let fields = file_schema.fields().len();
+            let checked = check_input(table, &files, fields, delimiter, MalformedRowMode::Skip)?;
+            if mode == MalformedRowMode::Fail && !checked.malformed_rows.is_empty() {
+                return Ok(checked
+                    .malformed_rows
+                    .iter()
+                    .map(|row| format!("Malformed input row at {}", row))
+                    .collect());
+            }
+            Some(checked)
+        }
+        None => None,
+    };
+    let files = checked.as_ref().map_or(&files, |checked| &checked.files);
+    let source = read_tbl_files(ctx, files, &csv_options).await?;
+
+    let converted = match options.format {
+        OutputFormat::Csv => {
+            let options = CsvReadOptions::new().schema(&schema).has_header(true);
+            ctx.read_csv(&output, options).await?
+        }
+        OutputFormat::Json => {
+            let options = NdJsonReadOptions::default().schema(&schema);
+            ctx.read_json(&output, options).await?
+        }
+        OutputFormat::Parquet => {
+            ctx.read_parquet(&output, ParquetReadOptions::default())
+                .await?
+        }
+        OutputFormat::ArrowIpc => ctx.read_table(Arc::new(read_ipc_table(Path::new(&output))?))?,
+    };
+
+    let columns: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+    let source_summary = summarize(source, &columns).await?;
+    let converted_summary = summarize(converted, &columns).await?;
+
+    info!("Comparing input and output");
+    let mut discrepancies = vec![];
+    for (i, name) in summary_names(&columns).iter().enumerate() {
+        if source_summary[i] != converted_summary[i] {
+            discrepancies.push(format!(
+                "{} is {} in the input but {} in the output",
+                name, source_summary[i], converted_summary[i]
+            ));
+        }
+    }
+    Ok(discrepancies)
+}
+
+/// Names of the values computed by `summarize`
+fn summary_names(columns: &[&str]) -> Vec<String> {
+    let mut names = vec!["row count".to_string()];
+    for column in columns {
+        names.push(format!("non-null count of {}", column));
+        names.push(format!("minimum of {}", column));
+        names.push(format!("maximum of {}", column));
+    }
+    names
+}
+
+/// Compute the row count and the non-null count, minimum, and maximum of each column
+async fn summarize(df: DataFrame, columns: &[&str]) -> Result<Vec<ScalarValue>> {
+    let mut aggregates = vec![count(lit(1))];
+    for column in columns {
+        aggregates.push(count(col(*column)));
+        aggregates.push(min(col(*column)));
+        aggregates.push(max(col(*column)));
+    }
+    let batches = df.aggregate(vec![], aggregates)?.collect().await?;
+    let batch = batches
+        .first()
+        .ok_or_else(|| TpcError::Conversion("Summary returned no rows".to_string()))?;
+    batch
+        .columns()
+        .iter()
+        .map(|array| Ok(ScalarValue::try_from_array(array, 0)?))
+        .collect()
+}
@@ -0,0 +1,312 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Writing DataFrames to local files, timing how long is spent reading and writing
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::ops::AddAssign;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::ipc::writer::FileWriter;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::{csv, json};
+use datafusion::parquet::arrow::ArrowWriter;
+use datafusion::parquet::file::properties::WriterProperties;
+use datafusion::physical_plan::SendableRecordBatchStream;
+use datafusion::prelude::*;
+use futures::TryStreamExt;
+
+use crate::error::{Result, TpcError};
+use crate::{ConversionOptions, OutputFormat};
+
+/// Time spent reading input and writing output. Files written at the same time each add their
+/// own time, so the totals can be longer than the time the conversion took.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Timings {
+    /// Waiting for record batches, which includes reading and parsing the input
+    pub read: Duration,
+    /// Encoding, compressing, and writing record batches
+    pub write: Duration,
+}
+
+impl AddAssign for Timings {
+    fn add_assign(&mut self, other: Self) {
+        self.read += other.read;
+        self.write += other.write;
+    }
+}
+
+/// Number of rows written and the time spent writing them
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Written {
+    pub rows: u64,
+    pub timings: Timings,
+    /// Number of rows in each file written
+    pub file_rows: HashMap<PathBuf, u64>,
+}
+
+impl AddAssign for Written {
+    fn add_assign(&mut self, other: Self) {
+        self.rows += other.rows;
+        self.timings += other.timings;
+        self.file_rows.extend(other.file_rows);
+    }
+}
+
+/// Write a DataFrame to a directory with one `part-N` file per partition, or `part-N-M` files
+/// when partitions are split by `max_rows_per_file`
+pub(crate) async fn write_dataframe(
+    df: DataFrame,
+    output_filename: &str,
+    options: &ConversionOptions,
+) -> Result<Written> {
+    let props = match options.format {
+        OutputFormat::Parquet => Some(options.writer_properties()?),
+        OutputFormat::Csv | OutputFormat::Json | OutputFormat::ArrowIpc => None,
+    };
+    let streams = df.execute_stream_partitioned().await?;
+    fs::create_dir_all(output_filename)?;
+
+    // write the partitions in parallel, as DataFusion does
+    let tasks: Vec<_> = streams
+        .into_iter()
+        .enumerate()
+        .map(|(part, stream)| {
+            tokio::spawn(write_stream(
+                stream,
+                Path::new(output_filename).to_path_buf(),
+                part,
+                options.format,
+                props.clone(),
+                options.max_rows_per_file,
+            ))
+        })
+        .collect();
+    let mut written = Written::default();
+    for task in tasks {
+        written += task
+            .await
+            .map_err(|e| TpcError::Conversion(format!("Writer task failed: {}", e)))??;
+    }
+    Ok(written)
+}
+
+/// Write a stream of record batches to files in the output format, starting a new file each
+/// time `max_rows_per_file` rows have been written. CSV files have a header, and JSON files
+/// have one object per line.
+async fn write_stream(
+    mut stream: SendableRecordBatchStream,
+    dir: PathBuf,
+    part: usize,
+    format: OutputFormat,
+    props: Option<WriterProperties>,
+    max_rows_per_file: Option<usize>,
+) -> Result<Written> {
+    let mut written = Written::default();
+    let mut writer =
+        PartWriter::try_new(dir, part, format, stream.schema(), props, max_rows_per_file)?;
+    while let Some(batch) = next_batch(&mut stream, &mut written.timings).await? {
+        let start = Instant::now();
+        written.rows += batch.num_rows() as u64;
+        writer.write(batch)?;
+        written.timings.write += start.elapsed();
+    }
+    let start = Instant::now();
+    written.file_rows.extend(writer.close()?);
+    written.timings.write += start.elapsed();
+    Ok(written)
+}
+
+/// Writes record batches to the `part-N` file of a directory, or to `part-N-M` files when
+/// `max_rows_per_file` splits the part
+pub(crate) struct PartWriter {
+    dir: PathBuf,
+    part: usize,
+    format: OutputFormat,
+    schema: SchemaRef,
+    props: Option<WriterProperties>,
+    max_rows_per_file: Option<usize>,
+    writer: BatchWriter,
+    /// Path and number of rows of each file, the last of which is being written
+    files: Vec<(PathBuf, u64)>,
+}
+
+impl PartWriter {
+    /// Create the part's first file, which is written even if no batches are
+    pub(crate) fn try_new(
+        dir: PathBuf,
+        part: usize,
+        format: OutputFormat,
+        schema: SchemaRef,
+        props: Option<WriterProperties>,
+        max_rows_per_file: Option<usize>,
+    ) -> Result<Self> {
+        let path = Self::path(&dir, part, format, max_rows_per_file, 0);
+        let writer = BatchWriter::try_new(&path, format, &schema, props.clone())?;
+        let files = vec![(path, 0)];
+        Ok(Self {
+            dir,
+            part,
+            format,
+            schema,
+            props,
+            max_rows_per_file,
+            writer,
+            files,
+        })
+    }
+
+    // split files are numbered within the part so that they sort in the order written
+    fn path(
+        dir: &Path,
+        part: usize,
+        format: OutputFormat,
+        max_rows_per_file: Option<usize>,
+        file: usize,
+    ) -> PathBuf {
+        match max_rows_per_file {
+            Some(_) => dir.join(format!("part-{}-{}.{}", part, file, format.extension())),
+            None => dir.join(format!("part-{}.{}", part, format.extension())),
+        }
+    }
+
+    pub(crate) fn write(&mut self, mut batch: RecordBatch) -> Result<()> {
+        while batch.num_rows() > 0 {
+            let file_rows = self.files.last().map_or(0, |(_, rows)| *rows as usize);
+            // only start a new file once there are more rows, so that no file is empty
+            if self.max_rows_per_file == Some(file_rows) {
+                let path = Self::path(
+                    &self.dir,
+                    self.part,
+                    self.format,
+                    self.max_rows_per_file,
+                    self.files.len(),
+                );
+                let writer =
+                    BatchWriter::try_new(&path, self.format, &self.schema, self.props.clone())?;
+                std::mem::replace(&mut self.writer, writer).close()?;
+                self.files.push((path, 0));
+                continue;
+            }
+            let rows = match self.max_rows_per_file {
+                Some(max_rows) => batch.num_rows().min(max_rows - file_rows),
+                None => batch.num_rows(),
+            };
+            self.writer.write(&batch.slice(0, rows))?;
+            if let Some((_, file_rows)) = self.files.last_mut() {
+                *file_rows += rows as u64;
+            }
+            batch = batch.slice(rows, batch.num_rows() - rows);
+        }
+        Ok(())
+    }
+
+    /// Close the last file, returning the path and number of rows of each file written
+    pub(crate) fn close(self) -> Result<Vec<(PathBuf, u64)>> {
+        self.writer.close()?;
+        Ok(self.files)
+    }
+}
+
+/// Get the next record batch from a stream, adding the time spent waiting to the read time
+pub(crate) async fn next_batch(
+    batches: &mut SendableRecordBatchStream,
+    timings: &mut Timings,
+) -> Result<Option<RecordBatch>> {
+    let start = Instant::now();
+    let batch = batches.try_next().await?;
+    timings.read += start.elapsed();
+    Ok(batch)
+}
+
+/// Cast the decimal columns of a batch, which the JSON writer doesn't support, to floats.
+/// Reading the output with the table's schema rounds them back to the same decimals.
+pub(crate) fn json_batch(batch: &RecordBatch) -> Result<RecordBatch> {
+    let schema = batch.schema();
+    let mut fields = vec![];
+    let mut columns = vec![];
+    for (field, column) in schema.fields().iter().zip(batch.columns()) {
+        if matches!(field.data_type(), DataType::Decimal128(_, _)) {
+            fields.push(Field::new(
+                field.name(),
+                DataType::Float64,
+                field.is_nullable(),
+            ));
+            columns.push(cast(column, &DataType::Float64)?);
+        } else {
+            fields.push(field.as_ref().clone());
+            columns.push(column.clone());
+        }
+    }
+    Ok(RecordBatch::try_new(
+        Arc::new(Schema::new(fields)),
+        columns,
+    )?)
+}
+
+/// Writes record batches to a local file in the output format
+enum BatchWriter {
+    Csv(Box<csv::Writer<File>>),
+    Json(Box<json::LineDelimitedWriter<File>>),
+    Parquet(Box<ArrowWriter<File>>),
+    ArrowIpc(Box<FileWriter<File>>),
+}
+
+impl BatchWriter {
+    /// Create a file and a writer for it in the output format
+    fn try_new(
+        path: &Path,
+        format: OutputFormat,
+        schema: &SchemaRef,
+        props: Option<WriterProperties>,
+    ) -> Result<Self> {
+        let file = File::create(path)?;
+        Ok(match format {
+            OutputFormat::Csv => Self::Csv(Box::new(csv::Writer::new(file))),
+            OutputFormat::Json => Self::Json(Box::new(json::LineDelimitedWriter::new(file))),
+            OutputFormat::Parquet => Self::Parquet(Box::new(ArrowWriter::try_new(
+                file,
+                Arc::clone(schema),
+                props,
+            )?)),
+            OutputFormat::ArrowIpc => Self::ArrowIpc(Box::new(FileWriter::try_new(file, schema)?)),
+        })
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        match self {
+            Self::Csv(writer) => writer.write(batch)?,
+            Self::Json(writer) => writer.write(json_batch(batch)?)?,
+            Self::Parquet(writer) => writer.write(batch)?,
+            Self::ArrowIpc(writer) => writer.write(batch)?,
+        }
+        Ok(())
+    }
+
+    fn close(self) -> Result<()> {
+        match self {
+            // the CSV writer flushes after every batch
+            Self::Csv(_) => {}
+            Self::Json(mut writer) => writer.finish()?,
+            Self::Parquet(writer) => {
+                (*writer).close()?;
+            }
+            Self::ArrowIpc(mut writer) => writer.finish()?,
+        }
+        Ok(())
+    }
+}
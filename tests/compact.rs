@@ -0,0 +1,246 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compacting and repartitioning converted tables and reading their footers back
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use datafusion::parquet::basic::Compression;
+use datafusion::parquet::file::metadata::ParquetMetaData;
+use datafusion::parquet::file::properties::{EnabledStatistics, WriterVersion};
+use datafusion::parquet::file::reader::{FileReader, SerializedFileReader};
+use tempfile::TempDir;
+
+use tpctools::tpch::TpcH;
+use tpctools::{
+    compact, convert_to_parquet, repartition, CompressionCodec, ConversionOptions, DateGranularity,
+    DatePartitioning, TpcError,
+};
+
+const ORDERS: &str = "\
+1|370|O|172799.49|1996-01-02|5-LOW|Clerk#000000951|0|nstructions sleep furiously among |
+2|781|O|38426.09|1996-12-01|1-URGENT|Clerk#000000880|0| foxes. pending accounts at the pending|
+3|1234|F|205654.30|1993-10-14|5-LOW|Clerk#000000955|0|sly final accounts boost. |
+4|1369|O|56000.91|1995-10-11|5-LOW|Clerk#000000124|0|sits. slyly regular warthogs cajole.|
+5|445|F|105367.67|1994-07-30|5-LOW|Clerk#000000925|0|quickly. bold deposits sleep slyly.|
+6|557|F|45523.10|1992-02-21|4-NOT SPECIFIED|Clerk#000000058|0|ggle. special, final requests|
+";
+
+/// Convert the sample orders in a directory, returning the output path
+async fn convert_orders(dir: &TempDir, options: ConversionOptions) -> PathBuf {
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir_all(&input).unwrap();
+    fs::write(input.join("orders.tbl"), ORDERS).unwrap();
+    convert_to_parquet(
+        &TpcH::new(),
+        input.to_str().unwrap(),
+        output.to_str().unwrap(),
+        &options,
+    )
+    .await
+    .unwrap();
+    output
+}
+
+/// List the Parquet files of a table, relative to its directory
+fn table_files(table_dir: &Path) -> Vec<String> {
+    let mut files = vec![];
+    let mut dirs = vec![table_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "parquet") {
+                let file = path.strip_prefix(table_dir).unwrap();
+                files.push(file.display().to_string());
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+fn footer(path: &Path) -> ParquetMetaData {
+    let reader = SerializedFileReader::new(fs::File::open(path).unwrap()).unwrap();
+    reader.metadata().clone()
+}
+
+#[tokio::test]
+async fn compact_keeps_the_writer_settings() {
+    let dir = TempDir::new().unwrap();
+    let options = ConversionOptions::builder()
+        .tables(vec!["orders".to_string()])
+        .target_partitions(3)
+        .compression(CompressionCodec::Zstd(3))
+        .writer_version(WriterVersion::PARQUET_1_0)
+        .statistics(EnabledStatistics::Page)
+        .bloom_filter_columns(vec!["o_comment".to_string()])
+        .max_row_group_size(1)
+        .build();
+    let output = convert_orders(&dir, options).await;
+
+    compact(output.to_str().unwrap(), "orders", 1 << 30)
+        .await
+        .unwrap();
+    let table_dir = output.join("orders.parquet");
+    assert_eq!(table_files(&table_dir), vec!["part-0.parquet"]);
+
+    let metadata = footer(&table_dir.join("part-0.parquet"));
+    assert_eq!(metadata.file_metadata().version(), 1);
+    assert_eq!(metadata.file_metadata().num_rows(), 6);
+    assert_eq!(metadata.num_row_groups(), 6);
+    for column in metadata.row_group(0).columns() {
+        let name = column.column_path().string();
+        assert!(
+            matches!(column.compression(), Compression::ZSTD(_)),
+            "{}",
+            name
+        );
+        assert!(column.column_index_offset().is_some(), "{}", name);
+        assert_eq!(
+            column.bloom_filter_offset().is_some(),
+            name == "o_comment",
+            "{}",
+            name
+        );
+    }
+}
+
+#[tokio::test]
+async fn compact_rewrites_each_partition_directory() {
+    let dir = TempDir::new().unwrap();
+    let options = ConversionOptions::builder()
+        .tables(vec!["orders".to_string()])
+        .target_partitions(2)
+        .partition_by(
+            DatePartitioning::by_column(&TpcH::new(), "o_orderdate", DateGranularity::Year)
+                .unwrap(),
+        )
+        .build();
+    let output = convert_orders(&dir, options).await;
+    let table_dir = output.join("orders.parquet");
+    assert_eq!(table_files(&table_dir).len(), 10);
+
+    compact(output.to_str().unwrap(), "orders", 1 << 30)
+        .await
+        .unwrap();
+    let files = table_files(&table_dir);
+    let years = ["1992", "1993", "1994", "1995", "1996"];
+    let expected: Vec<String> = years
+        .iter()
+        .map(|year| format!("year={}/part-0.parquet", year))
+        .collect();
+    assert_eq!(files, expected);
+    let rows: i64 = files
+        .iter()
+        .map(|file| footer(&table_dir.join(file)).file_metadata().num_rows())
+        .sum();
+    assert_eq!(rows, 6);
+}
+
+#[tokio::test]
+async fn repartition_replaces_the_table_directory() {
+    let dir = TempDir::new().unwrap();
+    let options = ConversionOptions::builder()
+        .tables(vec!["orders".to_string()])
+        .build();
+    let output = convert_orders(&dir, options).await;
+
+    repartition(output.to_str().unwrap(), "orders", 3)
+        .await
+        .unwrap();
+    let table_dir = output.join("orders.parquet");
+    assert_eq!(
+        table_files(&table_dir),
+        vec!["part-0.parquet", "part-1.parquet", "part-2.parquet"]
+    );
+    assert!(table_dir.join("_SUCCESS").exists());
+    // nothing is left next to the table
+    let mut entries: Vec<_> = fs::read_dir(&output)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    entries.sort();
+    assert_eq!(entries, vec!["orders.parquet"]);
+}
+
+#[tokio::test]
+async fn repartition_keeps_a_single_file_table_in_one_file() {
+    let dir = TempDir::new().unwrap();
+    let options = ConversionOptions::builder()
+        .tables(vec!["orders".to_string()])
+        .single_file(true)
+        .build();
+    let output = convert_orders(&dir, options).await;
+    let output_path = output.to_str().unwrap();
+
+    let err = repartition(output_path, "orders", 2).await.unwrap_err();
+    assert!(matches!(err, TpcError::InvalidOption(_)), "{}", err);
+
+    repartition(output_path, "orders", 1).await.unwrap();
+    let table_file = output.join("orders.parquet");
+    assert!(table_file.is_file());
+    assert_eq!(footer(&table_file).file_metadata().num_rows(), 6);
+    assert!(!output.join("orders-compact.parquet").exists());
+}
+
+#[tokio::test]
+async fn repartition_keeps_the_footer_metadata() {
+    let dir = TempDir::new().unwrap();
+    let options = ConversionOptions::builder()
+        .tables(vec!["orders".to_string()])
+        .footer_metadata(true)
+        .key_value_metadata("owner", "benchmarks")
+        .statistics(EnabledStatistics::None)
+        .build();
+    let output = convert_orders(&dir, options).await;
+
+    repartition(output.to_str().unwrap(), "orders", 2)
+        .await
+        .unwrap();
+    for file in table_files(&output.join("orders.parquet")) {
+        let metadata = footer(&output.join("orders.parquet").join(&file));
+        let key_value_metadata = metadata.file_metadata().key_value_metadata().unwrap();
+        let value = |key: &str| {
+            key_value_metadata
+                .iter()
+                .find(|kv| kv.key == key)
+                .and_then(|kv| kv.value.clone())
+        };
+        assert_eq!(value("owner").as_deref(), Some("benchmarks"), "{}", file);
+        assert_eq!(
+            value("tpctools.benchmark").as_deref(),
+            Some("tpch"),
+            "{}",
+            file
+        );
+        assert_eq!(
+            value("tpctools.table").as_deref(),
+            Some("orders"),
+            "{}",
+            file
+        );
+        let schemas = key_value_metadata
+            .iter()
+            .filter(|kv| kv.key == "ARROW:schema")
+            .count();
+        assert_eq!(schemas, 1, "{}", file);
+        for row_group in metadata.row_groups() {
+            for column in row_group.columns() {
+                assert!(column.statistics().is_none(), "{}", file);
+            }
+        }
+    }
+}
@@ -0,0 +1,902 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Converting small sample files with the public API and reading the output back
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use datafusion::arrow::array::{Array, Date32Array, Decimal128Array, StringArray};
+use datafusion::arrow::datatypes::DataType;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::parquet::file::reader::{FileReader, SerializedFileReader};
+use datafusion::prelude::*;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tempfile::TempDir;
+use tokio::sync::mpsc;
+
+use tpctools::tpcds::TpcDs;
+use tpctools::tpch::TpcH;
+use tpctools::{
+    convert_tbl, convert_to_parquet, csv_read_options, register_tables, tbl_file_schema,
+    CancellationToken, ConversionOptions, DateGranularity, DatePartitioning, MalformedRow,
+    MalformedRowMode, OutputFormat, ProgressEvent, ResumeMode, Tpc, TpcError,
+};
+
+const LINEITEM: &str = "\
+1|155190|7706|1|17|21168.23|0.04|0.02|N|O|1996-03-13|1996-02-12|1996-03-22|DELIVER IN PERSON|TRUCK|egular courts above the|
+1|67310|7311|2|36|45983.16|0.09|0.06|N|O|1996-04-12|1996-02-28|1996-04-20|TAKE BACK RETURN|MAIL|ly final dependencies: slyly bold |
+3|4297|1798|1|45|54058.05|0.06|0.00|R|F|1994-02-02|1994-01-04|1994-02-23|NONE|AIR|ongside of the furiously brave acco|
+";
+
+/// Convert a table's sample input with `convert_tbl` and read every output batch back
+async fn convert_sample(
+    benchmark: &dyn Tpc,
+    table: &str,
+    input: &str,
+    options: &ConversionOptions,
+) -> Vec<RecordBatch> {
+    let dir = TempDir::new().unwrap();
+    let (ctx, output) = convert_in(&dir, benchmark, table, input, options).await;
+    ctx.read_parquet(&output, ParquetReadOptions::default())
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap()
+}
+
+/// Convert a table's sample input in a directory with `convert_tbl`, returning the context and
+/// the output directory
+async fn convert_in(
+    dir: &TempDir,
+    benchmark: &dyn Tpc,
+    table: &str,
+    input: &str,
+    options: &ConversionOptions,
+) -> (SessionContext, String) {
+    let input_path = dir
+        .path()
+        .join(format!("{}.{}", table, benchmark.get_table_ext()));
+    fs::write(&input_path, input).unwrap();
+    let output = dir.path().join(format!("{}.parquet", table));
+    let output = output.to_str().unwrap();
+
+    let schema = benchmark.get_schema(table).unwrap();
+    let file_schema = tbl_file_schema(&schema, options).unwrap();
+    let csv_options = csv_read_options(benchmark, &file_schema, b'|');
+    let ctx = SessionContext::new();
+    let rows = convert_tbl(&ctx, &input_path, output, &csv_options, options)
+        .await
+        .unwrap();
+    assert_eq!(rows, input.lines().count() as u64);
+    assert!(Path::new(output).is_dir());
+    (ctx, output.to_string())
+}
+
+/// Get the values of a string column
+fn strings(batches: &[RecordBatch], column: &str) -> Vec<String> {
+    batches
+        .iter()
+        .flat_map(|batch| {
+            let index = batch.schema().index_of(column).unwrap();
+            let array = batch
+                .column(index)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap()
+                .clone();
+            (0..array.len()).map(move |i| array.value(i).to_string())
+        })
+        .collect()
+}
+
+/// Get the unscaled values of a decimal column
+fn decimals(batches: &[RecordBatch], column: &str) -> Vec<i128> {
+    batches
+        .iter()
+        .flat_map(|batch| {
+            let index = batch.schema().index_of(column).unwrap();
+            let array = batch
+                .column(index)
+                .as_any()
+                .downcast_ref::<Decimal128Array>()
+                .unwrap()
+                .clone();
+            (0..array.len()).map(move |i| array.value(i))
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn lineitem_decimals_round_trip() {
+    let batches = convert_sample(
+        &TpcH::new(),
+        "lineitem",
+        LINEITEM,
+        &ConversionOptions::default(),
+    )
+    .await;
+    let schema = batches[0].schema();
+    for column in ["l_quantity", "l_extendedprice", "l_discount", "l_tax"] {
+        assert_eq!(
+            schema.field_with_name(column).unwrap().data_type(),
+            &DataType::Decimal128(15, 2),
+            "type of {}",
+            column
+        );
+    }
+    assert_eq!(decimals(&batches, "l_quantity"), vec![1700, 3600, 4500]);
+    assert_eq!(
+        decimals(&batches, "l_extendedprice"),
+        vec![2116823, 4598316, 5405805]
+    );
+    assert_eq!(decimals(&batches, "l_discount"), vec![4, 9, 6]);
+    assert_eq!(decimals(&batches, "l_tax"), vec![2, 6, 0]);
+}
+
+const NATION: &str = "\
+0|ALGERIA|0| haggle. carefully final deposits detect slyly agai|
+1|ARGENTINA|1|al foxes promise slyly according to the regular accounts. bold requests alon|
+";
+
+/// Check that the converted nation sample has exactly the table's columns and values
+fn check_nation(batches: &[RecordBatch]) {
+    let benchmark = TpcH::new();
+    let expected = benchmark.get_schema("nation").unwrap();
+    let schema = batches[0].schema();
+    let names: Vec<_> = schema.fields().iter().map(|field| field.name()).collect();
+    let expected: Vec<_> = expected.fields().iter().map(|field| field.name()).collect();
+    assert_eq!(names, expected);
+    assert_eq!(strings(batches, "n_name"), vec!["ALGERIA", "ARGENTINA"]);
+    assert_eq!(
+        strings(batches, "n_comment")[1],
+        "al foxes promise slyly according to the regular accounts. bold requests alon"
+    );
+}
+
+#[tokio::test]
+async fn input_with_trailing_delimiter() {
+    let batches = convert_sample(
+        &TpcH::new(),
+        "nation",
+        NATION,
+        &ConversionOptions::default(),
+    )
+    .await;
+    check_nation(&batches);
+}
+
+#[tokio::test]
+async fn input_without_trailing_delimiter() {
+    let input: String = NATION
+        .lines()
+        .map(|line| format!("{}\n", line.trim_end_matches('|')))
+        .collect();
+    let options = ConversionOptions::builder()
+        .trailing_delimiter(false)
+        .build();
+    let batches = convert_sample(&TpcH::new(), "nation", &input, &options).await;
+    check_nation(&batches);
+}
+
+#[tokio::test]
+async fn trailing_column_can_be_kept() {
+    let options = ConversionOptions::builder()
+        .drop_trailing_column(false)
+        .build();
+    let batches = convert_sample(&TpcH::new(), "nation", NATION, &options).await;
+    let schema = batches[0].schema();
+    assert_eq!(schema.fields().len(), 5);
+    assert_eq!(schema.field(4).name(), "__trailing_delimiter");
+}
+
+#[tokio::test]
+async fn empty_input_writes_an_empty_file_with_the_schema() {
+    let dir = TempDir::new().unwrap();
+    let (ctx, output) = convert_in(
+        &dir,
+        &TpcH::new(),
+        "region",
+        "",
+        &ConversionOptions::default(),
+    )
+    .await;
+    assert!(Path::new(&output).join("part-0.parquet").is_file());
+
+    let df = ctx
+        .read_parquet(&output, ParquetReadOptions::default())
+        .await
+        .unwrap();
+    let expected = TpcH::new().get_schema("region").unwrap();
+    let columns: Vec<_> = df
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| (field.name().clone(), field.data_type().clone()))
+        .collect();
+    let expected: Vec<_> = expected
+        .fields()
+        .iter()
+        .map(|field| (field.name().clone(), field.data_type().clone()))
+        .collect();
+    assert_eq!(columns, expected);
+    assert_eq!(df.count().await.unwrap(), 0);
+}
+
+const DATE_DIM: &str = "\
+2415022|AAAAAAAAOKJNECAA|1900-01-02|0|1|1|1900|1|1|2|1|1900|1|1|Monday|1900Q1|N|N|Y|2415021|2415020|2414657|2414930|N|N|N|N|N|
+2451545|AAAAAAAAJFGGFCAA|2000-01-01|1200|5218|401|2000|6|1|1|1|2000|401|5218|Saturday|2000Q1|N|Y|N|2451545|2451574|2451180|2451453|N|N|N|N|N|
+";
+
+/// Get the values of a date column as days since the Unix epoch
+fn dates(batches: &[RecordBatch], column: &str) -> Vec<i32> {
+    batches
+        .iter()
+        .flat_map(|batch| {
+            let index = batch.schema().index_of(column).unwrap();
+            let array = batch
+                .column(index)
+                .as_any()
+                .downcast_ref::<Date32Array>()
+                .unwrap()
+                .clone();
+            (0..array.len()).map(move |i| array.value(i))
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn date_dim_dates_are_epoch_days() {
+    let batches = convert_sample(
+        &TpcDs::new(),
+        "date_dim",
+        DATE_DIM,
+        &ConversionOptions::default(),
+    )
+    .await;
+    let schema = batches[0].schema();
+    assert_eq!(
+        schema.field_with_name("d_date").unwrap().data_type(),
+        &DataType::Date32
+    );
+    assert_eq!(dates(&batches, "d_date"), vec![-25566, 10957]);
+}
+
+#[tokio::test]
+async fn date_format_parses_other_layouts() {
+    let input = DATE_DIM
+        .replace("1900-01-02", "01/02/1900")
+        .replace("2000-01-01", "01/01/2000");
+    let options = ConversionOptions::builder().date_format("%m/%d/%Y").build();
+    let batches = convert_sample(&TpcDs::new(), "date_dim", &input, &options).await;
+    assert_eq!(dates(&batches, "d_date"), vec![-25566, 10957]);
+}
+
+/// Convert the nation sample with `convert_to_parquet`, returning the table's output directory
+async fn convert_nation_table(dir: &TempDir, options: &ConversionOptions) -> PathBuf {
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir_all(&input).unwrap();
+    fs::write(input.join("nation.tbl"), NATION).unwrap();
+    convert_to_parquet(
+        &TpcH::new(),
+        input.to_str().unwrap(),
+        output.to_str().unwrap(),
+        options,
+    )
+    .await
+    .unwrap();
+    output.join("nation.parquet")
+}
+
+#[tokio::test]
+async fn success_marker_is_written_by_default() {
+    let dir = TempDir::new().unwrap();
+    let options = ConversionOptions::builder()
+        .tables(vec!["nation".to_string()])
+        .build();
+    let table_dir = convert_nation_table(&dir, &options).await;
+    assert!(table_dir.join("_SUCCESS").is_file());
+}
+
+#[tokio::test]
+async fn success_marker_can_be_left_out() {
+    let dir = TempDir::new().unwrap();
+    let options = ConversionOptions::builder()
+        .tables(vec!["nation".to_string()])
+        .write_success_marker(false)
+        .build();
+    let table_dir = convert_nation_table(&dir, &options).await;
+    assert!(table_dir.join("part-0.parquet").is_file());
+    assert!(!table_dir.join("_SUCCESS").exists());
+
+    let options = ConversionOptions::builder()
+        .write_success_marker(false)
+        .resume_mode(ResumeMode::Skip)
+        .build();
+    assert!(matches!(
+        options.validate(),
+        Err(TpcError::InvalidOption(_))
+    ));
+}
+
+#[tokio::test]
+async fn dry_run_plans_archive_input_without_extracting_it() {
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("input.tar");
+    let mut archive = tar::Builder::new(fs::File::create(&archive_path).unwrap());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(NATION.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    archive
+        .append_data(&mut header, "data/nation.tbl", NATION.as_bytes())
+        .unwrap();
+    archive.finish().unwrap();
+    drop(archive);
+
+    let output = dir.path().join("output");
+    let options = ConversionOptions::builder()
+        .tables(vec!["nation".to_string()])
+        .detect_delimiter(true)
+        .dry_run(true)
+        .build();
+    let report = convert_to_parquet(
+        &TpcH::new(),
+        archive_path.to_str().unwrap(),
+        output.to_str().unwrap(),
+        &options,
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.plan.len(), 1);
+    assert_eq!(report.plan[0].inputs, vec![archive_path.join("nation.tbl")]);
+    assert_eq!(report.plan[0].delimiter, b'|');
+    assert!(!output.exists());
+}
+
+#[tokio::test]
+async fn partitioned_output_sorts_each_partition() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir_all(&input).unwrap();
+    let extra = "2|106170|1191|1|38|44694.46|0.00|0.05|N|O|1996-03-02|1997-01-14|1997-02-02|TAKE BACK RETURN|RAIL|ven requests. deposits breach a|\n";
+    fs::write(input.join("lineitem.tbl"), format!("{}{}", LINEITEM, extra)).unwrap();
+    let options = ConversionOptions::builder()
+        .tables(vec!["lineitem".to_string()])
+        .partition_by(
+            DatePartitioning::by_column(&TpcH::new(), "l_shipdate", DateGranularity::Month)
+                .unwrap(),
+        )
+        .sort_columns(vec!["l_extendedprice".to_string()])
+        .target_partitions(2)
+        .build();
+    let report = convert_to_parquet(
+        &TpcH::new(),
+        input.to_str().unwrap(),
+        output.to_str().unwrap(),
+        &options,
+    )
+    .await
+    .unwrap();
+    let partitions = [
+        "year=1994/month=2",
+        "year=1996/month=3",
+        "year=1996/month=4",
+    ];
+    let expected: Vec<String> = partitions
+        .iter()
+        .flat_map(|partition| {
+            (0..2).map(move |part| format!("lineitem.parquet/{}/part-{}.parquet", partition, part))
+        })
+        .collect();
+    assert_eq!(report.per_table[0].files, expected);
+    assert_eq!(report.per_table[0].rows, 4);
+
+    let ctx = SessionContext::new();
+    let table_dir = output.join("lineitem.parquet");
+    let mut prices = vec![];
+    for partition in partitions {
+        let batches = ctx
+            .read_parquet(
+                table_dir.join(partition).to_str().unwrap(),
+                ParquetReadOptions::default(),
+            )
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        prices.push(decimals(&batches, "l_extendedprice"));
+    }
+    assert_eq!(
+        prices,
+        vec![vec![5405805], vec![2116823, 4469446], vec![4598316]]
+    );
+}
+
+#[tokio::test]
+async fn partition_column_applies_to_the_tables_that_have_it() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir_all(&input).unwrap();
+    fs::write(input.join("lineitem.tbl"), LINEITEM).unwrap();
+    fs::write(input.join("nation.tbl"), NATION).unwrap();
+    let partitioning =
+        DatePartitioning::parse(&TpcH::new(), "l_shipdate", DateGranularity::Year).unwrap();
+    assert_eq!(partitioning.columns.len(), 1);
+    let options = ConversionOptions::builder()
+        .tables(vec!["lineitem".to_string(), "nation".to_string()])
+        .partition_by(partitioning)
+        .build();
+    let report = convert_to_parquet(
+        &TpcH::new(),
+        input.to_str().unwrap(),
+        output.to_str().unwrap(),
+        &options,
+    )
+    .await
+    .unwrap();
+    let files: Vec<_> = report
+        .per_table
+        .iter()
+        .map(|stats| stats.files.clone())
+        .collect();
+    assert_eq!(
+        files,
+        vec![
+            vec![
+                "lineitem.parquet/year=1994/part-0.parquet".to_string(),
+                "lineitem.parquet/year=1996/part-0.parquet".to_string(),
+            ],
+            vec!["nation.parquet/part-0.parquet".to_string()],
+        ]
+    );
+
+    let err = DatePartitioning::parse(&TpcH::new(), "d_date", DateGranularity::Year).unwrap_err();
+    assert!(matches!(err, TpcError::InvalidOption(_)), "{}", err);
+}
+
+#[tokio::test]
+async fn tpcds_sales_are_partitioned_by_date_key() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir_all(&input).unwrap();
+    // sold on 2000-01-01 and 2000-02-01, whose Julian day numbers are the date keys, and on no
+    // date
+    let store_sales = "\
+2451545|4|20|28|48|33|39|8|45|64|112|40.12|23.76|5.79|42.04|65.75|51.43|88.10|69.98|96.25|9.27|63.32|3.26|
+2451576|21|36|35|45|14|13|2|53|241|998|94.81|68.35|67.05|29.75|27.31|37.37|81.05|59.59|27.84|90.25|51.68|97.89|
+|21|36|35|45|14|13|2|53|242|998|94.81|68.35|67.05|29.75|27.31|37.37|81.05|59.59|27.84|90.25|51.68|97.89|
+";
+    fs::write(input.join("store_sales.dat"), store_sales).unwrap();
+    let benchmark = TpcDs::new();
+    let options = ConversionOptions::builder()
+        .tables(vec!["store_sales".to_string()])
+        .partition_by(
+            DatePartitioning::parse(
+                &benchmark,
+                "store_sales=ss_sold_date_sk",
+                DateGranularity::Month,
+            )
+            .unwrap(),
+        )
+        .build();
+    let report = convert_to_parquet(
+        &benchmark,
+        input.to_str().unwrap(),
+        output.to_str().unwrap(),
+        &options,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        report.per_table[0].files,
+        vec![
+            "store_sales.parquet/year=2000/month=1/part-0.parquet",
+            "store_sales.parquet/year=2000/month=2/part-0.parquet",
+            "store_sales.parquet/year=__HIVE_DEFAULT_PARTITION__/month=__HIVE_DEFAULT_PARTITION__/part-0.parquet",
+        ]
+    );
+    assert_eq!(report.per_table[0].rows, 3);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn cancelling_removes_only_the_incomplete_table() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir_all(input.join("region.tbl")).unwrap();
+    fs::write(input.join("nation.tbl"), NATION).unwrap();
+    // enough region files that the conversion is cancelled before they are all converted
+    for part in 1..=20 {
+        let row = format!(
+            "{}|AFRICA|lar deposits. blithely final packages cajole.|\n",
+            part
+        );
+        fs::write(input.join(format!("region.tbl/part-{}.tbl", part)), row).unwrap();
+    }
+
+    let token = CancellationToken::new();
+    let (progress, mut events) = mpsc::unbounded_channel();
+    let listener = {
+        let token = token.clone();
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                if matches!(&event, ProgressEvent::FileConverted { table, .. } if table == "region")
+                {
+                    token.cancel();
+                }
+            }
+        })
+    };
+    let options = ConversionOptions::builder()
+        .tables(vec!["nation".to_string(), "region".to_string()])
+        .concurrency(1)
+        .file_concurrency(1)
+        .progress(progress)
+        .cancellation_token(token)
+        .build();
+    let result = convert_to_parquet(
+        &TpcH::new(),
+        input.to_str().unwrap(),
+        output.to_str().unwrap(),
+        &options,
+    )
+    .await;
+    drop(options);
+    listener.await.unwrap();
+
+    assert!(matches!(result, Err(TpcError::Cancelled)), "{:?}", result);
+    assert!(output.join("nation.parquet/_SUCCESS").is_file());
+    let mut entries: Vec<_> = fs::read_dir(&output)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    entries.sort();
+    assert_eq!(entries, vec!["nation.parquet"]);
+}
+
+#[tokio::test]
+async fn object_store_output_reads_back() {
+    for single_file in [false, true] {
+        let dir = TempDir::new().unwrap();
+        let input = dir.path().join("input");
+        let output = dir.path().join("output");
+        fs::create_dir_all(&input).unwrap();
+        fs::create_dir_all(&output).unwrap();
+        fs::write(input.join("nation.tbl"), NATION).unwrap();
+        let output_url = format!("file://{}", output.display());
+        let options = ConversionOptions::builder()
+            .tables(vec!["nation".to_string()])
+            .single_file(single_file)
+            .build();
+        let benchmark = TpcH::new();
+        let convert =
+            || convert_to_parquet(&benchmark, input.to_str().unwrap(), &output_url, &options);
+        convert().await.unwrap();
+
+        let table = output.join("nation.parquet");
+        assert_eq!(table.is_file(), single_file);
+        let ctx = SessionContext::new();
+        let batches = ctx
+            .read_parquet(table.to_str().unwrap(), ParquetReadOptions::default())
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        check_nation(&batches);
+
+        let error = convert().await.unwrap_err().to_string();
+        let expected = format!("already exists: file://{}", table.display());
+        assert!(error.ends_with(&expected), "{}", error);
+    }
+}
+
+/// Nation sample rows with a row that is missing fields in between
+const NATION_WITH_MALFORMED_ROW: &str = "\
+0|ALGERIA|0| haggle. carefully final deposits detect slyly agai|
+1|ARGENTINA|
+2|BRAZIL|1|y alongside of the pending deposits. carefully special packages are about the ironic forges. slyly special |
+";
+
+#[tokio::test]
+async fn malformed_rows_are_reported_by_line() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir_all(&input).unwrap();
+    fs::write(input.join("nation.tbl"), NATION_WITH_MALFORMED_ROW).unwrap();
+    let options = ConversionOptions::builder()
+        .tables(vec!["nation".to_string()])
+        .malformed_rows(MalformedRowMode::Fail)
+        .build();
+    let result = convert_to_parquet(
+        &TpcH::new(),
+        input.to_str().unwrap(),
+        output.to_str().unwrap(),
+        &options,
+    )
+    .await;
+    match result {
+        Err(TpcError::MalformedRows { table, rows }) => {
+            assert_eq!(table, "nation");
+            assert_eq!(
+                rows,
+                vec![MalformedRow {
+                    file: input.join("nation.tbl"),
+                    line: 2,
+                    reason: "expected 5 fields but found 3".to_string(),
+                }]
+            );
+        }
+        result => panic!("unexpected result: {:?}", result),
+    }
+    assert!(!output.join("nation.parquet").exists());
+}
+
+#[tokio::test]
+async fn skipping_malformed_rows_drops_only_those_lines() {
+    let mut gz = GzEncoder::new(vec![], Compression::default());
+    gz.write_all(NATION_WITH_MALFORMED_ROW.as_bytes()).unwrap();
+    let gz = gz.finish().unwrap();
+    let inputs: [(&str, &[u8]); 2] = [
+        ("nation.tbl", NATION_WITH_MALFORMED_ROW.as_bytes()),
+        ("nation.tbl.gz", &gz),
+    ];
+    for (name, data) in inputs {
+        let dir = TempDir::new().unwrap();
+        let input = dir.path().join("input");
+        let output = dir.path().join("output");
+        fs::create_dir_all(&input).unwrap();
+        fs::write(input.join(name), data).unwrap();
+        let options = ConversionOptions::builder()
+            .tables(vec!["nation".to_string()])
+            .malformed_rows(MalformedRowMode::Skip)
+            .build();
+        let report = convert_to_parquet(
+            &TpcH::new(),
+            input.to_str().unwrap(),
+            output.to_str().unwrap(),
+            &options,
+        )
+        .await
+        .unwrap();
+
+        let stats = &report.per_table[0];
+        assert_eq!(stats.rows, 2, "{}", name);
+        let lines: Vec<_> = stats
+            .malformed_rows
+            .iter()
+            .map(|row| (row.file.clone(), row.line))
+            .collect();
+        assert_eq!(lines, vec![(input.join(name), 2)], "{}", name);
+        let ctx = SessionContext::new();
+        let batches = ctx
+            .read_parquet(
+                output.join("nation.parquet").to_str().unwrap(),
+                ParquetReadOptions::default(),
+            )
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        assert_eq!(strings(&batches, "n_name"), vec!["ALGERIA", "BRAZIL"]);
+    }
+}
+
+#[tokio::test]
+async fn source_is_deleted_only_after_a_table_succeeds() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir_all(&input).unwrap();
+    fs::write(input.join("nation.tbl"), NATION).unwrap();
+    fs::write(input.join("region.tbl"), "0|AFRICA|\n").unwrap();
+    let options = ConversionOptions::builder()
+        .tables(vec!["nation".to_string(), "region".to_string()])
+        .concurrency(1)
+        .malformed_rows(MalformedRowMode::Fail)
+        .delete_source_on_success(true)
+        .build();
+    let result = convert_to_parquet(
+        &TpcH::new(),
+        input.to_str().unwrap(),
+        output.to_str().unwrap(),
+        &options,
+    )
+    .await;
+
+    assert!(
+        matches!(&result, Err(TpcError::MalformedRows { table, .. }) if table == "region"),
+        "{:?}",
+        result
+    );
+    assert!(output.join("nation.parquet/_SUCCESS").is_file());
+    assert!(!input.join("nation.tbl").exists());
+    assert!(input.join("region.tbl").is_file());
+}
+
+#[tokio::test]
+async fn progress_events_follow_each_table() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir_all(input.join("nation.tbl")).unwrap();
+    let (first, second) = NATION.split_at(NATION.find('\n').unwrap() + 1);
+    fs::write(input.join("nation.tbl/part-1.tbl"), first).unwrap();
+    fs::write(input.join("nation.tbl/part-2.tbl"), second.repeat(2)).unwrap();
+    let (progress, mut events) = mpsc::unbounded_channel();
+    let options = ConversionOptions::builder()
+        .tables(vec!["nation".to_string()])
+        .progress(progress)
+        .build();
+    convert_to_parquet(
+        &TpcH::new(),
+        input.to_str().unwrap(),
+        output.to_str().unwrap(),
+        &options,
+    )
+    .await
+    .unwrap();
+    drop(options);
+
+    let mut received = vec![];
+    while let Some(event) = events.recv().await {
+        received.push(event);
+    }
+    assert!(
+        matches!(&received[0], ProgressEvent::TableStarted { table, input_files: 2 } if table == "nation"),
+        "{:?}",
+        received
+    );
+    // files of a table are converted concurrently, so they may be reported in any order
+    let mut files: Vec<_> = received[1..received.len() - 1]
+        .iter()
+        .map(|event| match event {
+            ProgressEvent::FileConverted {
+                table, file, rows, ..
+            } if table == "nation" => (file.clone(), *rows),
+            event => panic!("unexpected event: {:?}", event),
+        })
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        vec![
+            ("nation.parquet/part-0.parquet".to_string(), 1),
+            ("nation.parquet/part-1.parquet".to_string(), 2),
+        ]
+    );
+    match received.last().unwrap() {
+        ProgressEvent::TableFinished(stats) => {
+            assert_eq!(stats.table, "nation");
+            assert_eq!(stats.rows, 3);
+            let names: Vec<_> = files.into_iter().map(|(file, _)| file).collect();
+            assert_eq!(stats.files, names);
+        }
+        event => panic!("unexpected event: {:?}", event),
+    }
+}
+
+#[tokio::test]
+async fn files_are_split_at_the_row_limit() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir_all(&input).unwrap();
+    let orders: String = (1..=50)
+        .map(|key| {
+            format!(
+                "{}|370|O|172799.49|1996-01-02|5-LOW|Clerk#000000951|0|nstructions sleep|\n",
+                key
+            )
+        })
+        .collect();
+    fs::write(input.join("orders.tbl"), orders).unwrap();
+    let options = ConversionOptions::builder()
+        .tables(vec!["orders".to_string()])
+        .max_rows_per_file(7)
+        .build();
+    let report = convert_to_parquet(
+        &TpcH::new(),
+        input.to_str().unwrap(),
+        output.to_str().unwrap(),
+        &options,
+    )
+    .await
+    .unwrap();
+
+    let expected: Vec<_> = (0..8)
+        .map(|part| format!("orders.parquet/part-{}.parquet", part))
+        .collect();
+    let mut files = report.per_table[0].files.clone();
+    files.sort();
+    assert_eq!(files, expected);
+    let rows: Vec<_> = expected
+        .iter()
+        .map(|file| {
+            let reader = SerializedFileReader::new(File::open(output.join(file)).unwrap()).unwrap();
+            reader.metadata().file_metadata().num_rows()
+        })
+        .collect();
+    assert_eq!(rows, vec![7, 7, 7, 7, 7, 7, 7, 1]);
+}
+
+/// Convert the lineitem sample to a format and check that `register_tables` reads back the
+/// same decimals
+async fn check_lineitem_decimals_read_back(format: OutputFormat) {
+    let dir = TempDir::new().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir_all(&input).unwrap();
+    let benchmark = TpcH::new();
+    // every table is registered, so the others are converted from empty input
+    for table in benchmark.get_table_names() {
+        fs::write(input.join(format!("{}.tbl", table)), "").unwrap();
+    }
+    fs::write(input.join("lineitem.tbl"), LINEITEM).unwrap();
+    let options = ConversionOptions::builder().format(format).build();
+    convert_to_parquet(
+        &benchmark,
+        input.to_str().unwrap(),
+        output.to_str().unwrap(),
+        &options,
+    )
+    .await
+    .unwrap();
+
+    let ctx = SessionContext::new();
+    register_tables(&ctx, &benchmark, output.to_str().unwrap(), format)
+        .await
+        .unwrap();
+    let batches = ctx
+        .sql("SELECT * FROM lineitem ORDER BY l_orderkey, l_linenumber")
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+    assert_eq!(
+        batches[0]
+            .schema()
+            .field_with_name("l_extendedprice")
+            .unwrap()
+            .data_type(),
+        &DataType::Decimal128(15, 2)
+    );
+    assert_eq!(decimals(&batches, "l_quantity"), vec![1700, 3600, 4500]);
+    assert_eq!(
+        decimals(&batches, "l_extendedprice"),
+        vec![2116823, 4598316, 5405805]
+    );
+    assert_eq!(decimals(&batches, "l_discount"), vec![4, 9, 6]);
+    assert_eq!(decimals(&batches, "l_tax"), vec![2, 6, 0]);
+}
+
+#[tokio::test]
+async fn json_output_reads_back_the_same_decimals() {
+    check_lineitem_decimals_read_back(OutputFormat::Json).await;
+}
+
+#[tokio::test]
+async fn arrow_ipc_output_reads_back_the_same_decimals() {
+    check_lineitem_decimals_read_back(OutputFormat::ArrowIpc).await;
+}
@@ -0,0 +1,103 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Running every bundled query against a few generated rows of each table
+
+use std::fs;
+
+use datafusion::arrow::datatypes::DataType;
+use tempfile::TempDir;
+
+use tpctools::tpcds::TpcDs;
+use tpctools::tpch::TpcH;
+use tpctools::{convert_to_parquet, run_query, ConversionOptions, Tpc};
+
+/// Queries that the bundled version of DataFusion can't run, as listed in the README
+const TPCDS_UNSUPPORTED: &[usize] = &[6, 9, 10, 14, 23, 35, 41, 44, 45, 54, 58];
+
+/// Write `rows` rows of every table, numbered from 1 so that keys join, and convert them
+async fn convert_sample(benchmark: &dyn Tpc, dir: &TempDir, rows: usize) -> String {
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir_all(&input).unwrap();
+    for table in benchmark.get_table_names() {
+        let schema = benchmark.get_schema(table).unwrap();
+        let mut data = String::new();
+        for row in 1..=rows {
+            for field in schema.fields() {
+                let value = match field.data_type() {
+                    DataType::Int32 | DataType::Int64 => row.to_string(),
+                    DataType::Decimal128(_, _) => format!("{}.25", row),
+                    DataType::Date32 => format!("2000-0{}-01", row),
+                    _ => format!("{}{}", field.name(), row),
+                };
+                data.push_str(&value);
+                data.push('|');
+            }
+            data.push('\n');
+        }
+        let file = input.join(format!("{}.{}", table, benchmark.get_table_ext()));
+        fs::write(file, data).unwrap();
+    }
+    convert_to_parquet(
+        benchmark,
+        input.to_str().unwrap(),
+        output.to_str().unwrap(),
+        &ConversionOptions::default(),
+    )
+    .await
+    .unwrap();
+    output.to_str().unwrap().to_string()
+}
+
+/// Run every query of a benchmark, returning the numbers of those that fail
+async fn failing_queries(benchmark: &dyn Tpc) -> Vec<usize> {
+    let dir = TempDir::new().unwrap();
+    let output = convert_sample(benchmark, &dir, 3).await;
+    let mut failing = vec![];
+    for query in 1..=benchmark.queries().len() {
+        match run_query(benchmark, &output, query).await {
+            Ok(batches) => assert!(!batches.is_empty(), "query {}", query),
+            Err(_) => failing.push(query),
+        }
+    }
+    failing
+}
+
+/// Run every query of a benchmark on a thread with a larger stack than test threads have, which
+/// DataFusion needs to plan the larger queries
+fn failing_queries_on_large_stack(benchmark: fn() -> Box<dyn Tpc>) -> Vec<usize> {
+    std::thread::Builder::new()
+        .stack_size(64 * 1024 * 1024)
+        .spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(failing_queries(benchmark().as_ref()))
+        })
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+#[test]
+fn every_tpch_query_runs() {
+    let failing = failing_queries_on_large_stack(|| Box::new(TpcH::new()));
+    assert_eq!(failing, Vec::<usize>::new());
+}
+
+#[test]
+fn tpcds_queries_run_apart_from_the_unsupported_ones() {
+    let failing = failing_queries_on_large_stack(|| Box::new(TpcDs::new()));
+    assert_eq!(failing, TPCDS_UNSUPPORTED);
+}